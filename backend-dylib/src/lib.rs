@@ -2,40 +2,81 @@
 
 mod platform;
 
-use std::{net::SocketAddr, sync::Arc};
+use std::{
+    net::SocketAddr,
+    sync::{Arc, OnceLock},
+};
 
 use static_cell::make_static;
 
 use platform::handle_panic;
 
-use allocation_catcher_backend::{spawn_thread, AllocationCatcher, StorageAllocationHandler};
+use allocation_catcher_backend::{
+    spawn_thread, AllocationCatcher, Configuration, Options, StorageAllocationHandler,
+};
 use allocation_catcher_backend_server::{serve_tcp, SimpleServer};
 
-static mut ALLOCATION_CATCHER: Option<AllocationCatcher> = None;
+static ALLOCATION_CATCHER: OnceLock<AllocationCatcher> = OnceLock::new();
+
+// Number of back trace frames captured when recording from the start,
+// there is no chance to configure the process before it allocates.
+const RECORDING_BACKTRACE_FRAMES: u32 = 32;
 
 fn initialize() {
-    std::panic::set_hook(Box::new(|panic_info| handle_panic(panic_info)));
+    std::panic::set_hook(Box::new(handle_panic));
+
+    assert!(ALLOCATION_CATCHER.get().is_none());
+
+    // Short-lived processes are recorded from the start.
+    let record_path = std::env::var_os("ALLOCATION_CATCHER_RECORD");
+
+    // The layout of the blocks can not change once they are allocated.
+    let redzone = std::env::var("ALLOCATION_CATCHER_REDZONE")
+        .ok()
+        .and_then(|x| x.parse().ok())
+        .unwrap_or(0);
 
-    assert!(unsafe { ALLOCATION_CATCHER.is_none() });
+    let options = Options {
+        initial_configuration: record_path.as_ref().map(|_| Configuration {
+            backtrace_frames_count: RECORDING_BACKTRACE_FRAMES,
+            ..Default::default()
+        }),
+        redzone,
+        ..Default::default()
+    };
 
     let state = unsafe {
-        let allocation_catcher = AllocationCatcher::init(Default::default());
+        let allocation_catcher = AllocationCatcher::init(options);
         let state = allocation_catcher.state();
         let allocation_handler = make_static!(StorageAllocationHandler::new(state));
         allocation_catcher.set_allocation_handler(allocation_handler);
+
+        if let Some(path) = record_path {
+            state
+                .start_recording(path.as_ref())
+                .expect("failed to start recording");
+        }
+
         allocation_catcher.enable();
-        ALLOCATION_CATCHER = Some(allocation_catcher);
+        assert!(ALLOCATION_CATCHER.set(allocation_catcher).is_ok());
         state
     };
 
     spawn_thread(|| {
         serve_tcp(
-            &SocketAddr::from(([0, 0, 0, 0], 9940)),
+            SocketAddr::from(([0, 0, 0, 0], 9940)),
             Arc::new(SimpleServer::new(state)),
         )
     });
 }
 
 fn deinitialize() {
-    assert!(unsafe { ALLOCATION_CATCHER.is_some() });
+    assert!(ALLOCATION_CATCHER.get().is_some());
+
+    // Flush the trace before the process exits. On Windows the writer thread
+    // is already terminated by the time DllMain is notified.
+    #[cfg(unix)]
+    if let Some(allocation_catcher) = ALLOCATION_CATCHER.get() {
+        allocation_catcher.state().stop_recording();
+    }
 }
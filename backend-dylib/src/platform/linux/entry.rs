@@ -0,0 +1,18 @@
+// The shared object is loaded with LD_PRELOAD, so the loader runs these
+// the same way it calls DllMain for the injected DLL on Windows.
+
+extern "C" fn constructor() {
+    crate::initialize();
+}
+
+extern "C" fn destructor() {
+    crate::deinitialize();
+}
+
+#[used]
+#[link_section = ".init_array"]
+static INIT_ARRAY_ENTRY: extern "C" fn() = constructor;
+
+#[used]
+#[link_section = ".fini_array"]
+static FINI_ARRAY_ENTRY: extern "C" fn() = destructor;
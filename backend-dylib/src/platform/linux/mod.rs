@@ -0,0 +1,4 @@
+mod entry;
+mod panic;
+
+pub use panic::handle_panic;
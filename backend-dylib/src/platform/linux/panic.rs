@@ -0,0 +1,21 @@
+use std::{fs::OpenOptions, io::Write, panic::PanicHookInfo};
+
+// If set, panics are appended to this file instead of being written to stderr.
+const LOG_FILE_ENV: &str = "ALLOCATION_CATCHER_LOG";
+
+pub fn handle_panic(panic_info: &PanicHookInfo) {
+    let message = format!(
+        "[allocation-catcher] pid {}: {}\n",
+        std::process::id(),
+        panic_info
+    );
+
+    let logged = std::env::var_os(LOG_FILE_ENV)
+        .and_then(|path| OpenOptions::new().create(true).append(true).open(path).ok())
+        .and_then(|mut file| file.write_all(message.as_bytes()).ok())
+        .is_some();
+
+    if !logged {
+        std::io::stderr().write_all(message.as_bytes()).ok();
+    }
+}
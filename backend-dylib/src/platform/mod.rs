@@ -1,3 +1,9 @@
+#[cfg(target_os = "linux")]
+mod linux;
+#[cfg(windows)]
 mod windows;
 
-pub use windows::handle_panic;
\ No newline at end of file
+#[cfg(target_os = "linux")]
+pub use linux::handle_panic;
+#[cfg(windows)]
+pub use windows::handle_panic;
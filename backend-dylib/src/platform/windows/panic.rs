@@ -1,8 +1,8 @@
-use std::{ffi::CString, panic::PanicInfo};
+use std::{ffi::CString, panic::PanicHookInfo};
 
 use winapi::um::winuser::MessageBoxA;
 
-pub fn handle_panic(panic_info: &PanicInfo) {
+pub fn handle_panic(panic_info: &PanicHookInfo) {
     let res = CString::new(panic_info.to_string());
 
     let str = if let Ok(cstring) = res.as_ref() {
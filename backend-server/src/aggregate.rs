@@ -0,0 +1,251 @@
+use std::collections::HashMap;
+
+use allocation_catcher_backend::{
+    modules::{self, Module},
+    storage::{Address, Allocation},
+};
+use common::proto;
+
+use crate::query::Query;
+
+struct Group<'a> {
+    count: u64,
+    total_size: u64,
+    min_size: u64,
+    max_size: u64,
+    example: &'a Allocation,
+}
+
+impl<'a> Group<'a> {
+    fn new(allocation: &'a Allocation) -> Self {
+        let size = allocation.size as u64;
+
+        Self {
+            count: 1,
+            total_size: size,
+            min_size: size,
+            max_size: size,
+            example: allocation,
+        }
+    }
+
+    fn add(&mut self, allocation: &Allocation) {
+        let size = allocation.size as u64;
+
+        self.count += 1;
+        self.total_size += size;
+        self.min_size = self.min_size.min(size);
+        self.max_size = self.max_size.max(size);
+    }
+}
+
+// FNV-1a, unlike the std hasher stable across builds.
+fn stable_hash(bytes: impl Iterator<Item = u8>) -> u64 {
+    bytes.fold(0xCBF2_9CE4_8422_2325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100_0000_01B3)
+    })
+}
+
+// The module and the offset of the frames within `modules`, which stay the same across runs
+// wherever the modules are loaded.
+fn frame_key(instruction_pointer: Address, modules: &[Module]) -> u64 {
+    match modules::find_module(modules, instruction_pointer) {
+        Some(module) => {
+            let offset = (instruction_pointer - module.base) as u64;
+            stable_hash(module.name.bytes().chain(offset.to_le_bytes()))
+        }
+        None => instruction_pointer as u64,
+    }
+}
+
+/// Call site of the allocation made of the first `depth` frames or words, or all of them if 0.
+/// Allocations without the requested trace share the empty key. The frames within `modules`,
+/// which must be in address order, are keyed relative to their module.
+pub fn group_key(
+    allocation: &Allocation,
+    group_by: proto::GroupBy,
+    depth: u32,
+    modules: &[Module],
+) -> Vec<u64> {
+    let depth = match depth {
+        0 => usize::MAX,
+        depth => depth as usize,
+    };
+
+    match group_by {
+        proto::GroupBy::BackTrace => allocation
+            .back_trace
+            .as_ref()
+            .map(|x| {
+                x.frames
+                    .iter()
+                    .take(depth)
+                    .map(|frame| frame_key(frame.instruction_pointer, modules))
+                    .collect()
+            })
+            .unwrap_or_default(),
+        proto::GroupBy::StackTrace => allocation
+            .stack_trace
+            .as_ref()
+            .map(|x| {
+                x.trace
+                    .iter()
+                    .take(depth)
+                    .map(|&word| word as u64)
+                    .collect()
+            })
+            .unwrap_or_default(),
+    }
+}
+
+pub fn aggregate<'a>(
+    allocations: impl Iterator<Item = &'a Allocation>,
+    query: &Query,
+    req: &proto::AggregateRequest,
+) -> Option<Vec<proto::AllocationGroup>> {
+    let group_by = proto::GroupBy::try_from(req.group_by).ok()?;
+
+    let mut groups: HashMap<Vec<u64>, Group> = HashMap::new();
+
+    for allocation in allocations.filter(|x| query.matches(x)) {
+        groups
+            .entry(group_key(allocation, group_by, req.depth, &[]))
+            .and_modify(|group| group.add(allocation))
+            .or_insert_with(|| Group::new(allocation));
+    }
+
+    // Ties are broken by the count then the key, for the order not to depend on the hashing.
+    let mut groups: Vec<_> = groups.into_iter().collect();
+    groups.sort_by(|(key, group), (other_key, other)| {
+        (other.total_size, other.count)
+            .cmp(&(group.total_size, group.count))
+            .then_with(|| key.cmp(other_key))
+    });
+
+    Some(
+        groups
+            .into_iter()
+            .take(req.limit.map_or(usize::MAX, |x| x as usize))
+            .map(|(key, group)| proto::AllocationGroup {
+                key,
+                count: group.count,
+                total_size: group.total_size,
+                min_size: group.min_size,
+                max_size: group.max_size,
+                example: Some(group.example.into()),
+            })
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use allocation_catcher_backend::storage::{BackTrace, BackTraceFrame, StackTrace};
+
+    use super::*;
+
+    fn allocation(size: usize, trace: &[usize]) -> Allocation {
+        Allocation {
+            base_address: 0x1000,
+            size,
+            heap_handle: 0,
+            stack_trace: Some(StackTrace {
+                base: 0,
+                trace: trace.to_vec(),
+            }),
+            back_trace: Some(Arc::new(BackTrace {
+                id: 0,
+                frames: trace
+                    .iter()
+                    .map(|&x| BackTraceFrame {
+                        instruction_pointer: x,
+                        stack_pointer: 0,
+                        module_base: None,
+                        resolved_symbols: Vec::new(),
+                    })
+                    .collect(),
+            })),
+            sequence: 0,
+            timestamp: 0,
+            thread_id: 0,
+            redzone: 0,
+        }
+    }
+
+    fn groups(
+        allocations: &[Allocation],
+        group_by: proto::GroupBy,
+        depth: u32,
+        limit: Option<u64>,
+    ) -> Vec<(Vec<u64>, u64, u64, u64, u64)> {
+        let req = proto::AggregateRequest {
+            group_by: group_by as i32,
+            depth,
+            predicates: Vec::new(),
+            limit,
+        };
+        let query = Query::filter(&req.predicates).unwrap();
+
+        aggregate(allocations.iter(), &query, &req)
+            .unwrap()
+            .into_iter()
+            .map(|x| (x.key, x.count, x.total_size, x.min_size, x.max_size))
+            .collect()
+    }
+
+    #[test]
+    fn groups_by_call_site_largest_first() {
+        let allocations = [
+            allocation(0x10, &[1, 2]),
+            allocation(0x28, &[1, 3]),
+            allocation(0x20, &[1, 2]),
+            allocation(0x5, &[]),
+        ];
+
+        assert_eq!(
+            groups(&allocations, proto::GroupBy::BackTrace, 0, None),
+            [
+                (vec![1, 2], 2, 0x30, 0x10, 0x20),
+                (vec![1, 3], 1, 0x28, 0x28, 0x28),
+                (vec![], 1, 0x5, 0x5, 0x5),
+            ]
+        );
+        assert_eq!(
+            groups(&allocations, proto::GroupBy::StackTrace, 1, Some(1)),
+            [(vec![1], 3, 0x58, 0x10, 0x28)]
+        );
+    }
+
+    #[test]
+    fn ties_ordered_by_count_then_key() {
+        let allocations = [
+            allocation(0x20, &[3]),
+            allocation(0x20, &[2]),
+            allocation(0x10, &[1]),
+            allocation(0x10, &[1]),
+        ];
+
+        assert_eq!(
+            groups(&allocations, proto::GroupBy::BackTrace, 0, None),
+            [
+                (vec![1], 2, 0x20, 0x10, 0x10),
+                (vec![2], 1, 0x20, 0x20, 0x20),
+                (vec![3], 1, 0x20, 0x20, 0x20),
+            ]
+        );
+    }
+
+    #[test]
+    fn missing_traces_share_the_empty_key() {
+        let mut allocations = [allocation(0x10, &[1]), allocation(0x20, &[2])];
+        allocations[0].back_trace = None;
+        allocations[1].back_trace = None;
+
+        assert_eq!(
+            groups(&allocations, proto::GroupBy::BackTrace, 0, None),
+            [(vec![], 2, 0x30, 0x10, 0x20)]
+        );
+    }
+}
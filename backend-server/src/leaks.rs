@@ -0,0 +1,300 @@
+use std::ops::Range;
+
+use allocation_catcher_backend::{memory, modules, storage::Address, StateRef};
+use common::proto;
+
+use crate::{aggregate, query::Query};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mark {
+    Unreachable,
+    Reachable,
+    DefinitelyLost,
+    IndirectlyLost,
+    // Lost unless referenced from the threads which were not scanned, or moved during the scan.
+    PossiblyLost,
+}
+
+/// Live blocks in address order, with the conservative mark of each.
+struct Heap {
+    blocks: Vec<Range<usize>>,
+    marks: Vec<Mark>,
+}
+
+impl Heap {
+    fn new(mut blocks: Vec<Range<usize>>) -> Self {
+        blocks.sort_by_key(|x| x.start);
+
+        Self {
+            marks: vec![Mark::Unreachable; blocks.len()],
+            blocks,
+        }
+    }
+
+    // Interior pointers keep the block alive as well.
+    fn find(&self, address: usize) -> Option<usize> {
+        let index = self.blocks.partition_point(|x| x.start <= address);
+        index
+            .checked_sub(1)
+            .filter(|&x| self.blocks[x].contains(&address))
+    }
+
+    // Marks every block reachable from the range, which are `from` so far, as `to`.
+    fn mark(&mut self, range: Range<usize>, from: Mark, to: Mark) {
+        let mut pending = vec![range];
+
+        while let Some(range) = pending.pop() {
+            memory::scan_words(range, |_, value| {
+                if let Some(index) = self.find(value) {
+                    if self.marks[index] == from {
+                        self.marks[index] = to;
+                        pending.push(self.blocks[index].clone());
+                    }
+                }
+                true
+            });
+        }
+    }
+
+    // An unreachable block is definitely lost, the unreachable ones it points to indirectly.
+    fn lose(&mut self, index: usize) {
+        if self.marks[index] == Mark::Unreachable {
+            self.marks[index] = Mark::DefinitelyLost;
+            self.mark(
+                self.blocks[index].clone(),
+                Mark::Unreachable,
+                Mark::IndirectlyLost,
+            );
+        }
+    }
+
+    // Whether another unreachable block points into the block.
+    fn pointed_by_unreachable(&self) -> Vec<bool> {
+        let mut pointed = vec![false; self.blocks.len()];
+
+        for (index, block) in self.blocks.iter().enumerate() {
+            if self.marks[index] != Mark::Unreachable {
+                continue;
+            }
+
+            memory::scan_words(block.clone(), |_, value| {
+                if let Some(target) = self.find(value) {
+                    if target != index && self.marks[target] == Mark::Unreachable {
+                        pointed[target] = true;
+                    }
+                }
+                true
+            });
+        }
+
+        pointed
+    }
+
+    // Register values are roots as the scanned words are.
+    fn mark_values(&mut self, values: &[usize]) {
+        for &value in values {
+            if let Some(index) = self.find(value) {
+                if self.marks[index] == Mark::Unreachable {
+                    self.marks[index] = Mark::Reachable;
+                    self.mark(
+                        self.blocks[index].clone(),
+                        Mark::Unreachable,
+                        Mark::Reachable,
+                    );
+                }
+            }
+        }
+    }
+
+    // Classifies the blocks left unreachable by the marking. Without a complete and consistent
+    // scan of the roots, no block is known to be lost.
+    fn sweep(&mut self, complete: bool) {
+        // Lost blocks nothing else points to lose everything they point to indirectly.
+        let pointed = self.pointed_by_unreachable();
+        for (index, pointed) in pointed.into_iter().enumerate() {
+            if !pointed {
+                self.lose(index);
+            }
+        }
+
+        // The rest are cycles, one block of each is the definitely lost one.
+        for index in 0..self.blocks.len() {
+            self.lose(index);
+        }
+
+        if !complete {
+            for mark in self.marks.iter_mut() {
+                if matches!(*mark, Mark::DefinitelyLost | Mark::IndirectlyLost) {
+                    *mark = Mark::PossiblyLost;
+                }
+            }
+        }
+    }
+}
+
+/// Conservative mark and sweep over the live allocations.
+///
+/// The roots are the stacks of the other threads, the system call arguments known for them,
+/// and the writable data of the loaded modules. Memory not tracked by the catcher is not
+/// scanned, so blocks referenced only from it are reported as lost. While some threads are
+/// skipped, every unreachable block is reported as possibly lost instead.
+///
+/// The other threads keep running during the scan. A pointer they move from memory not
+/// scanned yet to memory already scanned is missed, and the block it points to looks
+/// unreachable. When the heap changes while scanning, which such threads usually do, the
+/// unreachable blocks are reported as possibly lost as well. Pointers moved without any
+/// allocation or free in the meantime can still give a false definitely lost block.
+pub fn leak_check(
+    state: StateRef,
+    req: &proto::LeakCheckRequest,
+) -> Option<proto::LeakCheckResponse> {
+    // Heap operations of the other threads, the scanning one is not tracked.
+    let changes = || {
+        let stats = state.lock_statistics();
+        stats.total_allocations + stats.total_reallocations + stats.total_deallocations
+    };
+    let changes_before = changes();
+
+    let mut heap = Heap::new(
+        state
+            .lock_storage()
+            .dump()
+            .map(|x| x.base_address..x.base_address + x.size)
+            .collect(),
+    );
+
+    let stacks = memory::thread_stacks();
+
+    for stack in stacks.stacks {
+        heap.mark_values(&stack.registers);
+        heap.mark(stack.range, Mark::Unreachable, Mark::Reachable);
+    }
+
+    for module in modules::loaded_modules() {
+        for range in module.writable {
+            heap.mark(range, Mark::Unreachable, Mark::Reachable);
+        }
+    }
+
+    heap.sweep(stacks.skipped.is_empty() && changes() == changes_before);
+
+    let reachable = heap.blocks.iter().zip(heap.marks.iter());
+    let reachable = reachable.filter(|(_, &mark)| mark == Mark::Reachable);
+
+    let aggregate_req = proto::AggregateRequest {
+        group_by: req.group_by,
+        depth: req.depth,
+        predicates: Vec::new(),
+        limit: req.limit,
+    };
+    let query = Query::filter(&aggregate_req.predicates)?;
+
+    // Blocks freed during the scan are no longer in the storage, so they are not reported.
+    let storage = state.lock_storage();
+    let lost = |kind: Mark| {
+        let allocations = heap
+            .blocks
+            .iter()
+            .zip(heap.marks.iter())
+            .filter(move |(_, &mark)| mark == kind)
+            .filter_map(|(block, _)| storage.find(block.start as Address));
+        aggregate::aggregate(allocations, &query, &aggregate_req)
+    };
+
+    Some(proto::LeakCheckResponse {
+        definitely_lost: lost(Mark::DefinitelyLost)?,
+        indirectly_lost: lost(Mark::IndirectlyLost)?,
+        possibly_lost: lost(Mark::PossiblyLost)?,
+        reachable_count: reachable.clone().count() as u64,
+        reachable_size: reachable.map(|(x, _)| x.len() as u64).sum(),
+        skipped_threads: stacks.skipped,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::mem;
+
+    use super::*;
+
+    // Blocks of words, the first one pointing to the next block if any.
+    fn chain(length: usize) -> Vec<Box<[usize]>> {
+        let mut blocks: Vec<Box<[usize]>> = (0..length).map(|_| vec![0; 2].into()).collect();
+        for index in 1..length {
+            blocks[index - 1][0] = blocks[index].as_ptr() as usize;
+        }
+        blocks
+    }
+
+    fn heap(blocks: &[&[Box<[usize]>]]) -> Heap {
+        Heap::new(
+            blocks
+                .iter()
+                .flat_map(|x| x.iter())
+                .map(|x| x.as_ptr() as usize..x.as_ptr() as usize + 2 * mem::size_of::<usize>())
+                .collect(),
+        )
+    }
+
+    fn marks(heap: &Heap, blocks: &[Box<[usize]>]) -> Vec<Mark> {
+        blocks
+            .iter()
+            .map(|x| heap.marks[heap.find(x.as_ptr() as usize).unwrap()])
+            .collect()
+    }
+
+    #[cfg(any(target_os = "linux", windows))]
+    #[test]
+    fn classifies_the_unreachable_blocks() {
+        let reachable = chain(2);
+        let lost = chain(3);
+        let mut cycle = chain(2);
+        cycle[1][0] = cycle[0].as_ptr() as usize;
+
+        let mut heap = heap(&[&reachable, &lost, &cycle]);
+        // Interior pointers keep the block alive.
+        let root = [reachable[0].as_ptr() as usize + 1];
+        heap.mark(
+            root.as_ptr() as usize..root.as_ptr() as usize + mem::size_of_val(&root),
+            Mark::Unreachable,
+            Mark::Reachable,
+        );
+        heap.sweep(true);
+
+        assert_eq!(marks(&heap, &reachable), [Mark::Reachable; 2]);
+        assert_eq!(
+            marks(&heap, &lost),
+            [
+                Mark::DefinitelyLost,
+                Mark::IndirectlyLost,
+                Mark::IndirectlyLost
+            ]
+        );
+        let cycle_marks = marks(&heap, &cycle);
+        assert!(cycle_marks.contains(&Mark::DefinitelyLost));
+        assert!(cycle_marks.contains(&Mark::IndirectlyLost));
+    }
+
+    #[cfg(any(target_os = "linux", windows))]
+    #[test]
+    fn registers_are_roots() {
+        let blocks = chain(2);
+
+        let mut heap = heap(&[&blocks]);
+        heap.mark_values(&[0, blocks[0].as_ptr() as usize]);
+        heap.sweep(true);
+
+        assert_eq!(marks(&heap, &blocks), [Mark::Reachable; 2]);
+    }
+
+    #[cfg(any(target_os = "linux", windows))]
+    #[test]
+    fn nothing_is_lost_for_sure_with_skipped_threads() {
+        let blocks = chain(2);
+
+        let mut heap = heap(&[&blocks]);
+        heap.sweep(false);
+
+        assert_eq!(marks(&heap, &blocks), [Mark::PossiblyLost; 2]);
+    }
+}
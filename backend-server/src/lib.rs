@@ -1,8 +1,15 @@
-use std::{io, iter};
+use std::{
+    collections::BTreeMap,
+    io,
+    path::Path,
+    sync::{Mutex, MutexGuard},
+};
 
 use allocation_catcher_backend::{
-    storage::{Address, Allocation},
-    wordsize, StateRef,
+    faults::FaultCondition,
+    memory, modules, redzone,
+    storage::{Address, Allocation, AllocationsStorage, FreedAllocation, FreedAllocationsStorage},
+    symbols, tracking_allocator_used, wordsize, Configuration, StateRef,
 };
 
 use bytes::{Bytes, BytesMut};
@@ -10,58 +17,408 @@ use common::{proto, PacketId};
 use num_enum::TryFromPrimitive;
 use prost::Message;
 
+mod aggregate;
+mod leaks;
+mod query;
+mod retainers;
 pub mod server;
+mod snapshot;
+mod stacks;
+mod subscription;
+
+use query::{Page, Query, SymbolMatcher};
+use snapshot::Snapshot;
+use stacks::StackTable;
+use subscription::Subscription;
 
-pub use server::{serve_stream, serve_tcp, RequestHandler};
+pub use server::{serve_stream, serve_tcp, RequestHandler, Response};
+
+// Largest region read by a single request.
+const MAX_READ_SIZE: u64 = 0x100000;
+// Number of found allocations converted with the storage locked at once.
+const FIND_BATCH_SIZE: usize = 0x1000;
 
 pub struct SimpleServer {
     state: StateRef,
+    snapshots: Mutex<BTreeMap<String, Snapshot>>,
 }
 
 impl RequestHandler for SimpleServer {
-    fn handle_request(&self, mut packet: Bytes) -> io::Result<Bytes> {
+    fn handle_request(&self, mut packet: Bytes) -> io::Result<Response> {
         let packet_id_num = packet[0];
         let packet_id = PacketId::try_from_primitive(packet_id_num)
             .map_err(|_| io::Error::from(io::ErrorKind::ConnectionReset))?;
-        self.request_inner(packet_id, packet.split_off(1))
-            .ok_or_else(|| io::Error::from(io::ErrorKind::ConnectionReset))
+        let data = packet.split_off(1);
+
+        match packet_id {
+            PacketId::Subscribe => self.subscribe(data).map(Response::Stream),
+            _ => self.request_inner(packet_id, data).map(Response::Packet),
+        }
+        .ok_or_else(|| io::Error::from(io::ErrorKind::ConnectionReset))
     }
 }
 
 impl SimpleServer {
     pub const fn new(state: StateRef) -> Self {
-        Self { state }
+        Self {
+            state,
+            snapshots: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    fn lock_snapshots(&self) -> MutexGuard<'_, BTreeMap<String, Snapshot>> {
+        self.snapshots
+            .lock()
+            .expect("unexpected snapshots lock poison")
+    }
+
+    // Modules and symbols are resolved only for the back traces being sent.
+    fn symbolize<'a>(&self, back_traces: impl Iterator<Item = &'a mut proto::BackTrace>) {
+        let count = self
+            .state
+            .get_configuration()
+            .backtrace_resolve_symbols_count as usize;
+        let modules = modules::loaded_modules();
+        let mut symbols = (count != 0).then(symbols::lock_symbols);
+
+        for back_trace in back_traces {
+            for frame in back_trace.frames.iter_mut() {
+                let address = frame.instruction_pointer as Address;
+                if let Some(module) = modules::find_module(&modules, address) {
+                    frame.module_base = Some(module.base as u64);
+                    frame.module = Some(module.name.clone());
+                }
+            }
+
+            if let Some(symbols) = symbols.as_mut() {
+                symbols.symbolize(back_trace, count);
+            }
+        }
+    }
+
+    fn find_allocations(
+        &self,
+        location: Option<&proto::filter::Location>,
+        query: &mut Query,
+    ) -> Vec<proto::Allocation> {
+        let instruction_pointers =
+            query.unresolved(locate_allocations(&**self.state.lock_storage(), location));
+        query.resolve(&instruction_pointers);
+
+        let selected = self.select_allocations(location, query);
+        self.convert_allocations(&selected, query.page())
+    }
+
+    // Only the matching allocations are selected with the storage locked, the response is
+    // built in batches for the hooks not to wait for all of it.
+    fn select_allocations(
+        &self,
+        location: Option<&proto::filter::Location>,
+        query: &Query,
+    ) -> Vec<(Address, u64)> {
+        query
+            .order(
+                locate_allocations(&**self.state.lock_storage(), location),
+                |x| x,
+            )
+            .into_iter()
+            .map(|x| (x.base_address, x.sequence))
+            .collect()
+    }
+
+    // Allocations freed since they were selected are left out before paginating, so that
+    // the page is still filled and lines up with the next one.
+    fn convert_allocations(
+        &self,
+        selected: &[(Address, u64)],
+        mut page: Page,
+    ) -> Vec<proto::Allocation> {
+        let mut allocations = Vec::new();
+
+        for batch in selected.chunks(FIND_BATCH_SIZE) {
+            if page.is_full() {
+                break;
+            }
+
+            let storage = self.state.lock_storage();
+            allocations.extend(
+                batch
+                    .iter()
+                    .filter_map(|&(base_address, sequence)| {
+                        storage
+                            .find(base_address)
+                            .filter(|x| x.sequence == sequence)
+                    })
+                    .filter(|_| page.admit())
+                    .map(proto::Allocation::from),
+            );
+        }
+
+        allocations
+    }
+
+    fn find_freed_allocations(
+        &self,
+        location: Option<&proto::filter::Location>,
+        query: &mut Query,
+    ) -> Vec<proto::FreedAllocation> {
+        let instruction_pointers = query.unresolved(
+            locate_freed_allocations(&self.state.lock_freed_storage(), location)
+                .map(|x| &x.allocation),
+        );
+        query.resolve(&instruction_pointers);
+
+        let selected = self.select_freed_allocations(location, query);
+        self.convert_freed_allocations(&selected, query.page())
+    }
+
+    fn select_freed_allocations(
+        &self,
+        location: Option<&proto::filter::Location>,
+        query: &Query,
+    ) -> Vec<(Address, u64)> {
+        query
+            .order(
+                locate_freed_allocations(&self.state.lock_freed_storage(), location),
+                |x| &x.allocation,
+            )
+            .into_iter()
+            .map(|x| (x.allocation.base_address, x.allocation.sequence))
+            .collect()
     }
 
-    fn handle_find(&self, req: proto::FindRequest) -> Vec<proto::FoundAllocation> {
-        let storage = self.state.lock_storage();
+    // As for the live allocations, the ones evicted meanwhile are left out.
+    fn convert_freed_allocations(
+        &self,
+        selected: &[(Address, u64)],
+        mut page: Page,
+    ) -> Vec<proto::FreedAllocation> {
+        let mut freed_allocations = Vec::new();
+
+        for batch in selected.chunks(FIND_BATCH_SIZE) {
+            if page.is_full() {
+                break;
+            }
+
+            let freed_storage = self.state.lock_freed_storage();
+            freed_allocations.extend(
+                batch
+                    .iter()
+                    .filter_map(|&(base_address, sequence)| {
+                        freed_storage
+                            .find(base_address)
+                            .find(|x| x.allocation.sequence == sequence)
+                    })
+                    .filter(|_| page.admit())
+                    .map(proto::FreedAllocation::from),
+            );
+        }
 
+        freed_allocations
+    }
+
+    fn handle_find(&self, req: proto::FindRequest) -> Option<proto::FindResponse> {
         let find_record = |record: &proto::FindRecord| {
-            let allocations = if let Some(filter) = record.filter.as_ref() {
-                let location = filter.location.as_ref().expect("location must be set");
-                match location {
-                    proto::filter::Location::Address(address) => {
-                        if let Some(allocation) = storage.find(*address as Address) {
-                            Box::new(iter::once(allocation))
-                        } else {
-                            Box::new(iter::empty()) as Box<dyn Iterator<Item = &Allocation>>
-                        }
-                    }
-                    proto::filter::Location::Range(range) => {
-                        storage.find_range(range.lower as Address, range.upper as Address)
-                    }
+            let location = record.filter.as_ref().and_then(|x| x.location.as_ref());
+            let freed = record.filter.as_ref().is_some_and(|x| x.freed);
+            let mut query = Query::new(record)?;
+
+            Some(if freed {
+                proto::FoundAllocation {
+                    id: record.id,
+                    allocations: Vec::new(),
+                    freed_allocations: self.find_freed_allocations(location, &mut query),
                 }
             } else {
-                storage.dump()
-            };
+                proto::FoundAllocation {
+                    id: record.id,
+                    allocations: self.find_allocations(location, &mut query),
+                    freed_allocations: Vec::new(),
+                }
+            })
+        };
 
-            proto::FoundAllocation {
-                id: record.id,
-                allocations: allocations.map(|x| x.into()).collect(),
+        let mut allocations: Vec<proto::FoundAllocation> =
+            req.records.iter().map(find_record).collect::<Option<_>>()?;
+
+        let mut stacks = StackTable::new();
+        if req.intern_stacks {
+            for found in allocations.iter_mut() {
+                for allocation in found.allocations.iter_mut() {
+                    stacks.intern(allocation);
+                }
+                for freed_allocation in found.freed_allocations.iter_mut() {
+                    stacks.intern_freed(freed_allocation);
+                }
             }
+        }
+
+        self.symbolize(
+            allocations
+                .iter_mut()
+                .flat_map(|x| {
+                    x.allocations
+                        .iter_mut()
+                        .flat_map(back_traces)
+                        .chain(x.freed_allocations.iter_mut().flat_map(freed_back_traces))
+                })
+                .chain(stacks.back_traces_mut()),
+        );
+
+        Some(proto::FindResponse {
+            allocations,
+            stacks: stacks.into(),
+        })
+    }
+
+    fn handle_find_page(&self, req: proto::FindPageRequest) -> Option<proto::FindPageResponse> {
+        let record = req.record?;
+        let filter = record.filter.clone().unwrap_or_default();
+
+        // Pages are always in address order, so there is nothing to sort or skip.
+        if filter.freed || record.sort.is_some() || record.offset != 0 || record.limit.is_some() {
+            return None;
+        }
+
+        let (lower, upper) = match filter.location {
+            Some(proto::filter::Location::Range(range)) => (range.lower, range.upper),
+            None => (0, u64::MAX),
+            _ => return None,
+        };
+
+        let mut query = Query::new(&record)?;
+        let page_size = req.page_size.max(1) as usize;
+        let (lower, upper) = (lower.max(req.cursor) as Address, upper as Address);
+
+        // The lock is held only to copy a bounded number of entries, whatever the predicates
+        // match. The symbols are resolved and the response is built without it.
+        let scanned: Vec<Allocation> = self
+            .state
+            .lock_storage()
+            .find_range(lower, upper)
+            .take(page_size)
+            .cloned()
+            .collect();
+
+        let instruction_pointers = query.unresolved(scanned.iter());
+        query.resolve(&instruction_pointers);
+
+        let next_cursor = match scanned.last() {
+            Some(last) if scanned.len() == page_size => (last.base_address as u64).checked_add(1),
+            _ => None,
+        };
+
+        let mut allocations: Vec<proto::Allocation> = scanned
+            .iter()
+            .filter(|x| query.matches(x))
+            .map(|x| x.into())
+            .collect();
+
+        let mut stacks = StackTable::new();
+        if req.intern_stacks {
+            allocations.iter_mut().for_each(|x| stacks.intern(x));
+        }
+
+        self.symbolize(
+            allocations
+                .iter_mut()
+                .flat_map(back_traces)
+                .chain(stacks.back_traces_mut()),
+        );
+
+        Some(proto::FindPageResponse {
+            allocations,
+            next_cursor,
+            stacks: stacks.into(),
+        })
+    }
+
+    fn handle_check_heap(&self, req: proto::CheckHeapRequest) -> proto::CheckHeapResponse {
+        let blocks: Vec<_> = self
+            .state
+            .lock_storage()
+            .dump()
+            .filter(|x| x.redzone != 0)
+            .map(|x| (x.base_address, x.size, x.redzone, x.sequence))
+            .collect();
+
+        // The blocks are read once the lock is released, the ones freed meanwhile are left out.
+        let limit = req.limit.map_or(usize::MAX, |x| x as usize);
+        let damaged: Vec<_> = blocks
+            .iter()
+            .filter_map(|&(base_address, size, redzone, sequence)| {
+                redzone::check(base_address, size, redzone)
+                    .map(|damage| (base_address, sequence, damage))
+            })
+            .take(limit)
+            .collect();
+
+        let timestamp = self.state.timestamp();
+        let mut errors: Vec<_> = {
+            let storage = self.state.lock_storage();
+
+            damaged
+                .iter()
+                .filter_map(|(base_address, sequence, damage)| {
+                    let allocation = storage
+                        .find(*base_address)
+                        .filter(|x| x.sequence == *sequence)?;
+
+                    Some(proto::HeapError {
+                        kind: proto::HeapErrorKind::Corruption as i32,
+                        address: *base_address as u64,
+                        heap_handle: allocation.heap_handle as u64,
+                        thread_id: allocation.thread_id,
+                        timestamp,
+                        allocation: Some(allocation.into()),
+                        damage: Some(damage.into()),
+                        ..Default::default()
+                    })
+                })
+                .collect()
+        };
+
+        // The quarantined blocks are not released while the lock is held.
+        let quarantined = {
+            let quarantine = self.state.lock_quarantine();
+
+            errors.extend(
+                quarantine
+                    .iter()
+                    .filter_map(|block| {
+                        let damage = block.check()?;
+                        let allocation = &block.freed.allocation;
+
+                        Some(proto::HeapError {
+                            kind: proto::HeapErrorKind::WriteAfterFree as i32,
+                            address: allocation.base_address as u64,
+                            heap_handle: allocation.heap_handle as u64,
+                            thread_id: allocation.thread_id,
+                            timestamp,
+                            previous_free: Some((&block.freed).into()),
+                            damage: Some((&damage).into()),
+                            ..Default::default()
+                        })
+                    })
+                    .take(limit.saturating_sub(errors.len())),
+            );
+
+            quarantine.len()
         };
 
-        req.records.iter().map(find_record).collect()
+        self.symbolize(errors.iter_mut().flat_map(error_back_traces));
+
+        proto::CheckHeapResponse {
+            checked: blocks.len() as u64,
+            errors,
+            quarantined: quarantined as u64,
+        }
+    }
+
+    fn subscribe(&self, data: Bytes) -> Option<Box<dyn Iterator<Item = Bytes> + Send>> {
+        let req = proto::SubscribeRequest::decode(data).ok()?;
+
+        Some(Box::new(Subscription::new(self.state, req)))
     }
 
     fn request_inner(&self, packet_id: PacketId, data: Bytes) -> Option<Bytes> {
@@ -82,9 +439,16 @@ impl SimpleServer {
             PacketId::SetConfiguration => {
                 let req = proto::SetConfigurationRequest::decode(data).ok()?;
 
-                self.state.set_configuration(req.configuration?.into());
+                let configuration: Configuration = req.configuration?.into();
+                // The RtlFreeHeap detour is installed on every Windows process.
+                let warnings = configuration_warnings(
+                    &configuration,
+                    tracking_allocator_used(),
+                    cfg!(windows),
+                );
+                self.state.set_configuration(configuration);
 
-                proto::SetConfigurationResponse {}
+                proto::SetConfigurationResponse { warnings }
                     .encode(&mut response)
                     .ok()?;
             }
@@ -101,18 +465,208 @@ impl SimpleServer {
                 let _req = proto::ClearStorageRequest::decode(data).ok()?;
 
                 self.state.lock_storage().clear();
+                self.state.lock_freed_storage().clear();
 
                 proto::ClearStorageResponse {}.encode(&mut response).ok()?;
             }
             PacketId::Find => {
                 let req = proto::FindRequest::decode(data).ok()?;
 
-                proto::FindResponse {
-                    allocations: self.handle_find(req),
+                self.handle_find(req)?.encode(&mut response).ok()?;
+            }
+            PacketId::FindPage => {
+                let req = proto::FindPageRequest::decode(data).ok()?;
+
+                self.handle_find_page(req)?.encode(&mut response).ok()?;
+            }
+            PacketId::Aggregate => {
+                let req = proto::AggregateRequest::decode(data).ok()?;
+
+                let mut query = Query::filter(&req.predicates)?;
+                let instruction_pointers = query.unresolved(self.state.lock_storage().dump());
+                query.resolve(&instruction_pointers);
+
+                let mut groups =
+                    aggregate::aggregate(self.state.lock_storage().dump(), &query, &req)?;
+                self.symbolize(
+                    groups
+                        .iter_mut()
+                        .flat_map(|x| x.example.iter_mut().flat_map(back_traces)),
+                );
+
+                proto::AggregateResponse { groups }
+                    .encode(&mut response)
+                    .ok()?;
+            }
+            PacketId::TakeSnapshot => {
+                let req = proto::TakeSnapshotRequest::decode(data).ok()?;
+
+                let modules = modules::loaded_modules();
+                let snapshot =
+                    Snapshot::new(self.state.run(), modules, self.state.lock_storage().dump());
+                let count = snapshot.count() as u64;
+                self.lock_snapshots().insert(req.name, snapshot);
+
+                proto::TakeSnapshotResponse { count }
+                    .encode(&mut response)
+                    .ok()?;
+            }
+            PacketId::GetSnapshot => {
+                let req = proto::GetSnapshotRequest::decode(data).ok()?;
+
+                let mut snapshot: proto::Snapshot = self.lock_snapshots().get(&req.name)?.into();
+                self.symbolize(snapshot.allocations.iter_mut().flat_map(back_traces));
+
+                proto::GetSnapshotResponse {
+                    snapshot: Some(snapshot),
+                }
+                .encode(&mut response)
+                .ok()?;
+            }
+            PacketId::PutSnapshot => {
+                let req = proto::PutSnapshotRequest::decode(data).ok()?;
+
+                let snapshot = Snapshot::from(&req.snapshot?);
+                self.lock_snapshots().insert(req.name, snapshot);
+
+                proto::PutSnapshotResponse {}.encode(&mut response).ok()?;
+            }
+            PacketId::DeleteSnapshot => {
+                let req = proto::DeleteSnapshotRequest::decode(data).ok()?;
+
+                self.lock_snapshots().remove(&req.name);
+
+                proto::DeleteSnapshotResponse {}
+                    .encode(&mut response)
+                    .ok()?;
+            }
+            PacketId::Diff => {
+                let req = proto::DiffRequest::decode(data).ok()?;
+
+                let mut groups = {
+                    let snapshots = self.lock_snapshots();
+                    snapshot::diff(snapshots.get(&req.from)?, snapshots.get(&req.to)?, &req)?
+                };
+                self.symbolize(
+                    groups
+                        .iter_mut()
+                        .flat_map(|x| x.example.iter_mut().flat_map(back_traces)),
+                );
+
+                proto::DiffResponse { groups }.encode(&mut response).ok()?;
+            }
+            PacketId::Subscribe => return None,
+            PacketId::StartRecording => {
+                let req = proto::StartRecordingRequest::decode(data).ok()?;
+
+                self.state.start_recording(Path::new(&req.path)).ok()?;
+
+                proto::StartRecordingResponse {}
+                    .encode(&mut response)
+                    .ok()?;
+            }
+            PacketId::StopRecording => {
+                let _req = proto::StopRecordingRequest::decode(data).ok()?;
+
+                let summary = self.state.stop_recording()?.ok()?;
+
+                proto::StopRecordingResponse {
+                    events: summary.events,
+                    dropped: summary.dropped,
+                }
+                .encode(&mut response)
+                .ok()?;
+            }
+            PacketId::GetModules => {
+                let _req = proto::GetModulesRequest::decode(data).ok()?;
+
+                proto::GetModulesResponse {
+                    modules: modules::loaded_modules().iter().map(|x| x.into()).collect(),
+                }
+                .encode(&mut response)
+                .ok()?;
+            }
+            PacketId::ReadMemory => {
+                let req = proto::ReadMemoryRequest::decode(data).ok()?;
+
+                let mut allocation: Option<proto::Allocation> = self
+                    .state
+                    .lock_storage()
+                    .find_containing(req.address as Address)
+                    .map(|x| x.into());
+
+                let (address, size) = match req.size {
+                    Some(size) => (req.address, size),
+                    None => {
+                        let allocation = allocation.as_ref()?;
+                        (allocation.base_address, allocation.size)
+                    }
+                };
+
+                self.symbolize(allocation.iter_mut().flat_map(back_traces));
+
+                // The block may be freed once the lock is released, reading it is still safe.
+                proto::ReadMemoryResponse {
+                    address,
+                    data: memory::read_memory(address as usize, size.min(MAX_READ_SIZE) as usize),
+                    allocation,
                 }
                 .encode(&mut response)
                 .ok()?;
             }
+            PacketId::FindRetainers => {
+                let req = proto::FindRetainersRequest::decode(data).ok()?;
+
+                let mut resp = retainers::find_retainers(self.state, &req)?;
+                self.symbolize(
+                    resp.target
+                        .iter_mut()
+                        .chain(resp.retainers.iter_mut().flat_map(|x| x.owner.as_mut()))
+                        .flat_map(back_traces),
+                );
+
+                resp.encode(&mut response).ok()?;
+            }
+            PacketId::LeakCheck => {
+                let req = proto::LeakCheckRequest::decode(data).ok()?;
+
+                let mut resp = leaks::leak_check(self.state, &req)?;
+                self.symbolize(
+                    resp.definitely_lost
+                        .iter_mut()
+                        .chain(resp.indirectly_lost.iter_mut())
+                        .flat_map(|x| x.example.as_mut())
+                        .flat_map(back_traces),
+                );
+
+                resp.encode(&mut response).ok()?;
+            }
+            PacketId::GetErrors => {
+                let req = proto::GetErrorsRequest::decode(data).ok()?;
+
+                let mut errors = Vec::new();
+                let (next_cursor, dropped) = self.state.lock_errors().read(
+                    req.cursor,
+                    req.limit.map_or(usize::MAX, |x| x as usize),
+                    &mut errors,
+                );
+
+                let mut errors: Vec<proto::HeapError> = errors.iter().map(|x| x.into()).collect();
+                self.symbolize(errors.iter_mut().flat_map(error_back_traces));
+
+                proto::GetErrorsResponse {
+                    errors,
+                    next_cursor,
+                    dropped,
+                }
+                .encode(&mut response)
+                .ok()?;
+            }
+            PacketId::CheckHeap => {
+                let req = proto::CheckHeapRequest::decode(data).ok()?;
+
+                self.handle_check_heap(req).encode(&mut response).ok()?;
+            }
             PacketId::GetStatistics => {
                 let _req = proto::GetStatisticsRequest::decode(data).ok()?;
 
@@ -128,11 +682,30 @@ impl SimpleServer {
                             .total_deallocations_non_allocated
                             as u64,
                         allocated: allocated as u64,
+                        timestamp: self.state.timestamp(),
+                        stacks: self.state.lock_stacks().count() as u64,
+                        total_injected_failures: statistics.total_injected_failures as u64,
                     }),
                 }
                 .encode(&mut response)
                 .ok()?;
             }
+            PacketId::SetFaults => {
+                let req = proto::SetFaultsRequest::decode(data).ok()?;
+
+                let conditions = req
+                    .rules
+                    .iter()
+                    .map(fault_condition)
+                    .collect::<Option<Vec<_>>>()?;
+                let warnings =
+                    fault_warnings(&req.rules, &conditions, &self.state.get_configuration());
+                self.state.set_faults(conditions, req.seed);
+
+                proto::SetFaultsResponse { warnings }
+                    .encode(&mut response)
+                    .ok()?;
+            }
             PacketId::ResetStatistics => {
                 let _req = proto::ResetStatisticsRequest::decode(data).ok()?;
 
@@ -147,3 +720,324 @@ impl SimpleServer {
         Some(response.freeze())
     }
 }
+
+fn back_traces(allocation: &mut proto::Allocation) -> Option<&mut proto::BackTrace> {
+    allocation.back_trace.as_mut()
+}
+
+fn freed_back_traces(
+    freed_allocation: &mut proto::FreedAllocation,
+) -> impl Iterator<Item = &mut proto::BackTrace> {
+    freed_allocation
+        .allocation
+        .as_mut()
+        .and_then(back_traces)
+        .into_iter()
+        .chain(freed_allocation.free_back_trace.as_mut())
+}
+
+fn fault_condition(rule: &proto::FaultRule) -> Option<FaultCondition> {
+    Some(match rule.condition.as_ref()? {
+        proto::fault_rule::Condition::Every(x) => FaultCondition::Every(*x),
+        proto::fault_rule::Condition::Probability(x) => FaultCondition::Probability(*x),
+        proto::fault_rule::Condition::LargerThan(x) => FaultCondition::LargerThan(*x as usize),
+        proto::fault_rule::Condition::Symbol(x) => {
+            let matcher = SymbolMatcher::try_from(x).ok()?;
+            FaultCondition::Symbol(symbols::find_functions(|name| matcher.matches(name)))
+        }
+        proto::fault_rule::Condition::Budget(x) => FaultCondition::Budget(*x as usize),
+    })
+}
+
+// The settings taking effect only on some of the allocators are set anyway.
+fn configuration_warnings(
+    configuration: &Configuration,
+    tracking_allocator: bool,
+    rtl_heap: bool,
+) -> Vec<String> {
+    let mut warnings = Vec::new();
+
+    if configuration.quarantine_size != 0 && tracking_allocator {
+        warnings.push(
+            "the blocks of TrackingAllocator are released right away, without quarantine"
+                .to_owned(),
+        );
+    }
+
+    if configuration.quarantine_size != 0 && rtl_heap {
+        warnings.push(
+            "the blocks freed by RtlFreeHeap are released right away, without quarantine"
+                .to_owned(),
+        );
+    }
+
+    warnings
+}
+
+// The rules which can never fail an allocation are set anyway, like any other rule.
+fn fault_warnings(
+    rules: &[proto::FaultRule],
+    conditions: &[FaultCondition],
+    configuration: &Configuration,
+) -> Vec<String> {
+    let mut warnings = Vec::new();
+
+    for (rule, condition) in rules.iter().zip(conditions) {
+        let FaultCondition::Symbol(ranges) = condition else {
+            continue;
+        };
+
+        if configuration.backtrace_frames_count == 0 {
+            warnings.push("symbol rules never match while back traces are disabled".to_owned());
+        } else if ranges.is_empty() {
+            if let Some(proto::fault_rule::Condition::Symbol(x)) = rule.condition.as_ref() {
+                warnings.push(format!("no loaded function matches {:?}", x.pattern));
+            }
+        }
+    }
+
+    warnings.dedup();
+    warnings
+}
+
+fn error_back_traces(error: &mut proto::HeapError) -> impl Iterator<Item = &mut proto::BackTrace> {
+    error
+        .back_trace
+        .as_mut()
+        .into_iter()
+        .chain(error.allocation.as_mut().and_then(back_traces))
+        .chain(error.previous_free.iter_mut().flat_map(freed_back_traces))
+}
+
+fn locate_allocations<'a>(
+    storage: &'a dyn AllocationsStorage,
+    location: Option<&proto::filter::Location>,
+) -> Box<dyn Iterator<Item = &'a Allocation> + 'a> {
+    match location {
+        Some(proto::filter::Location::Address(address)) => {
+            Box::new(storage.find(*address as Address).into_iter())
+        }
+        Some(proto::filter::Location::Range(range)) => {
+            storage.find_range(range.lower as Address, range.upper as Address)
+        }
+        Some(proto::filter::Location::Containing(address)) => {
+            Box::new(storage.find_containing(*address as Address).into_iter())
+        }
+        None => storage.dump(),
+    }
+}
+
+fn locate_freed_allocations<'a>(
+    freed_storage: &'a FreedAllocationsStorage,
+    location: Option<&proto::filter::Location>,
+) -> Box<dyn Iterator<Item = &'a FreedAllocation> + 'a> {
+    match location {
+        Some(proto::filter::Location::Address(address)) => {
+            Box::new(freed_storage.find(*address as Address))
+        }
+        Some(proto::filter::Location::Range(range)) => {
+            Box::new(freed_storage.find_range(range.lower as Address, range.upper as Address))
+        }
+        Some(proto::filter::Location::Containing(address)) => {
+            Box::new(freed_storage.find_containing(*address as Address))
+        }
+        None => Box::new(freed_storage.dump()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use allocation_catcher_backend::{BtreeMapStorage, State};
+
+    use super::*;
+
+    fn server(addresses: &[Address]) -> SimpleServer {
+        let state = State::new(Configuration::default(), Box::new(BtreeMapStorage::new()));
+        let server = SimpleServer::new(Box::leak(Box::new(state)));
+        for &address in addresses {
+            store(&server, address);
+        }
+        server
+    }
+
+    fn store(server: &SimpleServer, base_address: Address) {
+        server.state.lock_storage().store(Allocation {
+            base_address,
+            size: 0x10,
+            heap_handle: 0,
+            stack_trace: None,
+            back_trace: None,
+            sequence: 0,
+            timestamp: 0,
+            thread_id: 0,
+            redzone: 0,
+        });
+    }
+
+    fn find_page(
+        server: &SimpleServer,
+        cursor: u64,
+        predicates: Vec<proto::Predicate>,
+    ) -> (Vec<u64>, Option<u64>) {
+        let resp = server
+            .handle_find_page(proto::FindPageRequest {
+                record: Some(proto::FindRecord {
+                    predicates,
+                    ..Default::default()
+                }),
+                cursor,
+                page_size: 2,
+                intern_stacks: false,
+            })
+            .unwrap();
+
+        let addresses = resp.allocations.iter().map(|x| x.base_address).collect();
+        (addresses, resp.next_cursor)
+    }
+
+    #[test]
+    fn find_page_cursor_survives_changes() {
+        let server = server(&[0x1000, 0x2000, 0x3000, 0x4000]);
+
+        let (page, cursor) = find_page(&server, 0, Vec::new());
+        assert_eq!(page, [0x1000, 0x2000]);
+        assert_eq!(cursor, Some(0x2001));
+
+        // Neither skipped nor repeated by the next pages, whatever changes before the cursor.
+        server.state.lock_storage().remove(0x1000).unwrap();
+        store(&server, 0x1800);
+        store(&server, 0x2800);
+
+        let (page, cursor) = find_page(&server, cursor.unwrap(), Vec::new());
+        assert_eq!(page, [0x2800, 0x3000]);
+
+        let (page, cursor) = find_page(&server, cursor.unwrap(), Vec::new());
+        assert_eq!(page, [0x4000]);
+        assert_eq!(cursor, None);
+    }
+
+    #[test]
+    fn find_page_cursor_advances_past_unmatched_allocations() {
+        let server = server(&[0x1000, 0x2000, 0x3000]);
+        let none = vec![proto::Predicate {
+            predicate: Some(proto::predicate::Predicate::HeapHandle(1)),
+        }];
+
+        let (page, cursor) = find_page(&server, 0, none.clone());
+        assert_eq!(page, []);
+        assert_eq!(cursor, Some(0x2001));
+
+        let (page, cursor) = find_page(&server, cursor.unwrap(), none);
+        assert_eq!(page, []);
+        assert_eq!(cursor, None);
+    }
+
+    #[test]
+    fn find_page_rejects_unordered_records() {
+        let server = server(&[0x1000]);
+        let request = |record| proto::FindPageRequest {
+            record: Some(record),
+            cursor: 0,
+            page_size: 2,
+            intern_stacks: false,
+        };
+
+        let sorted = proto::FindRecord {
+            sort: Some(proto::Sort {
+                key: proto::SortKey::Size as i32,
+                descending: false,
+            }),
+            ..Default::default()
+        };
+        assert!(server.handle_find_page(request(sorted)).is_none());
+
+        let limited = proto::FindRecord {
+            limit: Some(1),
+            ..Default::default()
+        };
+        assert!(server.handle_find_page(request(limited)).is_none());
+    }
+
+    // Allocations freed between the selection and the conversion don't shorten the page.
+    #[test]
+    fn find_fills_the_page_past_freed_allocations() {
+        let server = server(&[0x1000, 0x2000, 0x3000, 0x4000, 0x5000]);
+        let query = Query::new(&proto::FindRecord {
+            offset: 1,
+            limit: Some(2),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let selected = server.select_allocations(None, &query);
+        server.state.lock_storage().remove(0x1000).unwrap();
+        server.state.lock_storage().remove(0x3000).unwrap();
+
+        let found: Vec<_> = server
+            .convert_allocations(&selected, query.page())
+            .iter()
+            .map(|x| x.base_address)
+            .collect();
+        assert_eq!(found, [0x4000, 0x5000]);
+    }
+
+    fn symbol_rule(pattern: &str) -> proto::FaultRule {
+        proto::FaultRule {
+            condition: Some(proto::fault_rule::Condition::Symbol(proto::SymbolPattern {
+                pattern: pattern.to_owned(),
+                regex: false,
+            })),
+        }
+    }
+
+    #[test]
+    fn symbol_fault_warnings() {
+        let rules = [symbol_rule("foo"), symbol_rule("bar")];
+        let conditions = [
+            FaultCondition::Symbol(vec![0x1000..0x1100, 0x2000..0x2010]),
+            FaultCondition::Symbol(Vec::new()),
+        ];
+
+        let disabled = Configuration::default();
+        assert_eq!(
+            fault_warnings(&rules, &conditions, &disabled),
+            ["symbol rules never match while back traces are disabled"]
+        );
+
+        let enabled = Configuration {
+            backtrace_frames_count: 8,
+            ..Default::default()
+        };
+        assert_eq!(
+            fault_warnings(&rules, &conditions, &enabled),
+            ["no loaded function matches \"bar\""]
+        );
+    }
+
+    #[test]
+    fn quarantine_warning_without_quarantine_support() {
+        let quarantine = Configuration {
+            quarantine_size: 0x1000,
+            ..Default::default()
+        };
+
+        assert_eq!(configuration_warnings(&quarantine, true, false).len(), 1);
+        assert_eq!(configuration_warnings(&quarantine, false, true).len(), 1);
+        assert_eq!(configuration_warnings(&quarantine, true, true).len(), 2);
+        assert!(configuration_warnings(&quarantine, false, false).is_empty());
+        assert!(configuration_warnings(&Configuration::default(), true, true).is_empty());
+    }
+
+    #[test]
+    fn symbol_fault_rule_resolves_functions() {
+        let condition = fault_condition(&symbol_rule("symbol_fault_rule_resolves_functions"));
+        let Some(FaultCondition::Symbol(ranges)) = condition else {
+            panic!("not a symbol rule");
+        };
+
+        #[cfg(target_os = "linux")]
+        assert!(ranges
+            .iter()
+            .any(|x| x.contains(&(symbol_fault_rule_resolves_functions as *const () as usize))));
+    }
+}
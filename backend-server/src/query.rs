@@ -0,0 +1,483 @@
+use std::{collections::HashSet, ops::Range};
+
+use allocation_catcher_backend::{
+    storage::{Address, Allocation, BackTraceSymbol},
+    symbols,
+};
+use common::proto;
+use regex::Regex;
+
+pub enum SymbolMatcher {
+    Substring(String),
+    Regex(Regex),
+}
+
+impl SymbolMatcher {
+    pub fn matches(&self, name: &str) -> bool {
+        match self {
+            SymbolMatcher::Substring(substring) => name.contains(substring.as_str()),
+            SymbolMatcher::Regex(regex) => regex.is_match(name),
+        }
+    }
+
+    fn matches_any(&self, symbols: &[BackTraceSymbol]) -> bool {
+        symbols
+            .iter()
+            .filter_map(|x| x.name.as_deref())
+            .any(|name| self.matches(name))
+    }
+}
+
+impl TryFrom<&proto::SymbolPattern> for SymbolMatcher {
+    type Error = ();
+
+    fn try_from(value: &proto::SymbolPattern) -> Result<Self, Self::Error> {
+        Ok(if value.regex {
+            SymbolMatcher::Regex(Regex::new(&value.pattern).map_err(|_| ())?)
+        } else {
+            SymbolMatcher::Substring(value.pattern.clone())
+        })
+    }
+}
+
+enum Predicate {
+    Size(Range<u64>),
+    HeapHandle(u64),
+    ThreadId(u64),
+    Sequence(Range<u64>),
+    Timestamp(Range<u64>),
+    Symbol {
+        matcher: SymbolMatcher,
+        // Instruction pointers resolved to a matching symbol by `Query::resolve`.
+        matching: HashSet<Address>,
+    },
+}
+
+impl Predicate {
+    fn matches(&self, allocation: &Allocation) -> bool {
+        match self {
+            Predicate::Size(range) => range.contains(&(allocation.size as u64)),
+            Predicate::HeapHandle(heap_handle) => allocation.heap_handle as u64 == *heap_handle,
+            Predicate::ThreadId(thread_id) => allocation.thread_id == *thread_id,
+            Predicate::Sequence(range) => range.contains(&allocation.sequence),
+            Predicate::Timestamp(range) => range.contains(&allocation.timestamp),
+            Predicate::Symbol { matcher, matching } => {
+                allocation.back_trace.as_ref().is_some_and(|x| {
+                    x.frames.iter().any(|frame| {
+                        // Frames of the snapshots put by the clients may already be resolved.
+                        if frame.resolved_symbols.is_empty() {
+                            matching.contains(&frame.instruction_pointer)
+                        } else {
+                            matcher.matches_any(&frame.resolved_symbols)
+                        }
+                    })
+                })
+            }
+        }
+    }
+
+    fn is_symbol(&self) -> bool {
+        matches!(self, Predicate::Symbol { .. })
+    }
+}
+
+impl TryFrom<&proto::Predicate> for Predicate {
+    type Error = ();
+
+    fn try_from(value: &proto::Predicate) -> Result<Self, Self::Error> {
+        let range = |x: &proto::Range| x.lower..x.upper;
+
+        Ok(match value.predicate.as_ref().ok_or(())? {
+            proto::predicate::Predicate::Size(x) => Predicate::Size(range(x)),
+            proto::predicate::Predicate::HeapHandle(x) => Predicate::HeapHandle(*x),
+            proto::predicate::Predicate::ThreadId(x) => Predicate::ThreadId(*x),
+            proto::predicate::Predicate::Sequence(x) => Predicate::Sequence(range(x)),
+            proto::predicate::Predicate::Timestamp(x) => Predicate::Timestamp(range(x)),
+            proto::predicate::Predicate::Symbol(x) => Predicate::Symbol {
+                matcher: x.try_into()?,
+                matching: HashSet::new(),
+            },
+        })
+    }
+}
+
+/// Predicates, order and pagination of a single `FindRecord`.
+pub struct Query {
+    predicates: Vec<Predicate>,
+    sort_key: proto::SortKey,
+    descending: bool,
+    offset: usize,
+    limit: usize,
+}
+
+impl Query {
+    // Fails if any of the predicates is malformed.
+    pub fn new(record: &proto::FindRecord) -> Option<Self> {
+        let sort = record.sort.clone().unwrap_or_default();
+
+        Some(Self {
+            sort_key: proto::SortKey::try_from(sort.key).ok()?,
+            descending: sort.descending,
+            offset: record.offset as usize,
+            limit: record.limit.map_or(usize::MAX, |x| x as usize),
+            ..Self::filter(&record.predicates)?
+        })
+    }
+
+    // Keeps the order and the number of the matching items.
+    pub fn filter(predicates: &[proto::Predicate]) -> Option<Self> {
+        Some(Self {
+            predicates: predicates
+                .iter()
+                .map(Predicate::try_from)
+                .collect::<Result<Vec<_>, _>>()
+                .ok()?,
+            sort_key: proto::SortKey::Default,
+            descending: false,
+            offset: 0,
+            limit: usize::MAX,
+        })
+    }
+
+    pub fn matches(&self, allocation: &Allocation) -> bool {
+        self.predicates.iter().all(|x| x.matches(allocation))
+    }
+
+    /// Instruction pointers to resolve for the symbol predicates to match the allocations.
+    pub fn unresolved<'a>(
+        &self,
+        allocations: impl Iterator<Item = &'a Allocation>,
+    ) -> HashSet<Address> {
+        if !self.predicates.iter().any(Predicate::is_symbol) {
+            return HashSet::new();
+        }
+
+        allocations
+            .filter(|x| {
+                self.predicates
+                    .iter()
+                    .filter(|predicate| !predicate.is_symbol())
+                    .all(|predicate| predicate.matches(x))
+            })
+            .filter_map(|x| x.back_trace.as_deref())
+            .flat_map(|x| x.frames.iter())
+            .filter(|x| x.resolved_symbols.is_empty())
+            .map(|x| x.instruction_pointer)
+            .collect()
+    }
+
+    /// Resolves the instruction pointers for the symbol predicates.
+    ///
+    /// Resolving is slow, so the storage must not be locked meanwhile: the allocations are
+    /// matched with the storage locked again afterwards.
+    pub fn resolve(&mut self, instruction_pointers: &HashSet<Address>) {
+        if instruction_pointers.is_empty() {
+            return;
+        }
+
+        let mut symbols = symbols::lock_symbols();
+
+        for &instruction_pointer in instruction_pointers {
+            let resolved = symbols.resolve(instruction_pointer);
+
+            for predicate in self.predicates.iter_mut() {
+                if let Predicate::Symbol { matcher, matching } = predicate {
+                    if matcher.matches_any(resolved) {
+                        matching.insert(instruction_pointer);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Filters and sorts the items, the pagination is left to `page`. The default order is
+    /// the order of `items`.
+    pub fn order<'a, T>(
+        &self,
+        items: impl Iterator<Item = &'a T>,
+        allocation: impl Fn(&T) -> &Allocation,
+    ) -> Vec<&'a T> {
+        let mut items: Vec<_> = items.filter(|x| self.matches(allocation(x))).collect();
+
+        match self.sort_key {
+            proto::SortKey::Default => {}
+            proto::SortKey::Address => items.sort_by_key(|x| allocation(x).base_address),
+            proto::SortKey::Size => items.sort_by_key(|x| allocation(x).size),
+            proto::SortKey::Sequence => items.sort_by_key(|x| allocation(x).sequence),
+            proto::SortKey::Timestamp => items.sort_by_key(|x| allocation(x).timestamp),
+        }
+
+        if self.descending {
+            items.reverse();
+        }
+
+        items
+    }
+
+    pub fn page(&self) -> Page {
+        Page {
+            offset: self.offset,
+            limit: self.limit,
+        }
+    }
+}
+
+/// Pagination of a query applied one item at a time, for the items ordered by `Query::order`
+/// that may be gone by the time they are paginated.
+pub struct Page {
+    offset: usize,
+    limit: usize,
+}
+
+impl Page {
+    // Whether the next item is on the page.
+    pub fn admit(&mut self) -> bool {
+        if self.offset != 0 {
+            self.offset -= 1;
+            false
+        } else if self.limit != 0 {
+            self.limit -= 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.limit == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use allocation_catcher_backend::storage::{BackTrace, BackTraceFrame};
+
+    use super::*;
+
+    fn allocation(base_address: Address, size: usize, sequence: u64) -> Allocation {
+        Allocation {
+            base_address,
+            size,
+            heap_handle: base_address & 1,
+            stack_trace: None,
+            back_trace: None,
+            sequence,
+            timestamp: sequence * 10,
+            thread_id: sequence % 2,
+            redzone: 0,
+        }
+    }
+
+    fn allocations() -> Vec<Allocation> {
+        vec![
+            allocation(0x1000, 0x30, 3),
+            allocation(0x2000, 0x10, 1),
+            allocation(0x3001, 0x20, 2),
+            allocation(0x4000, 0x40, 4),
+        ]
+    }
+
+    fn range(lower: u64, upper: u64) -> proto::Range {
+        proto::Range { lower, upper }
+    }
+
+    fn predicate(predicate: proto::predicate::Predicate) -> proto::Predicate {
+        proto::Predicate {
+            predicate: Some(predicate),
+        }
+    }
+
+    fn record(predicates: Vec<proto::Predicate>) -> proto::FindRecord {
+        proto::FindRecord {
+            predicates,
+            ..Default::default()
+        }
+    }
+
+    fn select(record: &proto::FindRecord, allocations: &[Allocation]) -> Vec<Address> {
+        let query = Query::new(record).unwrap();
+        let mut page = query.page();
+        query
+            .order(allocations.iter(), |x| x)
+            .into_iter()
+            .filter(|_| page.admit())
+            .map(|x| x.base_address)
+            .collect()
+    }
+
+    #[test]
+    fn predicates_must_all_match() {
+        use proto::predicate::Predicate;
+
+        let allocations = allocations();
+        let found = |predicates| select(&record(predicates), &allocations);
+
+        assert_eq!(
+            found(vec![predicate(Predicate::Size(range(0x10, 0x30)))]),
+            [0x2000, 0x3001]
+        );
+        assert_eq!(found(vec![predicate(Predicate::HeapHandle(1))]), [0x3001]);
+        assert_eq!(
+            found(vec![predicate(Predicate::ThreadId(1))]),
+            [0x1000, 0x2000]
+        );
+        assert_eq!(
+            found(vec![predicate(Predicate::Sequence(range(2, 4)))]),
+            [0x1000, 0x3001]
+        );
+        assert_eq!(
+            found(vec![predicate(Predicate::Timestamp(range(0, 25)))]),
+            [0x2000, 0x3001]
+        );
+        assert_eq!(
+            found(vec![
+                predicate(Predicate::Size(range(0x20, 0x50))),
+                predicate(Predicate::ThreadId(0)),
+            ]),
+            [0x3001, 0x4000]
+        );
+    }
+
+    #[test]
+    fn malformed_predicates_are_rejected() {
+        let empty = proto::Predicate { predicate: None };
+        assert!(Query::new(&record(vec![empty])).is_none());
+
+        let regex = predicate(proto::predicate::Predicate::Symbol(proto::SymbolPattern {
+            pattern: "(".to_owned(),
+            regex: true,
+        }));
+        assert!(Query::new(&record(vec![regex])).is_none());
+    }
+
+    #[test]
+    fn symbol_predicate_matches_resolved_frames() {
+        let symbol = |name: &str| BackTraceSymbol {
+            name: Some(name.to_owned()),
+            address: None,
+            filename: None,
+            lineno: None,
+            colno: None,
+        };
+        let back_trace = |names: &[&str]| {
+            Arc::new(BackTrace {
+                id: 0,
+                frames: vec![BackTraceFrame {
+                    instruction_pointer: 0x1234,
+                    stack_pointer: 0,
+                    module_base: None,
+                    resolved_symbols: names.iter().map(|x| symbol(x)).collect(),
+                }],
+            })
+        };
+
+        let mut allocations = allocations();
+        allocations[0].back_trace = Some(back_trace(&["app::parse", "app::main"]));
+        allocations[1].back_trace = Some(back_trace(&["app::render"]));
+
+        let pattern = |pattern: &str, regex| {
+            record(vec![predicate(proto::predicate::Predicate::Symbol(
+                proto::SymbolPattern {
+                    pattern: pattern.to_owned(),
+                    regex,
+                },
+            ))])
+        };
+
+        assert_eq!(select(&pattern("main", false), &allocations), [0x1000]);
+        assert_eq!(
+            select(&pattern("^app::(parse|render)$", true), &allocations),
+            [0x1000, 0x2000]
+        );
+        assert_eq!(select(&pattern("^main", true), &allocations), []);
+    }
+
+    #[test]
+    fn sort_and_pagination() {
+        let allocations = allocations();
+        let sorted = |key: proto::SortKey, descending, offset, limit| {
+            let record = proto::FindRecord {
+                sort: Some(proto::Sort {
+                    key: key as i32,
+                    descending,
+                }),
+                offset,
+                limit,
+                ..Default::default()
+            };
+            select(&record, &allocations)
+        };
+
+        assert_eq!(
+            sorted(proto::SortKey::Default, false, 0, None),
+            [0x1000, 0x2000, 0x3001, 0x4000]
+        );
+        assert_eq!(
+            sorted(proto::SortKey::Size, false, 0, None),
+            [0x2000, 0x3001, 0x1000, 0x4000]
+        );
+        assert_eq!(
+            sorted(proto::SortKey::Sequence, true, 0, None),
+            [0x4000, 0x1000, 0x3001, 0x2000]
+        );
+        assert_eq!(
+            sorted(proto::SortKey::Timestamp, false, 1, Some(2)),
+            [0x3001, 0x1000]
+        );
+        assert_eq!(sorted(proto::SortKey::Default, true, 3, Some(2)), [0x1000]);
+        assert_eq!(sorted(proto::SortKey::Address, false, 4, None), []);
+    }
+
+    #[inline(never)]
+    fn resolve_target() -> usize {
+        std::hint::black_box(0x5678)
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn symbol_predicate_resolves_unresolved_frames() {
+        assert_eq!(resolve_target(), 0x5678);
+        // Resolved as a return address, which points after the call.
+        let instruction_pointer = resolve_target as *const () as usize + 1;
+
+        let back_trace = |instruction_pointer| {
+            Some(Arc::new(BackTrace {
+                id: 0,
+                frames: vec![BackTraceFrame {
+                    instruction_pointer,
+                    stack_pointer: 0,
+                    module_base: None,
+                    resolved_symbols: Vec::new(),
+                }],
+            }))
+        };
+
+        let mut allocations = allocations();
+        allocations[0].back_trace = back_trace(instruction_pointer);
+        allocations[3].back_trace = back_trace(instruction_pointer + 0x10);
+
+        let mut query = Query::new(&record(vec![
+            predicate(proto::predicate::Predicate::Symbol(proto::SymbolPattern {
+                pattern: "resolve_target".to_owned(),
+                regex: false,
+            })),
+            predicate(proto::predicate::Predicate::Size(range(0x30, 0x40))),
+        ]))
+        .unwrap();
+
+        // Only the frames of the allocations matching the other predicates are resolved.
+        let unresolved = query.unresolved(allocations.iter());
+        assert_eq!(unresolved, HashSet::from([instruction_pointer]));
+        assert!(query.order(allocations.iter(), |x| x).is_empty());
+
+        query.resolve(&unresolved);
+        let found: Vec<_> = query
+            .order(allocations.iter(), |x| x)
+            .into_iter()
+            .map(|x| x.base_address)
+            .collect();
+        assert_eq!(found, [0x1000]);
+    }
+}
@@ -0,0 +1,218 @@
+use std::ops::Range;
+
+use allocation_catcher_backend::{memory, modules, storage::Address, StateRef};
+use common::proto;
+
+struct Region {
+    kind: proto::RetainerKind,
+    range: Range<usize>,
+    thread_id: u64,
+    module: String,
+}
+
+impl Region {
+    fn heap(range: Range<usize>) -> Self {
+        Self {
+            kind: proto::RetainerKind::Heap,
+            range,
+            thread_id: 0,
+            module: String::new(),
+        }
+    }
+}
+
+/// Every word pointing inside the allocation containing the address, in the live allocations
+/// and optionally in the stacks of the other threads and in the writable data of the modules.
+///
+/// The allocations are read after the storage lock is released, so the blocks freed meanwhile
+/// may still be reported.
+pub fn find_retainers(
+    state: StateRef,
+    req: &proto::FindRetainersRequest,
+) -> Option<proto::FindRetainersResponse> {
+    let (target, mut regions) = {
+        let storage = state.lock_storage();
+        let target = storage.find_containing(req.address as Address)?;
+
+        let regions: Vec<_> = storage
+            .dump()
+            .map(|x| Region::heap(x.base_address..x.base_address + x.size))
+            .collect();
+
+        (proto::Allocation::from(target), regions)
+    };
+
+    let mut skipped_threads = Vec::new();
+    if req.scan_stacks {
+        let stacks = memory::thread_stacks();
+        skipped_threads = stacks.skipped;
+
+        regions.extend(stacks.stacks.into_iter().map(|x| Region {
+            kind: proto::RetainerKind::Stack,
+            range: x.range,
+            thread_id: x.thread_id,
+            module: String::new(),
+        }));
+    }
+
+    if req.scan_modules {
+        for module in modules::loaded_modules() {
+            regions.extend(module.writable.iter().map(|x| Region {
+                kind: proto::RetainerKind::Module,
+                range: x.clone(),
+                thread_id: 0,
+                module: module.name.clone(),
+            }));
+        }
+    }
+
+    let target_range = target.base_address as usize..(target.base_address + target.size) as usize;
+    let limit = req.limit.map_or(usize::MAX, |x| x as usize);
+    let mut retainers = Vec::new();
+
+    for region in regions.iter() {
+        memory::scan_words(region.range.clone(), |address, value| {
+            if !target_range.contains(&value) {
+                return true;
+            }
+
+            retainers.push(proto::Retainer {
+                kind: region.kind as i32,
+                address: address as u64,
+                offset: (address - region.range.start) as u64,
+                target_offset: (value - target_range.start) as u64,
+                owner: None,
+                thread_id: region.thread_id,
+                module: region.module.clone(),
+            });
+            retainers.len() < limit
+        });
+
+        if retainers.len() >= limit {
+            break;
+        }
+    }
+
+    // Owners are looked up again, the ones freed during the scan are left out.
+    let storage = state.lock_storage();
+    for retainer in retainers.iter_mut() {
+        if retainer.kind() == proto::RetainerKind::Heap {
+            let base = retainer.address - retainer.offset;
+            retainer.owner = storage.find(base as Address).map(|x| x.into());
+        }
+    }
+
+    Some(proto::FindRetainersResponse {
+        target: Some(target),
+        retainers,
+        skipped_threads,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use allocation_catcher_backend::{storage::Allocation, BtreeMapStorage, Configuration, State};
+
+    use super::*;
+
+    fn state(blocks: &[&[usize]]) -> StateRef {
+        let state = State::new(Configuration::default(), Box::new(BtreeMapStorage::new()));
+        let state: StateRef = Box::leak(Box::new(state));
+
+        let mut storage = state.lock_storage();
+        for block in blocks {
+            storage.store(Allocation {
+                base_address: block.as_ptr() as Address,
+                size: std::mem::size_of_val(*block),
+                heap_handle: 0,
+                stack_trace: None,
+                back_trace: None,
+                sequence: 0,
+                timestamp: 0,
+                thread_id: 0,
+                redzone: 0,
+            });
+        }
+        drop(storage);
+
+        state
+    }
+
+    fn request(
+        address: usize,
+        scan_modules: bool,
+        limit: Option<u32>,
+    ) -> proto::FindRetainersRequest {
+        proto::FindRetainersRequest {
+            address: address as u64,
+            scan_stacks: false,
+            scan_modules,
+            limit,
+        }
+    }
+
+    #[cfg(any(target_os = "linux", windows))]
+    #[test]
+    fn heap_retainers_with_their_owner() {
+        let target = vec![0usize; 4];
+        let target_address = target.as_ptr() as usize;
+        let owner = vec![0, target_address + 8, 0, target_address];
+        let unrelated = vec![0usize; 2];
+        let state = state(&[&target, &owner, &unrelated]);
+
+        let resp = find_retainers(state, &request(target_address + 20, false, None)).unwrap();
+        assert_eq!(resp.target.unwrap().base_address, target_address as u64);
+
+        let mut retainers: Vec<_> = resp
+            .retainers
+            .iter()
+            .map(|x| {
+                let owner = x.owner.as_ref().map(|x| x.base_address);
+                (x.kind(), x.offset, x.target_offset, owner)
+            })
+            .collect();
+        retainers.sort_by_key(|x| x.1);
+
+        let owner = Some(owner.as_ptr() as u64);
+        assert_eq!(
+            retainers,
+            [
+                (proto::RetainerKind::Heap, 8, 8, owner),
+                (proto::RetainerKind::Heap, 24, 0, owner),
+            ]
+        );
+
+        let limited = find_retainers(state, &request(target_address, false, Some(1))).unwrap();
+        assert_eq!(limited.retainers.len(), 1);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn module_retainers() {
+        static RETAINER: AtomicUsize = AtomicUsize::new(0);
+
+        let target = vec![0usize; 2];
+        let target_address = target.as_ptr() as usize;
+        let state = state(&[&target]);
+        RETAINER.store(target_address, Ordering::Relaxed);
+
+        let resp = find_retainers(state, &request(target_address, true, None)).unwrap();
+        let retainer = resp
+            .retainers
+            .iter()
+            .find(|x| x.address == &RETAINER as *const _ as u64)
+            .expect("the static must retain the target");
+        assert_eq!(retainer.kind(), proto::RetainerKind::Module);
+        assert!(!retainer.module.is_empty());
+
+        RETAINER.store(0, Ordering::Relaxed);
+    }
+
+    #[test]
+    fn no_target_outside_of_the_allocations() {
+        let state = state(&[]);
+        assert!(find_retainers(state, &request(0x1000, false, None)).is_none());
+    }
+}
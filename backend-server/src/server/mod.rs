@@ -8,6 +8,13 @@ pub use transport::{
     serve_stream, serve_stream_client, serve_stream_client_once, serve_tcp, TransportListener,
 };
 
+pub enum Response {
+    Packet(Bytes),
+    // Every item is sent as a separate packet, the connection is served
+    // as usual once the stream ends.
+    Stream(Box<dyn Iterator<Item = Bytes> + Send>),
+}
+
 pub trait RequestHandler: Send + Sync {
-    fn handle_request(&self, packet: Bytes) -> io::Result<Bytes>;
+    fn handle_request(&self, packet: Bytes) -> io::Result<Response>;
 }
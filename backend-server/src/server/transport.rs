@@ -7,7 +7,7 @@ use std::{
 use allocation_catcher_backend::spawn_thread;
 use bytes::BytesMut;
 
-use crate::server::RequestHandler;
+use crate::server::{RequestHandler, Response};
 
 pub fn serve_stream_client<S: Read + Write>(
     mut stream: S,
@@ -32,12 +32,23 @@ pub fn serve_stream_client_once<S: Read + Write>(
     stream.read_exact(&mut packet)?;
     assert!(packet.len() == packet_length);
 
-    let response = request_handler.handle_request(packet.freeze())?;
-    stream.write(&(response.len() as u32).to_be_bytes())?;
-    stream.write(response.as_ref())?;
+    match request_handler.handle_request(packet.freeze())? {
+        Response::Packet(response) => write_packet(stream, &response)?,
+        Response::Stream(responses) => {
+            for response in responses {
+                write_packet(stream, &response)?;
+            }
+        }
+    }
+
     Ok(())
 }
 
+fn write_packet<S: Write>(stream: &mut S, packet: &[u8]) -> io::Result<()> {
+    stream.write_all(&(packet.len() as u32).to_be_bytes())?;
+    stream.write_all(packet)
+}
+
 pub trait TransportListener {
     type Stream: Read + Write + Sync + Send + 'static;
 
@@ -48,7 +59,7 @@ impl TransportListener for TcpListener {
     type Stream = TcpStream;
 
     fn accept(&self) -> io::Result<Self::Stream> {
-        let (stream, _sockadddr) = TcpListener::accept(&self)?;
+        let (stream, _sockadddr) = TcpListener::accept(self)?;
         Ok(stream)
     }
 }
@@ -0,0 +1,374 @@
+use std::collections::{BTreeMap, HashMap};
+
+use allocation_catcher_backend::{
+    modules::Module,
+    storage::{Address, Allocation},
+};
+use common::proto;
+
+use crate::aggregate::group_key;
+
+/// Copy of the live allocations. Keyed by the sequence number as well as the base address,
+/// so that a block freed and allocated again at the same address is not mistaken for the same one.
+pub struct Snapshot {
+    run: u64,
+    // In address order.
+    modules: Vec<Module>,
+    allocations: BTreeMap<(Address, u64), Allocation>,
+}
+
+impl Snapshot {
+    pub fn new<'a>(
+        run: u64,
+        modules: Vec<Module>,
+        allocations: impl Iterator<Item = &'a Allocation>,
+    ) -> Self {
+        Self {
+            run,
+            modules,
+            allocations: allocations
+                .map(|x| ((x.base_address, x.sequence), x.clone()))
+                .collect(),
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.allocations.len()
+    }
+}
+
+impl From<&proto::Snapshot> for Snapshot {
+    fn from(value: &proto::Snapshot) -> Self {
+        let mut modules: Vec<Module> = value.modules.iter().map(Module::from).collect();
+        modules.sort_by_key(|x| x.base);
+
+        Self {
+            run: value.run,
+            modules,
+            allocations: value
+                .allocations
+                .iter()
+                .map(|x| {
+                    let allocation = Allocation::from(x);
+                    ((allocation.base_address, allocation.sequence), allocation)
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<&Snapshot> for proto::Snapshot {
+    fn from(value: &Snapshot) -> Self {
+        Self {
+            allocations: value.allocations.values().map(|x| x.into()).collect(),
+            modules: value.modules.iter().map(|x| x.into()).collect(),
+            run: value.run,
+        }
+    }
+}
+
+#[derive(Default)]
+struct DiffGroup<'a> {
+    added_count: u64,
+    added_size: u64,
+    removed_count: u64,
+    removed_size: u64,
+    resized_count: u64,
+    resized_delta: i64,
+    example: Option<&'a Allocation>,
+}
+
+impl<'a> DiffGroup<'a> {
+    fn growth(&self) -> i64 {
+        self.added_size as i64 - self.removed_size as i64 + self.resized_delta
+    }
+
+    // Allocations added, removed or resized.
+    fn count(&self) -> u64 {
+        self.added_count + self.removed_count + self.resized_count
+    }
+}
+
+fn group<'m, 'a>(
+    groups: &'m mut HashMap<Vec<u64>, DiffGroup<'a>>,
+    snapshot: &Snapshot,
+    allocation: &'a Allocation,
+    group_by: proto::GroupBy,
+    depth: u32,
+) -> &'m mut DiffGroup<'a> {
+    let group = groups
+        .entry(group_key(allocation, group_by, depth, &snapshot.modules))
+        .or_default();
+    group.example.get_or_insert(allocation);
+    group
+}
+
+pub fn diff(
+    from: &Snapshot,
+    to: &Snapshot,
+    req: &proto::DiffRequest,
+) -> Option<Vec<proto::DiffGroup>> {
+    let group_by = proto::GroupBy::try_from(req.group_by).ok()?;
+
+    let mut groups = HashMap::new();
+
+    if from.run == to.run {
+        for (key, allocation) in to.allocations.iter() {
+            match from.allocations.get(key) {
+                None => {
+                    let group = group(&mut groups, to, allocation, group_by, req.depth);
+                    group.added_count += 1;
+                    group.added_size += allocation.size as u64;
+                }
+                Some(previous) if previous.size != allocation.size => {
+                    let group = group(&mut groups, to, allocation, group_by, req.depth);
+                    group.resized_count += 1;
+                    group.resized_delta += allocation.size as i64 - previous.size as i64;
+                }
+                Some(_) => {}
+            }
+        }
+
+        for (key, allocation) in from.allocations.iter() {
+            if !to.allocations.contains_key(key) {
+                let group = group(&mut groups, from, allocation, group_by, req.depth);
+                group.removed_count += 1;
+                group.removed_size += allocation.size as u64;
+            }
+        }
+    } else {
+        // Addresses and sequence numbers of different runs are unrelated, only the call sites
+        // are compared.
+        for allocation in to.allocations.values() {
+            let group = group(&mut groups, to, allocation, group_by, req.depth);
+            group.added_count += 1;
+            group.added_size += allocation.size as u64;
+        }
+
+        for allocation in from.allocations.values() {
+            let group = group(&mut groups, from, allocation, group_by, req.depth);
+            group.removed_count += 1;
+            group.removed_size += allocation.size as u64;
+        }
+
+        groups.retain(|_, x| x.added_count != x.removed_count || x.added_size != x.removed_size);
+    }
+
+    // Ties are broken by the count then the key, for the order not to depend on the hashing.
+    let mut groups: Vec<_> = groups.into_iter().collect();
+    groups.sort_by(|(key, group), (other_key, other)| {
+        (other.growth(), other.count())
+            .cmp(&(group.growth(), group.count()))
+            .then_with(|| key.cmp(other_key))
+    });
+
+    Some(
+        groups
+            .into_iter()
+            .take(req.limit.map_or(usize::MAX, |x| x as usize))
+            .map(|(key, group)| proto::DiffGroup {
+                key,
+                added_count: group.added_count,
+                added_size: group.added_size,
+                removed_count: group.removed_count,
+                removed_size: group.removed_size,
+                resized_count: group.resized_count,
+                resized_delta: group.resized_delta,
+                example: group.example.map(|x| x.into()),
+            })
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use allocation_catcher_backend::storage::{BackTrace, BackTraceFrame, StackTrace};
+
+    use super::*;
+
+    fn allocation(base_address: Address, size: usize, sequence: u64, site: usize) -> Allocation {
+        Allocation {
+            base_address,
+            size,
+            heap_handle: 0,
+            stack_trace: Some(StackTrace {
+                base: 0,
+                trace: vec![site],
+            }),
+            back_trace: None,
+            sequence,
+            timestamp: 0,
+            thread_id: 0,
+            redzone: 0,
+        }
+    }
+
+    const RUN: u64 = 1;
+
+    fn diff_snapshots(
+        from: &Snapshot,
+        to: &Snapshot,
+        group_by: proto::GroupBy,
+    ) -> Vec<(Vec<u64>, [i64; 6])> {
+        let req = proto::DiffRequest {
+            group_by: group_by as i32,
+            ..Default::default()
+        };
+
+        diff(from, to, &req)
+            .unwrap()
+            .into_iter()
+            .map(|x| {
+                let counts = [
+                    x.added_count as i64,
+                    x.added_size as i64,
+                    x.removed_count as i64,
+                    x.removed_size as i64,
+                    x.resized_count as i64,
+                    x.resized_delta,
+                ];
+                (x.key, counts)
+            })
+            .collect()
+    }
+
+    fn diff_groups(from: &[Allocation], to: &[Allocation]) -> Vec<(Vec<u64>, [i64; 6])> {
+        diff_snapshots(
+            &Snapshot::new(RUN, Vec::new(), from.iter()),
+            &Snapshot::new(RUN, Vec::new(), to.iter()),
+            proto::GroupBy::StackTrace,
+        )
+    }
+
+    #[test]
+    fn diff_by_call_site_largest_growth_first() {
+        let from = [
+            allocation(0x1000, 0x10, 1, 1),
+            allocation(0x2000, 0x20, 2, 2),
+            allocation(0x3000, 0x30, 3, 3),
+        ];
+        let to = [
+            allocation(0x1000, 0x10, 1, 1),
+            allocation(0x2000, 0x28, 2, 2),
+            allocation(0x4000, 0x100, 4, 1),
+        ];
+
+        assert_eq!(
+            diff_groups(&from, &to),
+            [
+                (vec![1], [1, 0x100, 0, 0, 0, 0]),
+                (vec![2], [0, 0, 0, 0, 1, 8]),
+                (vec![3], [0, 0, 1, 0x30, 0, 0]),
+            ]
+        );
+    }
+
+    #[test]
+    fn same_growth_ordered_by_count_then_key() {
+        let from = [allocation(0x1000, 0x10, 1, 2)];
+        let to = [
+            allocation(0x2000, 0x10, 2, 3),
+            allocation(0x3000, 0x20, 3, 2),
+            allocation(0x4000, 0x10, 4, 1),
+        ];
+
+        assert_eq!(
+            diff_groups(&from, &to),
+            [
+                (vec![2], [1, 0x20, 1, 0x10, 0, 0]),
+                (vec![1], [1, 0x10, 0, 0, 0, 0]),
+                (vec![3], [1, 0x10, 0, 0, 0, 0]),
+            ]
+        );
+    }
+
+    #[test]
+    fn reused_address_is_a_new_allocation() {
+        let from = [allocation(0x1000, 0x10, 1, 1)];
+        let to = [allocation(0x1000, 0x10, 2, 1)];
+
+        assert_eq!(
+            diff_groups(&from, &to),
+            [(vec![1], [1, 0x10, 1, 0x10, 0, 0])]
+        );
+    }
+
+    #[test]
+    fn snapshot_round_trip() {
+        let allocations = [
+            allocation(0x1000, 0x10, 1, 1),
+            allocation(0x1000, 0x20, 2, 2),
+        ];
+        let snapshot = Snapshot::new(RUN, Vec::new(), allocations.iter());
+        let copy = Snapshot::from(&proto::Snapshot::from(&snapshot));
+        let req = proto::DiffRequest::default();
+
+        assert_eq!(copy.count(), 2);
+        assert!(diff(&snapshot, &copy, &req).unwrap().is_empty());
+    }
+
+    fn module(base: Address) -> Module {
+        Module {
+            name: "app".to_owned(),
+            path: "/bin/app".to_owned(),
+            base,
+            size: 0x10000,
+            build_id: Vec::new(),
+            debug_file: None,
+            writable: Vec::new(),
+        }
+    }
+
+    fn traced(base_address: Address, size: usize, sequence: u64, ip: Address) -> Allocation {
+        Allocation {
+            back_trace: Some(Arc::new(BackTrace {
+                id: 0,
+                frames: vec![BackTraceFrame {
+                    instruction_pointer: ip,
+                    stack_pointer: 0,
+                    module_base: None,
+                    resolved_symbols: Vec::new(),
+                }],
+            })),
+            ..allocation(base_address, size, sequence, 0)
+        }
+    }
+
+    // The module is loaded elsewhere and the sequence numbers restart in the second run.
+    #[test]
+    fn runs_compared_per_call_site_relative_to_the_module() {
+        let from = [
+            traced(0x1000, 0x10, 1, 0x10_0100),
+            traced(0x2000, 0x20, 2, 0x10_0200),
+        ];
+        let to = [
+            traced(0x1000, 0x20, 1, 0x50_0200),
+            traced(0x2000, 0x10, 2, 0x50_0100),
+            traced(0x3000, 0x10, 3, 0x50_0100),
+        ];
+        let from = Snapshot::new(1, vec![module(0x10_0000)], from.iter());
+        let to = Snapshot::new(2, vec![module(0x50_0000)], to.iter());
+
+        let key = |snapshot: &Snapshot, base_address| {
+            let allocation = snapshot
+                .allocations
+                .values()
+                .find(|x| x.base_address == base_address);
+            group_key(
+                allocation.unwrap(),
+                proto::GroupBy::BackTrace,
+                0,
+                &snapshot.modules,
+            )
+        };
+        assert_eq!(key(&from, 0x1000), key(&to, 0x2000));
+
+        // The unchanged call site is left out, even though its blocks don't line up.
+        assert_eq!(
+            diff_snapshots(&from, &to, proto::GroupBy::BackTrace),
+            [(key(&to, 0x2000), [2, 0x20, 1, 0x10, 0, 0])]
+        );
+    }
+}
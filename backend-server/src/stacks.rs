@@ -0,0 +1,127 @@
+use std::collections::BTreeMap;
+
+use common::proto;
+
+/// Back traces moved out of the response allocations, each sent once.
+pub struct StackTable {
+    stacks: BTreeMap<u64, proto::BackTrace>,
+}
+
+impl StackTable {
+    pub fn new() -> Self {
+        Self {
+            stacks: BTreeMap::new(),
+        }
+    }
+
+    fn take(&mut self, stack_id: u64, back_trace: &mut Option<proto::BackTrace>) {
+        // Back traces without an id are left inline.
+        if stack_id == 0 {
+            return;
+        }
+
+        if let Some(back_trace) = back_trace.take() {
+            self.stacks.entry(stack_id).or_insert(back_trace);
+        }
+    }
+
+    pub fn intern(&mut self, allocation: &mut proto::Allocation) {
+        self.take(allocation.stack_id, &mut allocation.back_trace);
+    }
+
+    pub fn back_traces_mut(&mut self) -> impl Iterator<Item = &mut proto::BackTrace> {
+        self.stacks.values_mut()
+    }
+
+    pub fn intern_freed(&mut self, freed_allocation: &mut proto::FreedAllocation) {
+        if let Some(allocation) = freed_allocation.allocation.as_mut() {
+            self.intern(allocation);
+        }
+
+        self.take(
+            freed_allocation.free_stack_id,
+            &mut freed_allocation.free_back_trace,
+        );
+    }
+}
+
+impl From<StackTable> for Vec<proto::Stack> {
+    fn from(value: StackTable) -> Self {
+        value
+            .stacks
+            .into_iter()
+            .map(|(id, back_trace)| proto::Stack {
+                id,
+                back_trace: Some(back_trace),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn back_trace(instruction_pointer: u64) -> proto::BackTrace {
+        proto::BackTrace {
+            frames: vec![proto::BackTraceFrame {
+                instruction_pointer,
+                ..Default::default()
+            }],
+        }
+    }
+
+    fn allocation(stack_id: u64, instruction_pointer: u64) -> proto::Allocation {
+        proto::Allocation {
+            stack_id,
+            back_trace: Some(back_trace(instruction_pointer)),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn interned_back_traces_are_sent_once() {
+        let mut allocations = [
+            allocation(1, 0x10),
+            allocation(1, 0x10),
+            allocation(2, 0x20),
+        ];
+        let mut freed = proto::FreedAllocation {
+            allocation: Some(allocation(2, 0x20)),
+            free_back_trace: Some(back_trace(0x30)),
+            free_stack_id: 3,
+            ..Default::default()
+        };
+
+        let mut stacks = StackTable::new();
+        allocations.iter_mut().for_each(|x| stacks.intern(x));
+        stacks.intern_freed(&mut freed);
+
+        assert!(allocations.iter().all(|x| x.back_trace.is_none()));
+        assert!(freed.allocation.unwrap().back_trace.is_none());
+        assert!(freed.free_back_trace.is_none());
+
+        let stacks: Vec<proto::Stack> = stacks.into();
+        let stacks: Vec<_> = stacks
+            .iter()
+            .map(|x| {
+                (
+                    x.id,
+                    x.back_trace.as_ref().unwrap().frames[0].instruction_pointer,
+                )
+            })
+            .collect();
+        assert_eq!(stacks, [(1, 0x10), (2, 0x20), (3, 0x30)]);
+    }
+
+    #[test]
+    fn back_traces_without_id_stay_inline() {
+        let mut allocation = allocation(0, 0x10);
+
+        let mut stacks = StackTable::new();
+        stacks.intern(&mut allocation);
+
+        assert!(allocation.back_trace.is_some());
+        assert!(Vec::<proto::Stack>::from(stacks).is_empty());
+    }
+}
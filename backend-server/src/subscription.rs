@@ -0,0 +1,118 @@
+use std::{thread, time::Duration};
+
+use allocation_catcher_backend::{events::Event, StateRef};
+use bytes::Bytes;
+use common::proto;
+use prost::Message;
+
+// Maximum number of events sent at once.
+const BATCH_SIZE: usize = 0x1000;
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+// An empty batch is sent after this long without events, to notice closed connections.
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Endless stream of encoded `EventBatch` messages.
+pub struct Subscription {
+    state: StateRef,
+    cursor: u64,
+    // Cursor of the next heap error, if they are sent.
+    error_cursor: Option<u64>,
+    filter: proto::SubscribeRequest,
+}
+
+impl Subscription {
+    pub fn new(state: StateRef, filter: proto::SubscribeRequest) -> Self {
+        Self {
+            state,
+            cursor: state.subscribe(),
+            error_cursor: filter.errors.then(|| state.lock_errors().cursor()),
+            filter,
+        }
+    }
+
+    fn read_errors(&mut self) -> (Vec<proto::HeapError>, u64) {
+        let Some(cursor) = self.error_cursor else {
+            return (Vec::new(), 0);
+        };
+
+        let mut errors = Vec::new();
+        let (cursor, dropped) = self
+            .state
+            .lock_errors()
+            .read(cursor, BATCH_SIZE, &mut errors);
+        self.error_cursor = Some(cursor);
+
+        (errors.iter().map(|x| x.into()).collect(), dropped)
+    }
+
+    fn matches(&self, event: &Event) -> bool {
+        let in_range =
+            |range: &proto::Range, value: u64| (range.lower..range.upper).contains(&value);
+
+        self.filter
+            .size
+            .as_ref()
+            .is_none_or(|x| in_range(x, event.size as u64))
+            && self
+                .filter
+                .heap_handle
+                .is_none_or(|x| x == event.heap_handle as u64)
+            && self.filter.address.as_ref().is_none_or(|x| {
+                in_range(x, event.base_address as u64)
+                    || event
+                        .previous_address
+                        .is_some_and(|address| in_range(x, address as u64))
+            })
+    }
+}
+
+impl Iterator for Subscription {
+    type Item = Bytes;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut waited = Duration::ZERO;
+
+        loop {
+            let mut events = Vec::new();
+            let (cursor, dropped) =
+                self.state
+                    .lock_events()
+                    .read(self.cursor, BATCH_SIZE, &mut events);
+            self.cursor = cursor;
+
+            let events: Vec<proto::Event> = events
+                .iter()
+                .filter(|x| self.matches(x))
+                .map(|x| x.into())
+                .collect();
+            let (errors, dropped_errors) = self.read_errors();
+
+            if !events.is_empty()
+                || !errors.is_empty()
+                || dropped != 0
+                || dropped_errors != 0
+                || waited >= KEEPALIVE_INTERVAL
+            {
+                return Some(
+                    proto::EventBatch {
+                        events,
+                        dropped,
+                        errors,
+                        dropped_errors,
+                    }
+                    .encode_to_vec()
+                    .into(),
+                );
+            }
+
+            thread::sleep(POLL_INTERVAL);
+            waited += POLL_INTERVAL;
+        }
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.state.unsubscribe();
+    }
+}
@@ -1,3 +1,4 @@
+#[allow(unused_imports)]
 pub use crate::platform::debug_message_fmt;
 
 #[allow(unused_macros)]
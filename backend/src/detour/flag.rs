@@ -61,7 +61,7 @@ impl DetourFlagSet {
         }
     }
 
-    pub fn acquire(&self, flag: impl Into<usize>) -> Option<AcquisitionGuard> {
+    pub fn acquire(&self, flag: impl Into<usize>) -> Option<AcquisitionGuard<'_>> {
         let mask = 1 << flag.into();
         let acquisition = self.tls_slot_acquisition.acquire(mask);
 
@@ -90,9 +90,9 @@ pub enum DetourFlag {
     Lock,
 }
 
-impl Into<usize> for DetourFlag {
-    fn into(self) -> usize {
-        match self {
+impl From<DetourFlag> for usize {
+    fn from(value: DetourFlag) -> Self {
+        match value {
             DetourFlag::Lock => 0,
         }
     }
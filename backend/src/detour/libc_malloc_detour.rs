@@ -0,0 +1,703 @@
+// Without the `libc-interposer` feature the detour functions are not exported and
+// nothing ever calls them, but the module is still built so the API stays the same.
+#![cfg_attr(not(feature = "libc-interposer"), allow(dead_code))]
+
+use core::{
+    cell::UnsafeCell,
+    ffi::c_void,
+    mem::MaybeUninit,
+    sync::atomic::{AtomicU8, AtomicUsize, Ordering},
+};
+
+use libc::{c_int, size_t};
+
+use super::{
+    allocation_handler, flag_set, handle_released_detour, handle_validated_detour, heap_base,
+    is_enabled, report_damage, Allocation, Base, Deallocation, DetourFlag, Error, Reallocation,
+    Release,
+};
+use crate::redzone;
+
+type MallocFn = unsafe extern "C" fn(size_t) -> *mut c_void;
+type CallocFn = unsafe extern "C" fn(size_t, size_t) -> *mut c_void;
+type ReallocFn = unsafe extern "C" fn(*mut c_void, size_t) -> *mut c_void;
+type FreeFn = unsafe extern "C" fn(*mut c_void);
+type PosixMemalignFn = unsafe extern "C" fn(*mut *mut c_void, size_t, size_t) -> c_int;
+type AlignedAllocFn = unsafe extern "C" fn(size_t, size_t) -> *mut c_void;
+type MemalignFn = unsafe extern "C" fn(size_t, size_t) -> *mut c_void;
+type VallocFn = unsafe extern "C" fn(size_t) -> *mut c_void;
+type MallocUsableSizeFn = unsafe extern "C" fn(*mut c_void) -> size_t;
+
+// There is no heap handle on Linux, every allocation is reported with this one.
+const LIBC_HEAP_HANDLE: usize = 0;
+
+struct Originals {
+    malloc: MallocFn,
+    calloc: CallocFn,
+    realloc: ReallocFn,
+    free: FreeFn,
+    posix_memalign: PosixMemalignFn,
+    aligned_alloc: AlignedAllocFn,
+    memalign: MemalignFn,
+    valloc: VallocFn,
+    malloc_usable_size: MallocUsableSizeFn,
+}
+
+struct OriginalsCell(UnsafeCell<MaybeUninit<Originals>>);
+
+// SAFETY: written only once, by the thread that won the `UNRESOLVED -> RESOLVING` transition,
+// and read only after `RESOLVED` has been observed.
+unsafe impl Sync for OriginalsCell {}
+
+const UNRESOLVED: u8 = 0;
+const RESOLVING: u8 = 1;
+const RESOLVED: u8 = 2;
+
+static ORIGINALS: OriginalsCell = OriginalsCell(UnsafeCell::new(MaybeUninit::uninit()));
+static ORIGINALS_STATE: AtomicU8 = AtomicU8::new(UNRESOLVED);
+
+// The error is the name of the function.
+unsafe fn resolve<T: Copy>(name: &'static [u8]) -> Result<T, &'static [u8]> {
+    let symbol = libc::dlsym(libc::RTLD_NEXT, name.as_ptr() as _);
+    if symbol.is_null() {
+        Err(name)
+    } else {
+        Ok(core::mem::transmute_copy(&symbol))
+    }
+}
+
+unsafe fn resolve_originals() -> Result<Originals, &'static [u8]> {
+    Ok(Originals {
+        malloc: resolve(b"malloc\0")?,
+        calloc: resolve(b"calloc\0")?,
+        realloc: resolve(b"realloc\0")?,
+        free: resolve(b"free\0")?,
+        posix_memalign: resolve(b"posix_memalign\0")?,
+        aligned_alloc: resolve(b"aligned_alloc\0")?,
+        memalign: resolve(b"memalign\0")?,
+        valloc: resolve(b"valloc\0")?,
+        malloc_usable_size: resolve(b"malloc_usable_size\0")?,
+    })
+}
+
+// Returns `None` while the original functions are not resolved yet.
+// `dlsym` allocates, so the first call resolves them and every call made during
+// the resolution (by this thread or any other) is served from the bootstrap arena.
+fn originals() -> Option<&'static Originals> {
+    let state = ORIGINALS_STATE.load(Ordering::Acquire);
+
+    if state == RESOLVED {
+        return Some(unsafe { (*ORIGINALS.0.get()).assume_init_ref() });
+    }
+
+    if state != UNRESOLVED
+        || ORIGINALS_STATE
+            .compare_exchange(UNRESOLVED, RESOLVING, Ordering::AcqRel, Ordering::Acquire)
+            .is_err()
+    {
+        return None;
+    }
+
+    match unsafe { resolve_originals() } {
+        Ok(originals) => {
+            let originals = unsafe { (*ORIGINALS.0.get()).write(originals) };
+            ORIGINALS_STATE.store(RESOLVED, Ordering::Release);
+            Some(originals)
+        }
+        // Only the bootstrap arena would be left to allocate from.
+        Err(name) => abort_unresolved(name),
+    }
+}
+
+fn abort_unresolved(name: &[u8]) -> ! {
+    let name = name.strip_suffix(b"\0").unwrap_or(name);
+    let message: [&[u8]; 3] = [
+        b"allocation-catcher: could not resolve the original ",
+        name,
+        b" with dlsym(RTLD_NEXT), aborting\n",
+    ];
+
+    // Nothing may be allocated to format the message.
+    for part in message {
+        unsafe { libc::write(libc::STDERR_FILENO, part.as_ptr() as _, part.len()) };
+    }
+
+    unsafe { libc::abort() }
+}
+
+// Bump allocator used only until the original functions are resolved.
+// Blocks are never reused, so the memory is always zeroed and `free` is a no-op.
+const BOOTSTRAP_ARENA_SIZE: usize = 0x10000;
+
+// Every block is preceded by a header with its size, needed by `realloc` and `malloc_usable_size`.
+const BOOTSTRAP_HEADER_SIZE: usize = 16;
+
+#[repr(C, align(16))]
+struct BootstrapArena(UnsafeCell<[u8; BOOTSTRAP_ARENA_SIZE]>);
+
+// SAFETY: disjoint parts of the arena are handed out through the atomic offset.
+unsafe impl Sync for BootstrapArena {}
+
+static BOOTSTRAP_ARENA: BootstrapArena = BootstrapArena(UnsafeCell::new([0; BOOTSTRAP_ARENA_SIZE]));
+static BOOTSTRAP_ARENA_OFFSET: AtomicUsize = AtomicUsize::new(0);
+
+fn bootstrap_allocate(size: usize, alignment: usize) -> *mut c_void {
+    let arena = BOOTSTRAP_ARENA.0.get() as usize;
+    let alignment = alignment.max(BOOTSTRAP_HEADER_SIZE);
+    let mut offset = BOOTSTRAP_ARENA_OFFSET.load(Ordering::Relaxed);
+
+    loop {
+        let start = (arena + offset + BOOTSTRAP_HEADER_SIZE).next_multiple_of(alignment) - arena;
+        let end = match start.checked_add(size) {
+            Some(end) if end <= BOOTSTRAP_ARENA_SIZE => end,
+            _ => return core::ptr::null_mut(),
+        };
+
+        match BOOTSTRAP_ARENA_OFFSET.compare_exchange_weak(
+            offset,
+            end,
+            Ordering::Relaxed,
+            Ordering::Relaxed,
+        ) {
+            Ok(_) => {
+                let ptr = (arena + start) as *mut c_void;
+                unsafe { bootstrap_header(ptr).write(size) };
+                return ptr;
+            }
+            Err(current) => offset = current,
+        }
+    }
+}
+
+fn is_bootstrap(ptr: *mut c_void) -> bool {
+    let arena = BOOTSTRAP_ARENA.0.get() as usize;
+    (arena..arena + BOOTSTRAP_ARENA_SIZE).contains(&(ptr as usize))
+}
+
+unsafe fn bootstrap_header(ptr: *mut c_void) -> *mut usize {
+    (ptr as usize - BOOTSTRAP_HEADER_SIZE) as *mut usize
+}
+
+fn is_valid_alignment(alignment: usize) -> bool {
+    alignment.is_power_of_two() && alignment.is_multiple_of(core::mem::size_of::<*mut c_void>())
+}
+
+fn page_size() -> usize {
+    unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize }
+}
+
+fn non_null(ptr: *mut c_void) -> Option<usize> {
+    if ptr.is_null() {
+        None
+    } else {
+        Some(ptr as usize)
+    }
+}
+
+// Size of the canaries around the blocks of `malloc`, `calloc` and `realloc`,
+// zero until the redzones are set up. Aligned blocks are never armed.
+static REDZONE: AtomicUsize = AtomicUsize::new(0);
+
+fn redzone() -> usize {
+    REDZONE.load(Ordering::Relaxed)
+}
+
+// Allocates the block with `allocate`, with canaries around it if the redzones are set up.
+unsafe fn allocate_armed(
+    size: usize,
+    redzone: usize,
+    allocate: impl FnOnce(usize) -> *mut c_void,
+) -> *mut c_void {
+    if redzone == 0 {
+        return allocate(size);
+    }
+
+    let Some(block_size) = redzone::block_size(size, redzone) else {
+        return core::ptr::null_mut();
+    };
+
+    let block = allocate(block_size);
+    if block.is_null() {
+        return block;
+    }
+
+    let ptr = block as usize + redzone::front_size(redzone);
+    redzone::arm(ptr, size, redzone);
+    ptr as *mut c_void
+}
+
+// The block known to the original functions, and the size of the user block if it is armed.
+unsafe fn unarm(ptr: *mut c_void, redzone: usize) -> (*mut c_void, Option<usize>) {
+    let armed_size = (redzone != 0)
+        .then(|| redzone::armed_size(ptr as usize, redzone))
+        .flatten();
+
+    match (redzone::block_start(ptr as usize, redzone), armed_size) {
+        (Some(block), Some(size)) => (block as *mut c_void, Some(size)),
+        _ => (ptr, None),
+    }
+}
+
+// Like `unarm`, but while enabled the tracked blocks whose header is damaged are found as well,
+// so that the damage is reported and the actual block is forwarded.
+unsafe fn unarm_tracked(ptr: *mut c_void, redzone: usize) -> (*mut c_void, Option<usize>) {
+    let unarmed = unarm(ptr, redzone);
+    if redzone == 0 || unarmed.1.is_some() || !is_enabled() {
+        return unarmed;
+    }
+
+    // The handler may hold the storage while the call is made on its behalf.
+    let Some(_lock) = flag_set().acquire(DetourFlag::Lock) else {
+        return unarmed;
+    };
+
+    match (
+        redzone::block_start(ptr as usize, redzone),
+        allocation_handler().armed_size(ptr as usize, redzone),
+    ) {
+        (Some(block), Some(size)) => (block as *mut c_void, Some(size)),
+        _ => unarmed,
+    }
+}
+
+// Whether the handler fails the allocation, errno is set as the original functions do
+// on exhausted memory.
+fn inject_failure(base: Base, size: usize, reallocated: Option<usize>) -> bool {
+    let inject = unsafe { allocation_handler() }.inject_failure(base, size, reallocated);
+    if inject {
+        unsafe { *libc::__errno_location() = libc::ENOMEM };
+    }
+    inject
+}
+
+#[cfg_attr(feature = "libc-interposer", no_mangle)]
+pub unsafe extern "C" fn malloc(size: size_t) -> *mut c_void {
+    let Some(originals) = originals() else {
+        return bootstrap_allocate(size, BOOTSTRAP_HEADER_SIZE);
+    };
+
+    let redzone = redzone();
+    let allocate = || allocate_armed(size, redzone, |x| (originals.malloc)(x));
+
+    if !is_enabled() {
+        return allocate();
+    }
+
+    let base = heap_base!(LIBC_HEAP_HANDLE);
+
+    handle_validated_detour(
+        || !inject_failure(base, size, None),
+        allocate,
+        |base_address| {
+            unsafe { allocation_handler() }.on_allocation(Allocation {
+                base,
+                size,
+                allocated_base_address: non_null(base_address),
+                redzone,
+            });
+        },
+    )
+    .unwrap_or(core::ptr::null_mut())
+}
+
+#[cfg_attr(feature = "libc-interposer", no_mangle)]
+pub unsafe extern "C" fn calloc(count: size_t, size: size_t) -> *mut c_void {
+    let Some(originals) = originals() else {
+        return match count.checked_mul(size) {
+            Some(total) => bootstrap_allocate(total, BOOTSTRAP_HEADER_SIZE),
+            None => core::ptr::null_mut(),
+        };
+    };
+
+    let redzone = redzone();
+    // The original function reports the overflows.
+    let allocate = || match count.checked_mul(size) {
+        Some(total) if redzone != 0 => allocate_armed(total, redzone, |x| (originals.calloc)(1, x)),
+        _ => (originals.calloc)(count, size),
+    };
+
+    if !is_enabled() {
+        return allocate();
+    }
+
+    let base = heap_base!(LIBC_HEAP_HANDLE);
+
+    handle_validated_detour(
+        || !inject_failure(base, count.saturating_mul(size), None),
+        allocate,
+        |base_address| {
+            unsafe { allocation_handler() }.on_allocation(Allocation {
+                base,
+                size: count.saturating_mul(size),
+                allocated_base_address: non_null(base_address),
+                redzone,
+            });
+        },
+    )
+    .unwrap_or(core::ptr::null_mut())
+}
+
+#[cfg_attr(feature = "libc-interposer", no_mangle)]
+pub unsafe extern "C" fn realloc(ptr: *mut c_void, size: size_t) -> *mut c_void {
+    if ptr.is_null() {
+        return malloc(size);
+    }
+
+    if is_bootstrap(ptr) {
+        // Move the block out of the arena. The new block is reported as a fresh allocation,
+        // since the bootstrap one has never been reported.
+        let new_ptr = malloc(size);
+        if !new_ptr.is_null() {
+            let old_size = *bootstrap_header(ptr);
+            core::ptr::copy_nonoverlapping(
+                ptr as *const u8,
+                new_ptr as *mut u8,
+                old_size.min(size),
+            );
+        }
+        return new_ptr;
+    }
+
+    let Some(originals) = originals() else {
+        return core::ptr::null_mut();
+    };
+
+    let redzone = redzone();
+    let (block, armed_size) = unarm_tracked(ptr, redzone);
+    // Blocks allocated before the redzones were set up stay unarmed.
+    let redzone = if armed_size.is_some() { redzone } else { 0 };
+    let reallocate = || allocate_armed(size, redzone, |x| (originals.realloc)(block, x));
+
+    if !is_enabled() {
+        return reallocate();
+    }
+
+    let base = heap_base!(LIBC_HEAP_HANDLE);
+
+    // The block is left untouched when the reallocation fails. As for `free`, the blocks
+    // already freed are reported instead of being forwarded, they may be in the quarantine.
+    handle_validated_detour(
+        || {
+            if !unsafe { allocation_handler() }.validate_deallocation(base, ptr as usize) {
+                return false;
+            }
+
+            if size != 0 && inject_failure(base, size, Some(ptr as usize)) {
+                return false;
+            }
+
+            report_damage(
+                allocation_handler(),
+                base,
+                ptr as usize,
+                armed_size,
+                redzone,
+            );
+            true
+        },
+        reallocate,
+        |base_address| {
+            if base_address.is_null() && size == 0 {
+                // realloc(ptr, 0) frees the block.
+                unsafe { allocation_handler() }.on_deallocation(Deallocation {
+                    base,
+                    base_address: ptr as usize,
+                    success: true,
+                    release: None,
+                });
+            } else {
+                unsafe { allocation_handler() }.on_reallocation(Reallocation {
+                    base_address: ptr as usize,
+                    allocation: Allocation {
+                        base,
+                        size,
+                        allocated_base_address: non_null(base_address),
+                        redzone,
+                    },
+                });
+            }
+        },
+    )
+    .unwrap_or(core::ptr::null_mut())
+}
+
+#[cfg_attr(feature = "libc-interposer", no_mangle)]
+pub unsafe extern "C" fn free(ptr: *mut c_void) {
+    if ptr.is_null() || is_bootstrap(ptr) {
+        return;
+    }
+
+    let Some(originals) = originals() else {
+        // Can not be allocated by the original allocator if it is not resolved yet.
+        return;
+    };
+
+    let redzone = redzone();
+    let (block, armed_size) = unarm_tracked(ptr, redzone);
+
+    if !is_enabled() {
+        return (originals.free)(block);
+    }
+
+    let base = heap_base!(LIBC_HEAP_HANDLE);
+
+    // glibc aborts on double frees, they are reported instead of being forwarded.
+    handle_released_detour(
+        || {
+            let valid = unsafe { allocation_handler() }.validate_deallocation(base, ptr as usize);
+            if valid {
+                report_damage(
+                    allocation_handler(),
+                    base,
+                    ptr as usize,
+                    armed_size,
+                    redzone,
+                );
+            }
+            valid
+        },
+        Release::new(release, block as usize),
+        |release| {
+            unsafe { allocation_handler() }.on_deallocation(Deallocation {
+                base,
+                base_address: ptr as usize,
+                success: true,
+                release: Some(release),
+            });
+        },
+    );
+}
+
+// Frees the blocks once they leave the quarantine.
+unsafe fn release(block: usize) {
+    if let Some(originals) = originals() {
+        (originals.free)(block as *mut c_void);
+    }
+}
+
+#[cfg_attr(feature = "libc-interposer", no_mangle)]
+pub unsafe extern "C" fn posix_memalign(
+    memptr: *mut *mut c_void,
+    alignment: size_t,
+    size: size_t,
+) -> c_int {
+    let Some(originals) = originals() else {
+        if !is_valid_alignment(alignment) {
+            return libc::EINVAL;
+        }
+
+        let ptr = bootstrap_allocate(size, alignment);
+        if ptr.is_null() {
+            return libc::ENOMEM;
+        }
+
+        *memptr = ptr;
+        return 0;
+    };
+
+    if !is_enabled() {
+        return (originals.posix_memalign)(memptr, alignment, size);
+    }
+
+    let base = heap_base!(LIBC_HEAP_HANDLE);
+
+    // Only the valid requests may fail on exhausted memory.
+    handle_validated_detour(
+        || !is_valid_alignment(alignment) || !inject_failure(base, size, None),
+        || (originals.posix_memalign)(memptr, alignment, size),
+        |result| {
+            if result == 0 {
+                unsafe { allocation_handler() }.on_allocation(Allocation {
+                    base,
+                    size,
+                    allocated_base_address: non_null(unsafe { *memptr }),
+                    redzone: 0,
+                });
+            }
+        },
+    )
+    .unwrap_or(libc::ENOMEM)
+}
+
+#[cfg_attr(feature = "libc-interposer", no_mangle)]
+pub unsafe extern "C" fn aligned_alloc(alignment: size_t, size: size_t) -> *mut c_void {
+    let Some(originals) = originals() else {
+        return bootstrap_allocate(size, alignment);
+    };
+
+    if !is_enabled() {
+        return (originals.aligned_alloc)(alignment, size);
+    }
+
+    let base = heap_base!(LIBC_HEAP_HANDLE);
+
+    handle_validated_detour(
+        || !inject_failure(base, size, None),
+        || (originals.aligned_alloc)(alignment, size),
+        |base_address| {
+            unsafe { allocation_handler() }.on_allocation(Allocation {
+                base,
+                size,
+                allocated_base_address: non_null(base_address),
+                redzone: 0,
+            });
+        },
+    )
+    .unwrap_or(core::ptr::null_mut())
+}
+
+#[cfg_attr(feature = "libc-interposer", no_mangle)]
+pub unsafe extern "C" fn memalign(alignment: size_t, size: size_t) -> *mut c_void {
+    let Some(originals) = originals() else {
+        return bootstrap_allocate(size, alignment);
+    };
+
+    if !is_enabled() {
+        return (originals.memalign)(alignment, size);
+    }
+
+    let base = heap_base!(LIBC_HEAP_HANDLE);
+
+    handle_validated_detour(
+        || !inject_failure(base, size, None),
+        || (originals.memalign)(alignment, size),
+        |base_address| {
+            unsafe { allocation_handler() }.on_allocation(Allocation {
+                base,
+                size,
+                allocated_base_address: non_null(base_address),
+                redzone: 0,
+            });
+        },
+    )
+    .unwrap_or(core::ptr::null_mut())
+}
+
+#[cfg_attr(feature = "libc-interposer", no_mangle)]
+pub unsafe extern "C" fn valloc(size: size_t) -> *mut c_void {
+    let Some(originals) = originals() else {
+        return bootstrap_allocate(size, page_size());
+    };
+
+    if !is_enabled() {
+        return (originals.valloc)(size);
+    }
+
+    let base = heap_base!(LIBC_HEAP_HANDLE);
+
+    handle_validated_detour(
+        || !inject_failure(base, size, None),
+        || (originals.valloc)(size),
+        |base_address| {
+            unsafe { allocation_handler() }.on_allocation(Allocation {
+                base,
+                size,
+                allocated_base_address: non_null(base_address),
+                redzone: 0,
+            });
+        },
+    )
+    .unwrap_or(core::ptr::null_mut())
+}
+
+#[cfg_attr(feature = "libc-interposer", no_mangle)]
+pub unsafe extern "C" fn malloc_usable_size(ptr: *mut c_void) -> size_t {
+    if ptr.is_null() {
+        return 0;
+    }
+
+    if is_bootstrap(ptr) {
+        return *bootstrap_header(ptr);
+    }
+
+    match originals() {
+        Some(originals) => match unarm_tracked(ptr, redzone()) {
+            (_, Some(size)) => size,
+            (block, None) => (originals.malloc_usable_size)(block),
+        },
+        None => 0,
+    }
+}
+
+pub unsafe fn initialize() -> Result<(), Error> {
+    // Another thread may be resolving the original functions right now.
+    while originals().is_none() {
+        core::hint::spin_loop();
+    }
+
+    Ok(())
+}
+
+pub unsafe fn set_redzone(redzone: usize) {
+    REDZONE.store(redzone, Ordering::Relaxed);
+}
+
+// The exported functions are always in place, reporting is switched by `detour::enable`.
+pub unsafe fn enable() -> Result<(), Error> {
+    Ok(())
+}
+
+pub unsafe fn disable() -> Result<(), Error> {
+    Ok(())
+}
+
+// The original functions are kept resolved: the exported functions keep forwarding to them.
+pub unsafe fn uninitialize() -> Result<(), Error> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn originals_are_resolved() {
+        assert!(unsafe { initialize() }.is_ok());
+
+        let originals = originals().unwrap();
+        let ptr = unsafe { (originals.malloc)(0x20) };
+        assert!(!ptr.is_null() && !is_bootstrap(ptr));
+        assert!(unsafe { (originals.malloc_usable_size)(ptr) } >= 0x20);
+        unsafe { (originals.free)(ptr) };
+    }
+
+    #[test]
+    fn missing_function_is_named() {
+        let name = b"allocation_catcher_missing\0";
+        let resolved = unsafe { resolve::<MallocFn>(name) };
+        assert_eq!(resolved.err(), Some(&name[..]));
+    }
+
+    #[test]
+    fn bootstrap_blocks_are_aligned_and_zeroed() {
+        let ptr = bootstrap_allocate(0x30, 0x40);
+        assert!(is_bootstrap(ptr));
+        assert_eq!(ptr as usize % 0x40, 0);
+        assert_eq!(unsafe { malloc_usable_size(ptr) }, 0x30);
+
+        let block = unsafe { core::slice::from_raw_parts(ptr as *const u8, 0x30) };
+        assert!(block.iter().all(|&x| x == 0));
+
+        // Never released, the arena is not reused.
+        unsafe { free(ptr) };
+        assert_ne!(bootstrap_allocate(0x30, 0x40), ptr);
+    }
+
+    #[test]
+    fn bootstrap_block_is_moved_out_by_reallocation() {
+        let ptr = bootstrap_allocate(0x10, BOOTSTRAP_HEADER_SIZE);
+        unsafe { core::ptr::write_bytes(ptr as *mut u8, 0xAB, 0x10) };
+
+        let moved = unsafe { realloc(ptr, 0x40) };
+        assert!(!moved.is_null() && !is_bootstrap(moved));
+
+        let block = unsafe { core::slice::from_raw_parts(moved as *const u8, 0x10) };
+        assert!(block.iter().all(|&x| x == 0xAB));
+        unsafe { free(moved) };
+    }
+
+    #[test]
+    fn exhausted_bootstrap_arena_fails_the_allocation() {
+        assert!(bootstrap_allocate(BOOTSTRAP_ARENA_SIZE, BOOTSTRAP_HEADER_SIZE).is_null());
+    }
+}
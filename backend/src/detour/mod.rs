@@ -1,9 +1,48 @@
 mod flag;
+#[cfg(target_os = "linux")]
+mod libc_malloc_detour;
+#[cfg(windows)]
 mod rtl_heap_detour;
+mod tracking_allocator;
 
-pub use flag::{flag_set, AcquisitionGuard, DetourFlag, DetourFlagSet};
-pub use rtl_heap_detour::{disable, enable, initialize, uninitialize};
+use core::sync::atomic::{AtomicBool, Ordering};
 
+use crate::redzone::{self, Damage};
+
+pub use flag::{flag_set, DetourFlag};
+pub use tracking_allocator::{tracking_allocator_used, TrackingAllocator};
+
+#[cfg(target_os = "linux")]
+use libc_malloc_detour as platform;
+#[cfg(windows)]
+use rtl_heap_detour as platform;
+
+// No heap detours on other platforms, only the `TrackingAllocator` is available.
+#[cfg(not(any(windows, target_os = "linux")))]
+mod platform {
+    use super::Error;
+
+    pub unsafe fn initialize() -> Result<(), Error> {
+        Ok(())
+    }
+
+    pub unsafe fn set_redzone(_redzone: usize) {}
+
+    pub unsafe fn enable() -> Result<(), Error> {
+        Ok(())
+    }
+
+    pub unsafe fn disable() -> Result<(), Error> {
+        Ok(())
+    }
+
+    pub unsafe fn uninitialize() -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+// Not every platform produces every error.
+#[allow(dead_code)]
 #[derive(Debug)]
 pub enum Error {
     CouldNotFindModule,
@@ -15,6 +54,7 @@ pub enum Error {
 
 pub type HeapHandle = usize;
 
+#[derive(Clone, Copy)]
 pub struct Base {
     pub heap_handle: HeapHandle,
 
@@ -28,6 +68,8 @@ pub struct Allocation {
     pub base: Base,
     pub size: usize,
     pub allocated_base_address: Option<usize>,
+    // Size of the canaries on each side of the block, zero if there are none.
+    pub redzone: usize,
 }
 
 pub struct Reallocation {
@@ -39,14 +81,91 @@ pub struct Deallocation {
     pub base: Base,
     pub base_address: usize,
     pub success: bool,
+    // Set if the block has not been released yet: the handler must either release it
+    // or hold it in the quarantine and release it later.
+    pub release: Option<Release>,
+}
+
+// Returns a freed block to the heap it was allocated from.
+pub struct Release {
+    function: unsafe fn(usize),
+    block: usize,
+}
+
+impl Release {
+    pub const fn new(function: unsafe fn(usize), block: usize) -> Self {
+        Self { function, block }
+    }
+
+    // SAFETY: must be called only once.
+    pub unsafe fn release(self) {
+        (self.function)(self.block)
+    }
 }
 
+// Reported before the block is freed or reallocated.
+pub struct Corruption {
+    pub base: Base,
+    pub base_address: usize,
+    pub size: usize,
+    pub damage: Damage,
+}
+
+extern "C" {
+    #[link_name = "llvm.returnaddress"]
+    fn return_address(a: i32) -> *const u8;
+
+    #[link_name = "llvm.addressofreturnaddress"]
+    fn addressofreturnaddress() -> *const u8;
+
+    #[link_name = "llvm.frameaddress"]
+    fn frame_address(a: i32) -> *const u8;
+}
+
+// Must be expanded directly inside of the detour function, so that the addresses
+// belong to the frame of the detour and not of some helper.
+macro_rules! heap_base {
+    ($heap_handle:expr) => {
+        $crate::detour::Base {
+            heap_handle: $heap_handle as usize,
+            return_address: unsafe { Some($crate::detour::return_address(0) as usize) },
+            address_of_return_address: unsafe {
+                Some($crate::detour::addressofreturnaddress() as usize)
+            },
+            stack_frame_address: unsafe { Some($crate::detour::frame_address(0) as usize) },
+        }
+    };
+}
+
+pub(crate) use heap_base;
+
 pub trait AllocationHandler: Sync {
     fn on_allocation(&self, allocation: Allocation);
 
     fn on_deallocation(&self, deallocation: Deallocation);
 
+    // Called before the free or the reallocation is forwarded by the detours whose allocator
+    // aborts on invalid frees. The call is neither forwarded nor handled if this returns false.
+    fn validate_deallocation(&self, _base: Base, _base_address: usize) -> bool {
+        true
+    }
+
+    // Called before an allocation is forwarded by the detours able to fail it as the allocator
+    // does on exhausted memory. The allocation fails without being forwarded if this returns true.
+    // `reallocated` is the base address of the block being resized, if any.
+    fn inject_failure(&self, _base: Base, _size: usize, _reallocated: Option<usize>) -> bool {
+        false
+    }
+
+    // Size of the tracked block armed with the redzone, called by the detours unable to read
+    // it from the header of the block, which may be damaged.
+    fn armed_size(&self, _base_address: usize, _redzone: usize) -> Option<usize> {
+        None
+    }
+
     fn on_reallocation(&self, reallocation: Reallocation);
+
+    fn on_corruption(&self, corruption: Corruption);
 }
 
 pub struct NoopAllocationHandler;
@@ -54,9 +173,115 @@ pub struct NoopAllocationHandler;
 impl AllocationHandler for NoopAllocationHandler {
     fn on_allocation(&self, _allocation: Allocation) {}
 
-    fn on_deallocation(&self, _deallocation: Deallocation) {}
+    fn on_deallocation(&self, deallocation: Deallocation) {
+        if let Some(release) = deallocation.release {
+            unsafe { release.release() };
+        }
+    }
 
     fn on_reallocation(&self, _reallocation: Reallocation) {}
+
+    fn on_corruption(&self, _corruption: Corruption) {}
+}
+
+// Detours that are always installed (the `TrackingAllocator` and the libc interposer)
+// report to the allocation handler only while this is set, unless given their own handler.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+#[inline(always)]
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+pub unsafe fn initialize() -> Result<(), Error> {
+    platform::initialize()
+}
+
+// SAFETY: must be called only once, the blocks allocated before are never armed.
+// Only the Linux malloc interposer supports redzones.
+pub unsafe fn set_redzone(redzone: usize) {
+    platform::set_redzone(redzone::round(redzone))
+}
+
+// SAFETY: `initialize` must be called before this method is called
+pub unsafe fn enable() -> Result<(), Error> {
+    platform::enable()?;
+    ENABLED.store(true, Ordering::SeqCst);
+    Ok(())
+}
+
+pub unsafe fn disable() -> Result<(), Error> {
+    ENABLED.store(false, Ordering::SeqCst);
+    platform::disable()
+}
+
+pub unsafe fn uninitialize() -> Result<(), Error> {
+    ENABLED.store(false, Ordering::SeqCst);
+    platform::uninitialize()
+}
+
+// The call is not forwarded nor handled if `validate` rejects it.
+#[inline(always)]
+fn handle_validated_detour<T: Copy>(
+    validate: impl FnOnce() -> bool,
+    forward: impl FnOnce() -> T,
+    handle: impl FnOnce(T),
+) -> Option<T> {
+    // Do not handle all the recursive calls to detour functions.
+    let recursion_lock = flag_set().acquire(DetourFlag::Lock);
+
+    if recursion_lock.is_some() && !validate() {
+        return None;
+    }
+
+    // Call original function.
+    let result = forward();
+
+    // Handle only non-recursive calls.
+    if recursion_lock.is_some() {
+        handle(result);
+    }
+
+    Some(result)
+}
+
+// Like `handle_validated_detour` for frees whose release is left to the handler.
+// The block is released right away if the call is not handled.
+#[inline(always)]
+fn handle_released_detour(
+    validate: impl FnOnce() -> bool,
+    release: Release,
+    handle: impl FnOnce(Release),
+) {
+    let recursion_lock = flag_set().acquire(DetourFlag::Lock);
+
+    match recursion_lock {
+        Some(_) if validate() => handle(release),
+        Some(_) => {}
+        None => unsafe { release.release() },
+    }
+}
+
+// Reports the damaged canaries of an armed block to the handler, before it is released.
+unsafe fn report_damage(
+    handler: &dyn AllocationHandler,
+    base: Base,
+    base_address: usize,
+    size: Option<usize>,
+    redzone: usize,
+) {
+    let Some(size) = size else {
+        return;
+    };
+
+    if let Some(damage) = redzone::inspect(base_address, size, redzone) {
+        handler.on_corruption(Corruption {
+            base,
+            base_address,
+            size,
+            damage,
+        });
+    }
 }
 
 static mut ALLOCATION_HANDLER: &'static dyn AllocationHandler = &NoopAllocationHandler;
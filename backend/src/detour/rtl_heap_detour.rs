@@ -12,7 +12,8 @@ use winapi::{
 };
 
 use super::{
-    allocation_handler, flag_set, Allocation, Base, Deallocation, DetourFlag, Error, Reallocation,
+    allocation_handler, flag_set, heap_base, Allocation, Deallocation, DetourFlag, Error,
+    Reallocation,
 };
 
 type RtlAllocateHeapFn = unsafe extern "system" fn(PVOID, ULONG, SIZE_T) -> PVOID;
@@ -50,28 +51,6 @@ fn handle_detour<T: Copy>(
     result
 }
 
-extern "C" {
-    #[link_name = "llvm.returnaddress"]
-    fn return_address(a: i32) -> *const u8;
-
-    #[link_name = "llvm.addressofreturnaddress"]
-    fn addressofreturnaddress() -> *const u8;
-
-    #[link_name = "llvm.frameaddress"]
-    fn frame_address(a: i32) -> *const u8;
-}
-
-macro_rules! heap_base {
-    ($heap_handle:ident) => {
-        Base {
-            heap_handle: $heap_handle as usize,
-            return_address: unsafe { Some(return_address(0) as usize) },
-            address_of_return_address: unsafe { Some(addressofreturnaddress() as usize) },
-            stack_frame_address: unsafe { Some(frame_address(0) as usize) },
-        }
-    };
-}
-
 #[allow(non_snake_case)]
 unsafe extern "system" fn RtlAllocateHeapDetour(
     HeapHandle: PVOID,
@@ -93,6 +72,7 @@ unsafe extern "system" fn RtlAllocateHeapDetour(
                 } else {
                     Some(base_address as usize)
                 },
+                redzone: 0,
             });
         },
     )
@@ -126,6 +106,7 @@ unsafe extern "system" fn RtlReAllocateHeapDetour(
                     } else {
                         Some(base_address as usize)
                     },
+                    redzone: 0,
                 },
             });
         },
@@ -149,6 +130,7 @@ unsafe extern "system" fn RtlFreeHeapDetour(
                 base,
                 base_address: BaseAddress as usize,
                 success: success != 0,
+                release: None,
             });
         },
     )
@@ -209,6 +191,10 @@ pub unsafe fn initialize() -> Result<(), Error> {
     }
 }
 
+// Other functions taking the blocks, such as RtlSizeHeap, are not detoured, so the returned
+// pointers can not be offset.
+pub unsafe fn set_redzone(_redzone: usize) {}
+
 // SAFETY: `initialize` must be called before this method is called
 pub unsafe fn enable() -> Result<(), Error> {
     let detours = DETOURS.as_ref().unwrap();
@@ -0,0 +1,335 @@
+use core::sync::atomic::{AtomicBool, Ordering};
+use std::alloc::{GlobalAlloc, Layout};
+
+use super::{
+    allocation_handler, handle_validated_detour, heap_base, is_enabled, report_damage, Allocation,
+    AllocationHandler, Deallocation, Reallocation,
+};
+use crate::redzone;
+
+// Set once any `TrackingAllocator` reports a call.
+static USED: AtomicBool = AtomicBool::new(false);
+
+/// Whether the calls of a `TrackingAllocator` have been reported, see its limitations.
+pub fn tracking_allocator_used() -> bool {
+    USED.load(Ordering::Relaxed)
+}
+
+/// Global allocator adapter reporting every allocation made through the inner allocator.
+///
+/// ```ignore
+/// #[global_allocator]
+/// static GLOBAL: TrackingAllocator<System> = TrackingAllocator::new(System);
+/// ```
+///
+/// Allocations are reported to the handler of the `AllocationCatcher` while it is enabled,
+/// or to the handler given to the adapter.
+/// The heap handle of every reported allocation is the address of the adapter.
+///
+/// The injected faults fail the allocations with a null pointer, which most of the Rust
+/// allocation paths turn into an abort through `handle_alloc_error`.
+///
+/// The quarantine is not supported: `dealloc` can not fail nor be deferred, so the freed
+/// blocks are returned to the inner allocator right away, whatever the quarantine size.
+/// Writes after free of these blocks are not detected, and their double frees reach the
+/// inner allocator.
+pub struct TrackingAllocator<A> {
+    inner: A,
+    redzone: usize,
+    handler: Option<&'static dyn AllocationHandler>,
+}
+
+impl<A> TrackingAllocator<A> {
+    pub const fn new(inner: A) -> Self {
+        Self {
+            inner,
+            redzone: 0,
+            handler: None,
+        }
+    }
+
+    /// Surrounds every block with at least `redzone` bytes of canaries on each side,
+    /// checked when the block is reallocated or freed.
+    pub const fn with_redzone(inner: A, redzone: usize) -> Self {
+        Self {
+            inner,
+            redzone: redzone::round(redzone),
+            handler: None,
+        }
+    }
+
+    /// Like `with_redzone`, reporting every call to `handler` instead, whether the
+    /// `AllocationCatcher` is enabled or not.
+    pub const fn with_handler(
+        inner: A,
+        redzone: usize,
+        handler: &'static dyn AllocationHandler,
+    ) -> Self {
+        Self {
+            inner,
+            redzone: redzone::round(redzone),
+            handler: Some(handler),
+        }
+    }
+
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    fn handler(&self) -> Option<&'static dyn AllocationHandler> {
+        if self.handler.is_some() {
+            return self.handler;
+        }
+
+        is_enabled().then(|| unsafe { allocation_handler() })
+    }
+
+    fn heap_handle(&self) -> usize {
+        if !USED.load(Ordering::Relaxed) {
+            USED.store(true, Ordering::Relaxed);
+        }
+
+        self as *const Self as usize
+    }
+
+    // Offset of the user block in the underlying one, keeping its alignment.
+    fn front_size(&self, layout: Layout) -> usize {
+        redzone::front_size(self.redzone).next_multiple_of(layout.align())
+    }
+
+    fn inner_layout(&self, layout: Layout) -> Option<Layout> {
+        if self.redzone == 0 {
+            return Some(layout);
+        }
+
+        let size = self
+            .front_size(layout)
+            .checked_add(layout.size())?
+            .checked_add(self.redzone)?;
+        Layout::from_size_align(size, layout.align()).ok()
+    }
+
+    unsafe fn arm(&self, block: *mut u8, layout: Layout) -> *mut u8 {
+        if self.redzone == 0 || block.is_null() {
+            return block;
+        }
+
+        let ptr = block.add(self.front_size(layout));
+        redzone::arm(ptr as usize, layout.size(), self.redzone);
+        ptr
+    }
+
+    unsafe fn unarm(&self, ptr: *mut u8, layout: Layout) -> *mut u8 {
+        if self.redzone == 0 {
+            return ptr;
+        }
+
+        ptr.sub(self.front_size(layout))
+    }
+
+    fn armed_size(&self, layout: Layout) -> Option<usize> {
+        (self.redzone != 0).then_some(layout.size())
+    }
+}
+
+fn non_null(ptr: *mut u8) -> Option<usize> {
+    if ptr.is_null() {
+        None
+    } else {
+        Some(ptr as usize)
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for TrackingAllocator<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let Some(inner_layout) = self.inner_layout(layout) else {
+            return core::ptr::null_mut();
+        };
+        let allocate = || self.arm(self.inner.alloc(inner_layout), layout);
+
+        let Some(handler) = self.handler() else {
+            return allocate();
+        };
+
+        let base = heap_base!(self.heap_handle());
+
+        handle_validated_detour(
+            || !handler.inject_failure(base, layout.size(), None),
+            allocate,
+            |base_address| {
+                handler.on_allocation(Allocation {
+                    base,
+                    size: layout.size(),
+                    allocated_base_address: non_null(base_address),
+                    redzone: self.redzone,
+                });
+            },
+        )
+        .unwrap_or(core::ptr::null_mut())
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let Some(inner_layout) = self.inner_layout(layout) else {
+            return core::ptr::null_mut();
+        };
+        let allocate = || self.arm(self.inner.alloc_zeroed(inner_layout), layout);
+
+        let Some(handler) = self.handler() else {
+            return allocate();
+        };
+
+        let base = heap_base!(self.heap_handle());
+
+        handle_validated_detour(
+            || !handler.inject_failure(base, layout.size(), None),
+            allocate,
+            |base_address| {
+                handler.on_allocation(Allocation {
+                    base,
+                    size: layout.size(),
+                    allocated_base_address: non_null(base_address),
+                    redzone: self.redzone,
+                });
+            },
+        )
+        .unwrap_or(core::ptr::null_mut())
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        let (Some(inner_layout), Some(new_inner_layout)) =
+            (self.inner_layout(layout), self.inner_layout(new_layout))
+        else {
+            return core::ptr::null_mut();
+        };
+        let reallocate = || {
+            let block = self.unarm(ptr, layout);
+            let block = self
+                .inner
+                .realloc(block, inner_layout, new_inner_layout.size());
+            self.arm(block, new_layout)
+        };
+
+        let Some(handler) = self.handler() else {
+            return reallocate();
+        };
+
+        let base = heap_base!(self.heap_handle());
+
+        // The block is left untouched when the reallocation fails.
+        handle_validated_detour(
+            || {
+                if handler.inject_failure(base, new_size, Some(ptr as usize)) {
+                    return false;
+                }
+
+                report_damage(
+                    handler,
+                    base,
+                    ptr as usize,
+                    self.armed_size(layout),
+                    self.redzone,
+                );
+                true
+            },
+            reallocate,
+            |base_address| {
+                handler.on_reallocation(Reallocation {
+                    base_address: ptr as usize,
+                    allocation: Allocation {
+                        base,
+                        size: new_size,
+                        allocated_base_address: non_null(base_address),
+                        redzone: self.redzone,
+                    },
+                });
+            },
+        )
+        .unwrap_or(core::ptr::null_mut())
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let Some(inner_layout) = self.inner_layout(layout) else {
+            return;
+        };
+        let deallocate = || self.inner.dealloc(self.unarm(ptr, layout), inner_layout);
+
+        let Some(handler) = self.handler() else {
+            return deallocate();
+        };
+
+        let base = heap_base!(self.heap_handle());
+
+        handle_validated_detour(
+            || {
+                report_damage(
+                    handler,
+                    base,
+                    ptr as usize,
+                    self.armed_size(layout),
+                    self.redzone,
+                );
+                true
+            },
+            deallocate,
+            |_| {
+                handler.on_deallocation(Deallocation {
+                    base,
+                    base_address: ptr as usize,
+                    success: true,
+                    release: None,
+                });
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::alloc::System;
+
+    use static_cell::make_static;
+
+    use super::*;
+    use crate::{
+        errors::HeapErrorKind, BtreeMapStorage, Configuration, State, StorageAllocationHandler,
+    };
+
+    #[test]
+    fn reports_allocations_and_damaged_canaries() {
+        let state = State::new(Configuration::default(), Box::new(BtreeMapStorage::new()));
+        let state: &State = Box::leak(Box::new(state));
+        let handler = make_static!(StorageAllocationHandler::new(state));
+        let allocator = TrackingAllocator::with_handler(System, 16, handler);
+
+        let stored = |ptr: *mut u8| {
+            state
+                .lock_storage()
+                .find(ptr as usize)
+                .map(|x| (x.size, x.heap_handle, x.redzone))
+        };
+        let heap_handle = &allocator as *const _ as usize;
+
+        let layout = Layout::from_size_align(0x20, 8).unwrap();
+        let ptr = unsafe { allocator.alloc(layout) };
+        assert_eq!(stored(ptr), Some((0x20, heap_handle, 16)));
+
+        let ptr = unsafe { allocator.realloc(ptr, layout, 0x30) };
+        let layout = Layout::from_size_align(0x30, 8).unwrap();
+        assert_eq!(stored(ptr), Some((0x30, heap_handle, 16)));
+
+        // Overflow by one byte into the back canary.
+        unsafe { *ptr.add(layout.size()) ^= 0xFF };
+        let damage = redzone::check(ptr as usize, layout.size(), 16).map(|x| x.offset);
+        assert_eq!(damage, Some(layout.size() as isize));
+
+        let cursor = state.lock_errors().cursor();
+        unsafe { allocator.dealloc(ptr, layout) };
+        assert_eq!(stored(ptr), None);
+
+        let mut errors = Vec::new();
+        state.lock_errors().read(cursor, usize::MAX, &mut errors);
+        let errors: Vec<_> = errors.iter().map(|x| (x.kind, x.address)).collect();
+        assert_eq!(errors, [(HeapErrorKind::Corruption, ptr as usize)]);
+    }
+}
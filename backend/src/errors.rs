@@ -0,0 +1,85 @@
+use std::sync::Arc;
+
+use common::proto;
+
+use crate::{
+    redzone::Damage,
+    ring::CursorRing,
+    storage::{Address, Allocation, BackTrace, FreedAllocation, HeapHandle, StackTrace},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeapErrorKind {
+    // Free of an address no tracked allocation starts at.
+    InvalidFree,
+    // Free of a block already freed and not allocated since.
+    DoubleFree,
+    // Damaged canaries of a block being freed or reallocated.
+    Corruption,
+    // Modified poison of a block leaving the quarantine.
+    WriteAfterFree,
+    // Allocation failed on purpose by a fault rule.
+    InjectedFailure,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct InjectedFailure {
+    pub size: usize,
+    // Index of the first rule failing the allocation.
+    pub rule: usize,
+}
+
+impl From<&InjectedFailure> for proto::InjectedFailure {
+    fn from(value: &InjectedFailure) -> Self {
+        Self {
+            size: value.size as u64,
+            rule: value.rule as u32,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HeapError {
+    pub kind: HeapErrorKind,
+    pub address: Address,
+    pub heap_handle: HeapHandle,
+    pub stack_trace: Option<StackTrace>,
+    pub back_trace: Option<Arc<BackTrace>>,
+    pub thread_id: u64,
+    pub timestamp: u64,
+    // Live allocation containing the address of an invalid free, or the damaged one.
+    pub allocation: Option<Allocation>,
+    // Most recent free of the block of a double free or of a write after free,
+    // along with its allocation.
+    pub previous_free: Option<FreedAllocation>,
+    pub damage: Option<Damage>,
+    pub injected: Option<InjectedFailure>,
+}
+
+impl From<&HeapError> for proto::HeapError {
+    fn from(value: &HeapError) -> Self {
+        Self {
+            kind: match value.kind {
+                HeapErrorKind::InvalidFree => proto::HeapErrorKind::InvalidFree,
+                HeapErrorKind::DoubleFree => proto::HeapErrorKind::DoubleFree,
+                HeapErrorKind::Corruption => proto::HeapErrorKind::Corruption,
+                HeapErrorKind::WriteAfterFree => proto::HeapErrorKind::WriteAfterFree,
+                HeapErrorKind::InjectedFailure => proto::HeapErrorKind::InjectedFailure,
+            } as i32,
+            address: value.address as u64,
+            heap_handle: value.heap_handle as u64,
+            stack_trace: value.stack_trace.as_ref().map(|x| x.into()),
+            back_trace: value.back_trace.as_deref().map(|x| x.into()),
+            stack_id: value.back_trace.as_ref().map_or(0, |x| x.id),
+            thread_id: value.thread_id,
+            timestamp: value.timestamp,
+            allocation: value.allocation.as_ref().map(|x| x.into()),
+            previous_free: value.previous_free.as_ref().map(|x| x.into()),
+            damage: value.damage.as_ref().map(|x| x.into()),
+            injected: value.injected.as_ref().map(|x| x.into()),
+        }
+    }
+}
+
+/// Bounded log of the heap errors, the oldest ones are evicted first.
+pub type ErrorLog = CursorRing<HeapError>;
@@ -0,0 +1,48 @@
+use common::proto;
+
+use crate::{
+    ring::CursorRing,
+    storage::{Address, HeapHandle},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    Allocation,
+    Reallocation,
+    Deallocation,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Event {
+    pub kind: EventKind,
+    pub base_address: Address,
+    // Address of the block before the reallocation.
+    pub previous_address: Option<Address>,
+    pub size: usize,
+    pub heap_handle: HeapHandle,
+    pub sequence: u64,
+    pub timestamp: u64,
+    pub thread_id: u64,
+}
+
+impl From<&Event> for proto::Event {
+    fn from(value: &Event) -> Self {
+        Self {
+            kind: match value.kind {
+                EventKind::Allocation => proto::EventKind::Allocation,
+                EventKind::Reallocation => proto::EventKind::Reallocation,
+                EventKind::Deallocation => proto::EventKind::Deallocation,
+            } as i32,
+            base_address: value.base_address as u64,
+            previous_address: value.previous_address.map(|x| x as u64),
+            size: value.size as u64,
+            heap_handle: value.heap_handle as u64,
+            sequence: value.sequence,
+            timestamp: value.timestamp,
+            thread_id: value.thread_id,
+        }
+    }
+}
+
+/// Ring of the most recent events shared by all the subscribers.
+pub type EventRing = CursorRing<Event>;
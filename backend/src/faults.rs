@@ -0,0 +1,215 @@
+// Allocation failures injected on demand to exercise the out of memory paths of the target.
+
+use std::ops::Range;
+
+use crate::storage::{Address, BackTrace};
+
+pub enum FaultCondition {
+    // Every nth allocation, starting with the nth one.
+    Every(u64),
+    Probability(f64),
+    LargerThan(usize),
+    // Allocations with a frame of their back trace in one of the functions, resolved to
+    // address ranges beforehand so that the hooks do not resolve symbols. Merged and
+    // in address order, see `symbols::find_functions`.
+    Symbol(Vec<Range<Address>>),
+    // Allocations which would bring the total size of the ones let through over the budget.
+    // Reallocations count by their growth, shrinking ones not at all.
+    Budget(usize),
+}
+
+struct FaultRule {
+    condition: FaultCondition,
+    // Allocations seen by the rule.
+    seen: u64,
+    // Total size of the allocations let through since the rule has been set.
+    allocated: usize,
+}
+
+impl FaultRule {
+    fn matches(
+        &mut self,
+        size: usize,
+        growth: usize,
+        random: &mut Random,
+        back_trace: Option<&BackTrace>,
+    ) -> bool {
+        self.seen += 1;
+
+        match &self.condition {
+            FaultCondition::Every(n) => self.seen.is_multiple_of(*n),
+            FaultCondition::Probability(p) => random.next() < *p,
+            FaultCondition::LargerThan(x) => size > *x,
+            FaultCondition::Budget(x) => self.allocated.saturating_add(growth) > *x,
+            FaultCondition::Symbol(ranges) => back_trace.is_some_and(|x| {
+                x.frames.iter().any(|frame| {
+                    let index = ranges.partition_point(|x| x.start <= frame.instruction_pointer);
+                    index != 0 && ranges[index - 1].contains(&frame.instruction_pointer)
+                })
+            }),
+        }
+    }
+}
+
+// xorshift64*, good enough to pick the allocations to fail.
+struct Random(u64);
+
+impl Random {
+    // In [0, 1).
+    fn next(&mut self) -> f64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        (self.0.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// Rules failing the allocations, the first matching one is reported.
+///
+/// Every rule sees every allocation, so that the counting rules are not skewed by the others.
+pub struct Faults {
+    rules: Vec<FaultRule>,
+    random: Random,
+}
+
+impl Faults {
+    pub const fn new() -> Self {
+        Self {
+            rules: Vec::new(),
+            random: Random(1),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    pub fn matches_symbols(&self) -> bool {
+        self.rules
+            .iter()
+            .any(|x| matches!(x.condition, FaultCondition::Symbol(_)))
+    }
+
+    // The seed must not be zero.
+    pub fn set(&mut self, conditions: Vec<FaultCondition>, seed: u64) {
+        self.rules = conditions
+            .into_iter()
+            .map(|condition| FaultRule {
+                condition,
+                seen: 0,
+                allocated: 0,
+            })
+            .collect();
+        self.random = Random(seed);
+    }
+
+    /// Index of the rule failing the allocation. The growth is the size for the allocations,
+    /// and the size added to the block for the reallocations. The back trace is needed only
+    /// to match the symbols.
+    pub fn check(
+        &mut self,
+        size: usize,
+        growth: usize,
+        back_trace: Option<&BackTrace>,
+    ) -> Option<usize> {
+        let mut failing = None;
+
+        for (index, rule) in self.rules.iter_mut().enumerate() {
+            if rule.matches(size, growth, &mut self.random, back_trace) {
+                failing.get_or_insert(index);
+            }
+        }
+
+        if failing.is_none() {
+            for rule in self.rules.iter_mut() {
+                rule.allocated = rule.allocated.saturating_add(growth);
+            }
+        }
+
+        failing
+    }
+}
+
+impl Default for Faults {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::BackTraceFrame;
+
+    use super::*;
+
+    fn back_trace(instruction_pointers: &[usize]) -> BackTrace {
+        BackTrace {
+            id: 0,
+            frames: instruction_pointers
+                .iter()
+                .map(|&instruction_pointer| BackTraceFrame {
+                    instruction_pointer,
+                    stack_pointer: 0,
+                    module_base: None,
+                    resolved_symbols: Vec::new(),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn symbol_matches_frames_in_ranges() {
+        let mut faults = Faults::new();
+        faults.set(
+            vec![FaultCondition::Symbol(vec![0x1000..0x1100, 0x2000..0x2010])],
+            1,
+        );
+
+        assert_eq!(
+            faults.check(8, 8, Some(&back_trace(&[0x500, 0x1080]))),
+            Some(0)
+        );
+        assert_eq!(faults.check(8, 8, Some(&back_trace(&[0x200F]))), Some(0));
+        assert_eq!(
+            faults.check(8, 8, Some(&back_trace(&[0x1100, 0x2010]))),
+            None
+        );
+        assert_eq!(faults.check(8, 8, Some(&back_trace(&[0xFFF]))), None);
+        assert_eq!(faults.check(8, 8, None), None);
+    }
+
+    #[test]
+    fn symbol_without_ranges_never_matches() {
+        let mut faults = Faults::new();
+        faults.set(vec![FaultCondition::Symbol(Vec::new())], 1);
+
+        assert!(faults.matches_symbols());
+        assert_eq!(faults.check(8, 8, Some(&back_trace(&[0x1000]))), None);
+    }
+
+    #[test]
+    fn first_matching_rule_is_reported() {
+        let mut faults = Faults::new();
+        faults.set(
+            vec![FaultCondition::Every(2), FaultCondition::LargerThan(0x10)],
+            1,
+        );
+
+        assert_eq!(faults.check(0x20, 0x20, None), Some(1));
+        assert_eq!(faults.check(0x20, 0x20, None), Some(0));
+        assert_eq!(faults.check(8, 8, None), None);
+    }
+
+    #[test]
+    fn budget_counts_reallocations_by_growth() {
+        let mut faults = Faults::new();
+        faults.set(vec![FaultCondition::Budget(0x100)], 1);
+
+        assert_eq!(faults.check(0xC0, 0xC0, None), None);
+        // Grown by 0x20 and shrunk.
+        assert_eq!(faults.check(0xE0, 0x20, None), None);
+        assert_eq!(faults.check(0x10, 0, None), None);
+        assert_eq!(faults.check(0x40, 0x30, None), Some(0));
+        assert_eq!(faults.check(0x20, 0x20, None), None);
+    }
+}
@@ -1,7 +1,13 @@
+use std::sync::Arc;
+
 use crate::{
-    detour::{self, Base},
+    detour::{self, Base, Release},
+    errors::{HeapError, HeapErrorKind, InjectedFailure},
+    events::{Event, EventKind},
+    platform,
+    quarantine::Quarantined,
     state::StateRef,
-    storage::{Allocation, BackTrace, BackTraceFrame, BackTraceSymbol, StackTrace},
+    storage::{Allocation, BackTrace, BackTraceFrame, FreedAllocation, StackTrace},
     Configuration,
 };
 
@@ -13,43 +19,150 @@ impl StorageAllocationHandler {
     pub const fn new(state: StateRef) -> Self {
         Self { state }
     }
+
+    // The block is held in the quarantine if its release is left to the handler.
+    fn store_freed(
+        &self,
+        allocation: Allocation,
+        base: &Base,
+        configuration: &Configuration,
+        release: Option<Release>,
+    ) {
+        // Blocks larger than the whole quarantine are released right away, as are all of them
+        // while the quarantine is disabled.
+        let release = match release {
+            Some(release)
+                if configuration.quarantine_size == 0
+                    || allocation.size > configuration.quarantine_size =>
+            {
+                unsafe { release.release() };
+                let evicted = self.state.lock_quarantine().evict();
+                self.release_quarantined(evicted, base, configuration);
+                None
+            }
+            release => release,
+        };
+
+        if configuration.freed_history_size == 0 && release.is_none() {
+            return;
+        }
+
+        let (stack_trace, back_trace) =
+            creeate_stack_and_back_trace(self.state, base, configuration);
+
+        let freed = FreedAllocation {
+            allocation,
+            stack_trace,
+            back_trace,
+            thread_id: platform::current_thread_id(),
+            timestamp: self.state.timestamp(),
+        };
+
+        if let Some(release) = release {
+            // SAFETY: the block is released once it leaves the quarantine.
+            let quarantined =
+                unsafe { Quarantined::new(freed.clone(), configuration.poison_byte, release) };
+            let evicted = self.state.lock_quarantine().push(quarantined);
+            self.release_quarantined(evicted, base, configuration);
+        }
+
+        if configuration.freed_history_size != 0 {
+            self.state.lock_freed_storage().store(freed);
+        }
+    }
+
+    // Writes to the blocks leaving the quarantine are reported with the traces of the free
+    // evicting them.
+    fn release_quarantined(
+        &self,
+        blocks: Vec<Quarantined>,
+        base: &Base,
+        configuration: &Configuration,
+    ) {
+        for block in blocks {
+            if let Some(damage) = block.check() {
+                let (stack_trace, back_trace) =
+                    creeate_stack_and_back_trace(self.state, base, configuration);
+
+                self.state.record_error(HeapError {
+                    kind: HeapErrorKind::WriteAfterFree,
+                    address: block.freed.allocation.base_address,
+                    heap_handle: block.freed.allocation.heap_handle,
+                    stack_trace,
+                    back_trace,
+                    thread_id: platform::current_thread_id(),
+                    timestamp: self.state.timestamp(),
+                    allocation: None,
+                    previous_free: Some(block.freed.clone()),
+                    damage: Some(damage),
+                    injected: None,
+                });
+            }
+
+            block.release();
+        }
+    }
+
+    // The previous free is known only while the block is in the freed history or in the quarantine.
+    // The blocks freed before tracking has been enabled may have been reused since.
+    fn report_invalid_free(&self, base: &Base, address: usize, configuration: &Configuration) {
+        let (stack_trace, back_trace) =
+            creeate_stack_and_back_trace(self.state, base, configuration);
+
+        let previous_free = self
+            .state
+            .lock_freed_storage()
+            .find(address)
+            .next()
+            .filter(|x| x.timestamp >= self.state.enabled_at())
+            .cloned()
+            .or_else(|| {
+                let quarantine = self.state.lock_quarantine();
+                quarantine.find(address).map(|x| x.freed.clone())
+            });
+        let allocation = match previous_free {
+            Some(_) => None,
+            None => self.state.lock_storage().find_containing(address).cloned(),
+        };
+
+        self.state.record_error(HeapError {
+            kind: match previous_free {
+                Some(_) => HeapErrorKind::DoubleFree,
+                None => HeapErrorKind::InvalidFree,
+            },
+            address,
+            heap_handle: base.heap_handle,
+            stack_trace,
+            back_trace,
+            thread_id: platform::current_thread_id(),
+            timestamp: self.state.timestamp(),
+            allocation,
+            previous_free,
+            damage: None,
+            injected: None,
+        });
+    }
 }
 
-fn create_back_trace(skip: usize, count: usize, resolve_symbols_count: usize) -> Option<BackTrace> {
+// Only the instruction pointers are captured, the symbols are resolved on demand.
+fn create_back_trace(state: StateRef, skip: usize, count: usize) -> Option<Arc<BackTrace>> {
     if count == 0 {
         return None;
     }
 
     let mut bt = BackTrace {
+        id: 0,
         frames: Vec::with_capacity(20),
     };
     let mut cnt = 0;
 
     backtrace::trace(|frame| {
         if cnt >= skip {
-            // Resolve symbols
-            let resolved_symbols = if resolve_symbols_count > 0 {
-                let mut s = Vec::with_capacity(3);
-
-                backtrace::resolve_frame(frame, |symbol| {
-                    if s.len() < resolve_symbols_count {
-                        s.push(BackTraceSymbol {
-                            name: symbol.name().and_then(|x| x.as_str().map(|y| y.to_owned())),
-                            address: symbol.addr().map(|x| x as usize),
-                        });
-                    }
-                });
-
-                s
-            } else {
-                Vec::new()
-            };
-
             bt.frames.push(BackTraceFrame {
                 instruction_pointer: frame.ip() as usize,
                 stack_pointer: frame.sp() as usize,
                 module_base: frame.module_base_address().map(|x| x as usize),
-                resolved_symbols,
+                resolved_symbols: Vec::new(),
             });
         }
 
@@ -58,7 +171,7 @@ fn create_back_trace(skip: usize, count: usize, resolve_symbols_count: usize) ->
         bt.frames.len() < count
     });
 
-    Some(bt)
+    Some(state.lock_stacks().intern(bt))
 }
 
 fn create_stack_trace(address: usize, size: usize, offset: usize) -> Option<StackTrace> {
@@ -78,9 +191,10 @@ fn create_stack_trace(address: usize, size: usize, offset: usize) -> Option<Stac
 }
 
 fn creeate_stack_and_back_trace(
+    state: StateRef,
     base: &Base,
     configuration: &Configuration,
-) -> (Option<StackTrace>, Option<BackTrace>) {
+) -> (Option<StackTrace>, Option<Arc<BackTrace>>) {
     let stack_trace = {
         let stack_base = base.address_of_return_address.or(base.stack_frame_address);
 
@@ -89,8 +203,8 @@ fn creeate_stack_and_back_trace(
 
             create_stack_trace(
                 stack_base,
-                configuration.stack_trace_size as usize,
-                configuration.stack_trace_offset as usize,
+                configuration.stack_trace_size,
+                configuration.stack_trace_offset,
             )
         } else {
             None
@@ -98,9 +212,9 @@ fn creeate_stack_and_back_trace(
     };
 
     let back_trace = create_back_trace(
+        state,
         configuration.backtrace_frames_skip as usize,
         configuration.backtrace_frames_count as usize,
-        configuration.backtrace_resolve_symbols_count as usize,
     );
 
     (stack_trace, back_trace)
@@ -111,7 +225,25 @@ impl detour::AllocationHandler for StorageAllocationHandler {
         if let Some(base_address) = allocation.allocated_base_address {
             let configuration = self.state.get_configuration();
             let (stack_trace, back_trace) =
-                creeate_stack_and_back_trace(&allocation.base, &configuration);
+                creeate_stack_and_back_trace(self.state, &allocation.base, &configuration);
+
+            let sequence = self.state.next_sequence();
+            let timestamp = self.state.timestamp();
+            let thread_id = platform::current_thread_id();
+
+            self.state.record_event(
+                Event {
+                    kind: EventKind::Allocation,
+                    base_address,
+                    previous_address: None,
+                    size: allocation.size,
+                    heap_handle: allocation.base.heap_handle,
+                    sequence,
+                    timestamp,
+                    thread_id,
+                },
+                back_trace.as_deref(),
+            );
 
             self.state.lock_storage().store(Allocation {
                 base_address,
@@ -119,6 +251,10 @@ impl detour::AllocationHandler for StorageAllocationHandler {
                 heap_handle: allocation.base.heap_handle,
                 stack_trace,
                 back_trace,
+                sequence,
+                timestamp,
+                thread_id,
+                redzone: allocation.redzone,
             });
 
             {
@@ -132,19 +268,64 @@ impl detour::AllocationHandler for StorageAllocationHandler {
     fn on_reallocation(&self, reallocation: detour::Reallocation) {
         if let Some(base_address) = reallocation.allocation.allocated_base_address {
             let configuration = self.state.get_configuration();
-            let (stack_trace, back_trace) =
-                creeate_stack_and_back_trace(&reallocation.allocation.base, &configuration);
+            let (stack_trace, back_trace) = creeate_stack_and_back_trace(
+                self.state,
+                &reallocation.allocation.base,
+                &configuration,
+            );
 
-            {
+            let thread_id = platform::current_thread_id();
+
+            let previous = {
                 let mut storage = self.state.lock_storage();
-                storage.remove(reallocation.base_address).ok();
+                let previous = storage.remove(reallocation.base_address).ok();
+
+                // A block resized in place keeps its identity.
+                let (sequence, timestamp) = match previous.as_ref() {
+                    Some(previous) if previous.base_address == base_address => {
+                        (previous.sequence, previous.timestamp)
+                    }
+                    _ => (self.state.next_sequence(), self.state.timestamp()),
+                };
+
+                self.state.record_event(
+                    Event {
+                        kind: EventKind::Reallocation,
+                        base_address,
+                        previous_address: Some(reallocation.base_address),
+                        size: reallocation.allocation.size,
+                        heap_handle: reallocation.allocation.base.heap_handle,
+                        sequence,
+                        timestamp,
+                        thread_id,
+                    },
+                    back_trace.as_deref(),
+                );
+
                 storage.store(Allocation {
                     base_address,
                     size: reallocation.allocation.size,
                     heap_handle: reallocation.allocation.base.heap_handle,
                     stack_trace,
                     back_trace,
+                    sequence,
+                    timestamp,
+                    thread_id,
+                    redzone: reallocation.allocation.redzone,
                 });
+                previous
+            };
+
+            // The block has been moved, so the old address is not valid anymore.
+            if let Some(previous) = previous {
+                if previous.base_address != base_address {
+                    self.store_freed(
+                        previous,
+                        &reallocation.allocation.base,
+                        &configuration,
+                        None,
+                    );
+                }
             }
 
             {
@@ -156,21 +337,450 @@ impl detour::AllocationHandler for StorageAllocationHandler {
     }
 
     fn on_deallocation(&self, deallocation: crate::detour::Deallocation) {
-        if deallocation.success {
-            let removed = self
+        if !deallocation.success {
+            // Rejected by the heap, nothing has been freed.
+            if self
                 .state
                 .lock_storage()
-                .remove(deallocation.base_address)
-                .is_ok();
+                .find(deallocation.base_address)
+                .is_none()
+            {
+                let configuration = self.state.get_configuration();
+                self.report_invalid_free(
+                    &deallocation.base,
+                    deallocation.base_address,
+                    &configuration,
+                );
+            }
+        } else {
+            let removed = self.state.lock_storage().remove(deallocation.base_address);
+            let non_allocated = removed.is_err();
+
+            self.state.record_event(
+                Event {
+                    kind: EventKind::Deallocation,
+                    base_address: deallocation.base_address,
+                    previous_address: None,
+                    size: removed.as_ref().map_or(0, |x| x.size),
+                    heap_handle: deallocation.base.heap_handle,
+                    sequence: removed.as_ref().map_or(0, |x| x.sequence),
+                    timestamp: self.state.timestamp(),
+                    thread_id: platform::current_thread_id(),
+                },
+                None,
+            );
+
+            // Unknown blocks accepted by the heap were allocated while not tracking.
+            match removed {
+                Ok(allocation) => {
+                    let configuration = self.state.get_configuration();
+                    self.store_freed(
+                        allocation,
+                        &deallocation.base,
+                        &configuration,
+                        deallocation.release,
+                    );
+                }
+                Err(_) => {
+                    if let Some(release) = deallocation.release {
+                        unsafe { release.release() };
+                    }
+                }
+            }
 
             {
                 // Update statistics
                 let mut stats = self.state.lock_statistics();
                 stats.total_deallocations += 1;
-                if !removed {
+                if non_allocated {
                     stats.total_deallocations_non_allocated += 1;
                 }
             }
         }
     }
+
+    fn on_corruption(&self, corruption: detour::Corruption) {
+        let configuration = self.state.get_configuration();
+        let (stack_trace, back_trace) =
+            creeate_stack_and_back_trace(self.state, &corruption.base, &configuration);
+
+        let allocation = self
+            .state
+            .lock_storage()
+            .find(corruption.base_address)
+            .cloned();
+
+        self.state.record_error(HeapError {
+            kind: HeapErrorKind::Corruption,
+            address: corruption.base_address,
+            heap_handle: corruption.base.heap_handle,
+            stack_trace,
+            back_trace,
+            thread_id: platform::current_thread_id(),
+            timestamp: self.state.timestamp(),
+            allocation,
+            previous_free: None,
+            damage: Some(corruption.damage),
+            injected: None,
+        });
+    }
+
+    fn inject_failure(&self, base: Base, size: usize, reallocated: Option<usize>) -> bool {
+        if !self.state.is_injecting_faults() {
+            return false;
+        }
+
+        // Reallocations count toward the budgets by their growth only.
+        let growth = match reallocated {
+            Some(base_address) => {
+                let storage = self.state.lock_storage();
+                size.saturating_sub(storage.find(base_address).map_or(0, |x| x.size))
+            }
+            None => size,
+        };
+
+        let configuration = self.state.get_configuration();
+        // Captured here for the frames to be skipped as for the other traces, and without
+        // the faults locked as unwinding is slow. The check runs under the same lock as the
+        // last look at the rules, in case they are replaced while capturing.
+        let mut traces = None;
+        let rule = loop {
+            let mut faults = self.state.lock_faults();
+            if traces.is_none() && faults.matches_symbols() {
+                drop(faults);
+                traces = Some(creeate_stack_and_back_trace(
+                    self.state,
+                    &base,
+                    &configuration,
+                ));
+                continue;
+            }
+
+            break faults.check(size, growth, traces.as_ref().and_then(|x| x.1.as_deref()));
+        };
+
+        let Some(rule) = rule else {
+            return false;
+        };
+
+        let (stack_trace, back_trace) = traces
+            .unwrap_or_else(|| creeate_stack_and_back_trace(self.state, &base, &configuration));
+
+        self.state.record_error(HeapError {
+            kind: HeapErrorKind::InjectedFailure,
+            address: 0,
+            heap_handle: base.heap_handle,
+            stack_trace,
+            back_trace,
+            thread_id: platform::current_thread_id(),
+            timestamp: self.state.timestamp(),
+            allocation: None,
+            previous_free: None,
+            damage: None,
+            injected: Some(InjectedFailure { size, rule }),
+        });
+
+        {
+            // Update statistics
+            let mut stats = self.state.lock_statistics();
+            stats.total_injected_failures += 1;
+        }
+
+        true
+    }
+
+    fn armed_size(&self, base_address: usize, redzone: usize) -> Option<usize> {
+        self.state
+            .lock_storage()
+            .find(base_address)
+            .filter(|x| x.redzone == redzone)
+            .map(|x| x.size)
+    }
+
+    fn validate_deallocation(&self, base: Base, base_address: usize) -> bool {
+        let interior = {
+            let storage = self.state.lock_storage();
+            if storage.find(base_address).is_some() {
+                return true;
+            }
+            storage.find_containing(base_address).is_some()
+        };
+
+        // Unknown blocks may have been allocated while not tracking, only the interior pointers
+        // of the tracked ones, the blocks in the quarantine and the ones freed since tracking
+        // has been enabled are known to be invalid.
+        if !interior
+            && !self
+                .state
+                .lock_freed_storage()
+                .find(base_address)
+                .next()
+                .is_some_and(|x| x.timestamp >= self.state.enabled_at())
+            && self.state.lock_quarantine().find(base_address).is_none()
+        {
+            return true;
+        }
+
+        let configuration = self.state.get_configuration();
+        self.report_invalid_free(&base, base_address, &configuration);
+
+        {
+            // Update statistics
+            let mut stats = self.state.lock_statistics();
+            stats.total_deallocations += 1;
+            stats.total_deallocations_non_allocated += 1;
+        }
+
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use detour::{AllocationHandler, Deallocation};
+
+    use super::*;
+    use crate::{BtreeMapStorage, State};
+
+    const BASE: Base = Base {
+        heap_handle: 1,
+        return_address: None,
+        address_of_return_address: None,
+        stack_frame_address: None,
+    };
+
+    fn handler(configuration: Configuration) -> StorageAllocationHandler {
+        let state = State::new(configuration, Box::new(BtreeMapStorage::new()));
+        StorageAllocationHandler::new(Box::leak(Box::new(state)))
+    }
+
+    fn allocate(handler: &StorageAllocationHandler, base_address: usize, size: usize) {
+        handler.on_allocation(detour::Allocation {
+            base: BASE,
+            size,
+            allocated_base_address: Some(base_address),
+            redzone: 0,
+        });
+    }
+
+    fn free(handler: &StorageAllocationHandler, base_address: usize, release: Option<Release>) {
+        handler.on_deallocation(Deallocation {
+            base: BASE,
+            base_address,
+            success: true,
+            release,
+        });
+    }
+
+    fn errors(handler: &StorageAllocationHandler) -> Vec<HeapError> {
+        let mut errors = Vec::new();
+        handler.state.lock_errors().read(0, usize::MAX, &mut errors);
+        errors
+    }
+
+    #[test]
+    fn double_free_of_a_block_in_the_freed_history() {
+        let handler = handler(Configuration {
+            freed_history_size: 0x10,
+            ..Default::default()
+        });
+
+        allocate(&handler, 0x1000, 0x20);
+        free(&handler, 0x1000, None);
+        assert!(errors(&handler).is_empty());
+
+        assert!(!handler.validate_deallocation(BASE, 0x1000));
+
+        let errors = errors(&handler);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].kind, HeapErrorKind::DoubleFree);
+        assert_eq!(errors[0].address, 0x1000);
+        let previous_free = errors[0].previous_free.as_ref().map(|x| x.allocation.size);
+        assert_eq!(previous_free, Some(0x20));
+    }
+
+    // The block may have been reused while not tracking.
+    #[test]
+    fn free_of_a_block_freed_before_enabling_is_let_through() {
+        let handler = handler(Configuration {
+            freed_history_size: 0x10,
+            ..Default::default()
+        });
+
+        allocate(&handler, 0x1000, 0x20);
+        free(&handler, 0x1000, None);
+
+        std::thread::sleep(std::time::Duration::from_millis(1));
+        handler.state.mark_enabled();
+
+        assert!(handler.validate_deallocation(BASE, 0x1000));
+        assert!(errors(&handler).is_empty());
+    }
+
+    #[test]
+    fn invalid_free_of_an_interior_pointer() {
+        let handler = handler(Configuration::default());
+
+        allocate(&handler, 0x1000, 0x20);
+        assert!(!handler.validate_deallocation(BASE, 0x1008));
+
+        let errors = errors(&handler);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].kind, HeapErrorKind::InvalidFree);
+        assert_eq!(errors[0].address, 0x1008);
+        let allocation = errors[0].allocation.as_ref().map(|x| x.base_address);
+        assert_eq!(allocation, Some(0x1000));
+        assert!(handler.state.lock_storage().find(0x1000).is_some());
+    }
+
+    #[test]
+    fn free_of_an_untracked_block_is_let_through() {
+        let handler = handler(Configuration {
+            freed_history_size: 0x10,
+            ..Default::default()
+        });
+
+        assert!(handler.validate_deallocation(BASE, 0x1000));
+        free(&handler, 0x1000, None);
+        assert!(errors(&handler).is_empty());
+    }
+
+    const BLOCK_SIZE: usize = 0x20;
+
+    unsafe fn release(block: usize) {
+        drop(Box::from_raw(block as *mut [u8; BLOCK_SIZE]));
+    }
+
+    // Allocates a block released by `release`.
+    fn allocate_block(handler: &StorageAllocationHandler) -> usize {
+        let block = Box::into_raw(Box::new([0u8; BLOCK_SIZE])) as usize;
+        allocate(handler, block, BLOCK_SIZE);
+        block
+    }
+
+    #[test]
+    fn write_after_free_reported_on_quarantine_eviction() {
+        let handler = handler(Configuration {
+            freed_history_size: 0x10,
+            quarantine_size: BLOCK_SIZE,
+            poison_byte: 0xAB,
+            ..Default::default()
+        });
+
+        let first = allocate_block(&handler);
+        free(&handler, first, Some(Release::new(release, first)));
+        assert_eq!(
+            unsafe { *(first as *const [u8; BLOCK_SIZE]) },
+            [0xAB; BLOCK_SIZE]
+        );
+        assert!(handler.state.lock_quarantine().find(first).is_some());
+        assert!(handler
+            .state
+            .lock_freed_storage()
+            .find(first)
+            .next()
+            .is_some());
+
+        unsafe { *((first + 4) as *mut u8) = 0 };
+
+        // Evicts the first block.
+        let second = allocate_block(&handler);
+        free(&handler, second, Some(Release::new(release, second)));
+        assert!(handler.state.lock_quarantine().find(first).is_none());
+
+        let errors = errors(&handler);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].kind, HeapErrorKind::WriteAfterFree);
+        assert_eq!(errors[0].address, first);
+        assert_eq!(errors[0].damage.as_ref().map(|x| x.offset), Some(4));
+        let previous_free = errors[0].previous_free.as_ref().map(|x| x.allocation.size);
+        assert_eq!(previous_free, Some(BLOCK_SIZE));
+
+        let mut quarantine = handler.state.lock_quarantine();
+        quarantine.set_capacity(0);
+        quarantine
+            .evict()
+            .into_iter()
+            .for_each(Quarantined::release);
+    }
+
+    #[test]
+    fn empty_block_released_while_the_quarantine_is_disabled() {
+        static RELEASED: AtomicUsize = AtomicUsize::new(0);
+
+        unsafe fn count_release(block: usize) {
+            RELEASED.fetch_add(1, Ordering::Relaxed);
+            release(block);
+        }
+
+        let handler = handler(Configuration::default());
+
+        let block = Box::into_raw(Box::new([0u8; BLOCK_SIZE])) as usize;
+        allocate(&handler, block, 0);
+        free(&handler, block, Some(Release::new(count_release, block)));
+
+        assert_eq!(RELEASED.load(Ordering::Relaxed), 1);
+        assert!(handler.state.lock_quarantine().is_empty());
+    }
+
+    #[test]
+    fn double_free_of_a_quarantined_block() {
+        let handler = handler(Configuration {
+            quarantine_size: BLOCK_SIZE,
+            ..Default::default()
+        });
+
+        let block = allocate_block(&handler);
+        free(&handler, block, Some(Release::new(release, block)));
+        assert!(!handler.validate_deallocation(BASE, block));
+
+        let errors = errors(&handler);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].kind, HeapErrorKind::DoubleFree);
+        assert!(errors[0].previous_free.is_some());
+
+        let mut quarantine = handler.state.lock_quarantine();
+        quarantine.set_capacity(0);
+        quarantine
+            .evict()
+            .into_iter()
+            .for_each(Quarantined::release);
+    }
+
+    #[test]
+    fn armed_size_of_tracked_blocks() {
+        let handler = handler(Configuration::default());
+
+        handler.on_allocation(detour::Allocation {
+            base: BASE,
+            size: 0x20,
+            allocated_base_address: Some(0x1000),
+            redzone: 0x10,
+        });
+        allocate(&handler, 0x2000, 0x20);
+
+        assert_eq!(handler.armed_size(0x1000, 0x10), Some(0x20));
+        assert_eq!(handler.armed_size(0x1000, 0x20), None);
+        assert_eq!(handler.armed_size(0x2000, 0x10), None);
+        assert_eq!(handler.armed_size(0x3000, 0x10), None);
+    }
+
+    #[test]
+    fn invalid_free_rejected_by_the_heap() {
+        let handler = handler(Configuration::default());
+
+        handler.on_deallocation(Deallocation {
+            base: BASE,
+            base_address: 0x1000,
+            success: false,
+            release: None,
+        });
+
+        let errors = errors(&handler);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].kind, HeapErrorKind::InvalidFree);
+        assert!(errors[0].allocation.is_none());
+    }
 }
@@ -5,15 +5,26 @@
 
 mod debug;
 mod detour;
+pub mod errors;
+pub mod events;
+pub mod faults;
 mod handler;
+pub mod memory;
+pub mod modules;
 mod platform;
+pub mod quarantine;
+pub mod recorder;
+pub mod redzone;
+pub mod ring;
 mod state;
 pub mod storage;
+pub mod symbols;
 
 use static_cell::make_static;
 
-pub use detour::AllocationHandler;
+pub use detour::{tracking_allocator_used, AllocationHandler, TrackingAllocator};
 pub use handler::StorageAllocationHandler;
+pub use platform::JoinHandle;
 pub use state::{Configuration, State, StateRef, Statistics};
 pub use storage::{AllocationsStorage, BtreeMapStorage};
 
@@ -21,14 +32,17 @@ pub fn wordsize() -> u32 {
     core::mem::size_of::<usize>() as u32
 }
 
-pub fn spawn_thread<F, T>(f: F) -> std::thread::JoinHandle<T>
+pub fn spawn_thread<F, T>(f: F) -> JoinHandle<T>
 where
     F: FnOnce() -> T,
     F: Send + 'static,
     T: Send + 'static,
 {
-    std::thread::spawn(|| {
-        // Disable detour calls for this thread.
+    // The blocks allocated for the thread are released by the thread itself, untracked.
+    let _ack = detour::flag_set().acquire(detour::DetourFlag::Lock);
+
+    platform::spawn_thread(|| {
+        // Disable detour calls for this thread, before anything on it allocates.
         detour::flag_set()
             .acquire(detour::DetourFlag::Lock)
             .expect("detour lock must not be locked in new thread")
@@ -46,10 +60,14 @@ pub struct AllocationCatcher {
 pub struct Options {
     pub initial_configuration: Option<Configuration>,
     pub storage: Option<Box<dyn AllocationsStorage>>,
+    // Size of the canaries around the blocks of the malloc interposer, zero for none.
+    pub redzone: usize,
 }
 
 impl AllocationCatcher {
-    // SAFETY: Must be called only once
+    /// # Safety
+    ///
+    /// Must be called only once.
     pub unsafe fn init(options: Options) -> AllocationCatcher {
         // REQUIRED: initializes the detour lock.
         let _ack = detour::flag_set()
@@ -65,12 +83,17 @@ impl AllocationCatcher {
 
         unsafe {
             detour::initialize().expect("detour initialize failed");
+            if options.redzone != 0 {
+                detour::set_redzone(options.redzone);
+            }
         }
 
         Self { state }
     }
 
-    // SAFETY: must not be called when detour is enabled
+    /// # Safety
+    ///
+    /// Must not be called when detour is enabled.
     pub unsafe fn set_allocation_handler(
         &self,
         allocation_handler: &'static dyn AllocationHandler,
@@ -82,10 +105,17 @@ impl AllocationCatcher {
         self.state
     }
 
+    /// # Safety
+    ///
+    /// The allocation handler must be ready to handle allocations from any thread.
     pub unsafe fn enable(&self) {
+        self.state.mark_enabled();
         detour::enable().expect("detour enable failed");
     }
 
+    /// # Safety
+    ///
+    /// Must not race with `enable`.
     pub unsafe fn disable(&self) {
         detour::disable().expect("detour disable failed");
     }
@@ -0,0 +1,186 @@
+use std::{mem, ops::Range};
+
+use crate::platform;
+
+/// Copies the readable prefix of `[address, address + size)`.
+///
+/// Unmapped or protected pages end the copy instead of faulting.
+pub fn read_memory(address: usize, size: usize) -> Vec<u8> {
+    let mut buffer = vec![0; size];
+    let read = platform::read_memory(address, &mut buffer);
+    buffer.truncate(read);
+    buffer
+}
+
+/// Calls `visit` with the address and the value of every aligned word
+/// of the readable part of the range, until it returns `false`.
+pub fn scan_words(range: Range<usize>, mut visit: impl FnMut(usize, usize) -> bool) {
+    // Regions are read piecewise, so that a scan never copies a large block at once.
+    const CHUNK_SIZE: usize = 0x100000;
+    const WORD_SIZE: usize = mem::size_of::<usize>();
+
+    let mut address = range.start.next_multiple_of(WORD_SIZE);
+
+    while address < range.end {
+        let size = (range.end - address).min(CHUNK_SIZE);
+        let data = read_memory(address, size);
+
+        for (index, word) in data.chunks_exact(WORD_SIZE).enumerate() {
+            let value = usize::from_ne_bytes(word.try_into().unwrap());
+            if !visit(address + index * WORD_SIZE, value) {
+                return;
+            }
+        }
+
+        // The rest of the range is not readable.
+        if data.len() < size {
+            return;
+        }
+
+        address += size;
+    }
+}
+
+/// Stack memory of a thread other than the calling one.
+pub struct ThreadStack {
+    pub thread_id: u64,
+    pub range: Range<usize>,
+    // Registers of the thread, or only the arguments of the system call it is blocked in
+    // when they could not be captured, which may point to the heap.
+    pub registers: Vec<usize>,
+}
+
+pub struct ThreadStacks {
+    pub stacks: Vec<ThreadStack>,
+    // Threads whose stack is not known, such as the running ones blocking the capture signal.
+    pub skipped: Vec<u64>,
+}
+
+pub fn thread_stacks() -> ThreadStacks {
+    platform::thread_stacks()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        hint,
+        sync::{
+            atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+            Arc,
+        },
+        thread,
+    };
+
+    use super::*;
+
+    #[cfg(target_os = "linux")]
+    const PAGE_SIZE: usize = 0x1000;
+
+    // Three pages filled with their index, the middle one inaccessible. Unmapping it instead
+    // would let the mappings of the other tests take its place.
+    #[cfg(target_os = "linux")]
+    fn pages_with_a_hole() -> usize {
+        unsafe {
+            let pages = libc::mmap(
+                core::ptr::null_mut(),
+                3 * PAGE_SIZE,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            );
+            assert_ne!(pages, libc::MAP_FAILED);
+
+            for page in 0..3 {
+                core::ptr::write_bytes(
+                    (pages as *mut u8).add(page * PAGE_SIZE),
+                    page as u8,
+                    PAGE_SIZE,
+                );
+            }
+            libc::mprotect(
+                (pages as *mut u8).add(PAGE_SIZE) as *mut _,
+                PAGE_SIZE,
+                libc::PROT_NONE,
+            );
+
+            pages as usize
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn read_stops_at_inaccessible_pages() {
+        let pages = pages_with_a_hole();
+
+        let data = read_memory(pages + PAGE_SIZE - 0x10, 0x20);
+        assert_eq!(data, [0; 0x10]);
+
+        assert!(read_memory(pages + PAGE_SIZE, 0x10).is_empty());
+
+        let data = read_memory(pages + 2 * PAGE_SIZE, PAGE_SIZE);
+        assert_eq!(data, [2; PAGE_SIZE]);
+
+        unsafe { libc::munmap(pages as *mut _, 3 * PAGE_SIZE) };
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn scan_stops_at_inaccessible_pages() {
+        let pages = pages_with_a_hole();
+
+        let mut last = None;
+        scan_words(pages + 1..pages + 3 * PAGE_SIZE, |address, value| {
+            assert_eq!(value, 0);
+            last = Some(address);
+            true
+        });
+        // Unaligned starts are rounded up to the next word.
+        assert_eq!(last, Some(pages + PAGE_SIZE - mem::size_of::<usize>()));
+
+        unsafe { libc::munmap(pages as *mut _, 3 * PAGE_SIZE) };
+    }
+
+    #[cfg(any(target_os = "linux", windows))]
+    #[test]
+    fn read_of_local_memory() {
+        let data: Vec<u8> = (0..=255).collect();
+        assert_eq!(read_memory(data.as_ptr() as usize, data.len()), data);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn stack_of_a_running_thread_is_captured() {
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_id = Arc::new(AtomicU64::new(0));
+        let local_address = Arc::new(AtomicUsize::new(0));
+
+        let busy = thread::spawn({
+            let (stop, thread_id, local_address) =
+                (stop.clone(), thread_id.clone(), local_address.clone());
+            move || {
+                let local = 0usize;
+                local_address.store(&local as *const _ as usize, Ordering::Relaxed);
+                thread_id.store(platform::current_thread_id(), Ordering::Release);
+
+                while !stop.load(Ordering::Relaxed) {
+                    hint::black_box(&local);
+                }
+            }
+        });
+
+        while thread_id.load(Ordering::Acquire) == 0 {
+            thread::yield_now();
+        }
+
+        let stacks = thread_stacks();
+        stop.store(true, Ordering::Relaxed);
+        busy.join().unwrap();
+
+        let thread_id = thread_id.load(Ordering::Relaxed);
+        let stack = stacks.stacks.iter().find(|x| x.thread_id == thread_id);
+        let stack = stack.expect("the running thread must not be skipped");
+        assert!(stack.range.contains(&local_address.load(Ordering::Relaxed)));
+        assert!(!stack.registers.is_empty());
+    }
+}
@@ -0,0 +1,116 @@
+use std::ops::Range;
+
+use common::proto;
+
+use crate::platform;
+
+/// Executable image mapped into the process.
+#[derive(Debug, Clone)]
+pub struct Module {
+    pub name: String,
+    pub path: String,
+    pub base: usize,
+    pub size: usize,
+    pub build_id: Vec<u8>,
+    pub debug_file: Option<String>,
+    // Mapped writable segments or sections, which may hold pointers to the heap.
+    pub writable: Vec<Range<usize>>,
+}
+
+impl Module {
+    pub fn contains(&self, address: usize) -> bool {
+        (self.base..self.base.saturating_add(self.size)).contains(&address)
+    }
+}
+
+impl From<&Module> for proto::Module {
+    fn from(value: &Module) -> Self {
+        Self {
+            name: value.name.clone(),
+            path: value.path.clone(),
+            base: value.base as u64,
+            size: value.size as u64,
+            build_id: value.build_id.clone(),
+            debug_file: value.debug_file.clone(),
+        }
+    }
+}
+
+impl From<&proto::Module> for Module {
+    fn from(value: &proto::Module) -> Self {
+        Self {
+            name: value.name.clone(),
+            path: value.path.clone(),
+            base: value.base as usize,
+            size: value.size as usize,
+            build_id: value.build_id.clone(),
+            debug_file: value.debug_file.clone(),
+            writable: Vec::new(),
+        }
+    }
+}
+
+/// Modules loaded at the moment of the call, in address order.
+pub fn loaded_modules() -> Vec<Module> {
+    let mut modules = platform::loaded_modules();
+    modules.sort_by_key(|x| x.base);
+    modules
+}
+
+// `modules` must be in address order.
+pub fn find_module(modules: &[Module], address: usize) -> Option<&Module> {
+    let index = modules.partition_point(|x| x.base <= address);
+    modules[..index].last().filter(|x| x.contains(address))
+}
+
+// File name part of the path, on any platform.
+pub(crate) fn file_name(path: &str) -> String {
+    path.rsplit(['/', '\\']).next().unwrap_or(path).to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn module(base: usize, size: usize) -> Module {
+        Module {
+            name: String::new(),
+            path: String::new(),
+            base,
+            size,
+            build_id: Vec::new(),
+            debug_file: None,
+            writable: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn find_module_of_addresses() {
+        let modules = [module(0x1000, 0x1000), module(0x4000, 0x2000)];
+        let found = |address| find_module(&modules, address).map(|x| x.base);
+
+        assert_eq!(found(0xFFF), None);
+        assert_eq!(found(0x1000), Some(0x1000));
+        assert_eq!(found(0x2000), None);
+        assert_eq!(found(0x5FFF), Some(0x4000));
+        assert_eq!(found(0x6000), None);
+    }
+
+    #[test]
+    fn file_name_of_any_platform() {
+        assert_eq!(file_name("/usr/lib/libc.so.6"), "libc.so.6");
+        assert_eq!(file_name(r"C:\Windows\System32\ntdll.dll"), "ntdll.dll");
+        assert_eq!(file_name("a.out"), "a.out");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn loaded_modules_in_address_order() {
+        let modules = loaded_modules();
+        assert!(modules.windows(2).all(|x| x[0].base < x[1].base));
+
+        let address = loaded_modules_in_address_order as *const () as usize;
+        let module = find_module(&modules, address).unwrap();
+        assert_eq!(module.name, file_name(&module.path));
+    }
+}
@@ -1,3 +1,15 @@
+#[cfg(unix)]
+mod unix;
+#[cfg(windows)]
 mod windows;
 
-pub use windows::{debug_message_fmt, TlsKey};
+#[cfg(unix)]
+pub use unix::{
+    current_thread_id, debug_message_fmt, loaded_modules, read_memory, spawn_thread, thread_stacks,
+    JoinHandle, TlsKey,
+};
+#[cfg(windows)]
+pub use windows::{
+    current_thread_id, debug_message_fmt, loaded_modules, read_memory, spawn_thread, thread_stacks,
+    JoinHandle, TlsKey,
+};
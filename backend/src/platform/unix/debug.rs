@@ -0,0 +1,21 @@
+use std::fmt::Write;
+
+use super::current_thread_id;
+
+#[allow(dead_code)]
+pub fn debug_message_fmt(args: core::fmt::Arguments) {
+    let thread_id = current_thread_id();
+
+    let mut formatted = heapless::String::<4096>::new();
+    writeln!(formatted, "[T:{}] {}", thread_id, args)
+        .expect("not enough buffer size for formatting debug message");
+
+    // Write directly to the descriptor: std's stderr may lock and allocate.
+    unsafe {
+        libc::write(
+            libc::STDERR_FILENO,
+            formatted.as_ptr() as *const libc::c_void,
+            formatted.len(),
+        );
+    }
+}
@@ -0,0 +1,297 @@
+use crate::memory::{ThreadStack, ThreadStacks};
+
+const PAGE_SIZE: usize = 0x1000;
+
+// Reads until the first unreadable page, returns the number of bytes read.
+#[cfg(target_os = "linux")]
+pub fn read_memory(address: usize, buffer: &mut [u8]) -> usize {
+    // The kernel never splits an iovec, so one per page gives page granularity on faults.
+    const IOV_COUNT: usize = 64;
+
+    let mut read = 0;
+
+    while read < buffer.len() {
+        let mut remote: [libc::iovec; IOV_COUNT] = unsafe { core::mem::zeroed() };
+        let mut count = 0;
+        let mut offset = read;
+
+        while count < IOV_COUNT && offset < buffer.len() {
+            let current = address + offset;
+            let len = (PAGE_SIZE - current % PAGE_SIZE).min(buffer.len() - offset);
+
+            remote[count] = libc::iovec {
+                iov_base: current as *mut libc::c_void,
+                iov_len: len,
+            };
+            count += 1;
+            offset += len;
+        }
+
+        let local = libc::iovec {
+            iov_base: buffer[read..].as_mut_ptr() as *mut libc::c_void,
+            iov_len: offset - read,
+        };
+
+        let result = unsafe {
+            libc::process_vm_readv(
+                libc::getpid(),
+                &local,
+                1,
+                remote.as_ptr(),
+                count as libc::c_ulong,
+                0,
+            )
+        };
+        if result <= 0 {
+            break;
+        }
+
+        read += result as usize;
+        if read < offset {
+            break;
+        }
+    }
+
+    read
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn read_memory(_address: usize, _buffer: &mut [u8]) -> usize {
+    0
+}
+
+// Capture of the registers of a running thread, from a signal handler run by the thread itself.
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+mod capture {
+    use std::{
+        sync::{
+            atomic::{AtomicBool, AtomicI32, AtomicUsize, Ordering},
+            Mutex, Once,
+        },
+        time::{Duration, Instant},
+    };
+
+    const REGISTER_COUNT: usize = 32;
+    // The thread may block the signal, or not be scheduled for a while.
+    const TIMEOUT: Duration = Duration::from_millis(100);
+
+    // One capture at a time, the handler fills the static slot.
+    static LOCK: Mutex<()> = Mutex::new(());
+    static INSTALL: Once = Once::new();
+    static INSTALLED: AtomicBool = AtomicBool::new(false);
+    // Thread expected to run the handler, claimed by it or reset on timeout.
+    static TARGET: AtomicI32 = AtomicI32::new(0);
+    static READY: AtomicBool = AtomicBool::new(false);
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+    static REGISTERS: [AtomicUsize; REGISTER_COUNT] =
+        [const { AtomicUsize::new(0) }; REGISTER_COUNT];
+
+    fn signal() -> libc::c_int {
+        libc::SIGRTMAX() - 1
+    }
+
+    // The stack pointer comes first.
+    #[cfg(target_arch = "x86_64")]
+    unsafe fn registers(context: &libc::ucontext_t) -> impl Iterator<Item = usize> + '_ {
+        let gregs = &context.uc_mcontext.gregs;
+        let stack_pointer = gregs[libc::REG_RSP as usize] as usize;
+        core::iter::once(stack_pointer).chain(gregs.iter().map(|&x| x as usize))
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    unsafe fn registers(context: &libc::ucontext_t) -> impl Iterator<Item = usize> + '_ {
+        let mcontext = &context.uc_mcontext;
+        core::iter::once(mcontext.sp as usize).chain(mcontext.regs.iter().map(|&x| x as usize))
+    }
+
+    // Only atomics and a system call, as is safe in a signal handler.
+    extern "C" fn handler(_: libc::c_int, _: *mut libc::siginfo_t, context: *mut libc::c_void) {
+        let thread_id = unsafe { libc::gettid() };
+        if TARGET
+            .compare_exchange(thread_id, 0, Ordering::AcqRel, Ordering::Relaxed)
+            .is_err()
+        {
+            return;
+        }
+
+        let context = unsafe { &*(context as *const libc::ucontext_t) };
+        let mut count = 0;
+        for (slot, value) in REGISTERS.iter().zip(unsafe { registers(context) }) {
+            slot.store(value, Ordering::Relaxed);
+            count += 1;
+        }
+        COUNT.store(count, Ordering::Relaxed);
+        READY.store(true, Ordering::Release);
+    }
+
+    // Installed once and for good, a signal arriving after its capture timed out must not
+    // find the default action, which terminates the process. A signal already handled by
+    // the program is left to it.
+    fn install() -> bool {
+        INSTALL.call_once(|| unsafe {
+            let mut previous: libc::sigaction = core::mem::zeroed();
+            if libc::sigaction(signal(), core::ptr::null(), &mut previous) != 0
+                || previous.sa_sigaction != libc::SIG_DFL
+            {
+                return;
+            }
+
+            let mut action: libc::sigaction = core::mem::zeroed();
+            action.sa_sigaction = handler as *const () as libc::sighandler_t;
+            action.sa_flags = libc::SA_SIGINFO | libc::SA_RESTART;
+            libc::sigemptyset(&mut action.sa_mask);
+            INSTALLED.store(
+                libc::sigaction(signal(), &action, core::ptr::null_mut()) == 0,
+                Ordering::Relaxed,
+            );
+        });
+
+        INSTALLED.load(Ordering::Relaxed)
+    }
+
+    /// Registers of the thread, its stack pointer first.
+    pub fn capture(thread_id: u64) -> Option<Vec<usize>> {
+        if !install() {
+            return None;
+        }
+
+        let _guard = LOCK.lock().unwrap_or_else(|x| x.into_inner());
+        let thread_id = thread_id as libc::pid_t;
+
+        READY.store(false, Ordering::Relaxed);
+        TARGET.store(thread_id, Ordering::Release);
+
+        let result =
+            unsafe { libc::syscall(libc::SYS_tgkill, libc::getpid(), thread_id, signal()) };
+        if result != 0 {
+            TARGET.store(0, Ordering::Release);
+            return None;
+        }
+
+        let deadline = Instant::now() + TIMEOUT;
+        while !READY.load(Ordering::Acquire) {
+            // Once claimed by the handler, the registers are on their way.
+            if Instant::now() >= deadline
+                && TARGET
+                    .compare_exchange(thread_id, 0, Ordering::AcqRel, Ordering::Relaxed)
+                    .is_ok()
+            {
+                return None;
+            }
+
+            std::thread::yield_now();
+        }
+
+        let count = COUNT.load(Ordering::Relaxed);
+        Some(
+            REGISTERS[..count]
+                .iter()
+                .map(|x| x.load(Ordering::Relaxed))
+                .collect(),
+        )
+    }
+}
+
+#[cfg(all(
+    target_os = "linux",
+    not(any(target_arch = "x86_64", target_arch = "aarch64"))
+))]
+mod capture {
+    pub fn capture(_thread_id: u64) -> Option<Vec<usize>> {
+        None
+    }
+}
+
+// Live part of the stacks of the other threads. Each thread is interrupted by a signal to
+// capture all of its registers. The threads not handling it in time fall back to the state
+// of their blocking system call, and are skipped while running.
+#[cfg(target_os = "linux")]
+pub fn thread_stacks() -> ThreadStacks {
+    use std::fs;
+
+    // Below the stack pointer, still in use by leaf functions.
+    const RED_ZONE: usize = 128;
+    // Reads of the state of a running thread, which is likely to block soon.
+    const ATTEMPTS: usize = 4;
+
+    let mut stacks = ThreadStacks {
+        stacks: Vec::new(),
+        skipped: Vec::new(),
+    };
+
+    let current = super::current_thread_id();
+
+    let Ok(maps) = fs::read_to_string("/proc/self/maps") else {
+        return stacks;
+    };
+    let mappings: Vec<_> = maps
+        .lines()
+        .filter_map(|line| {
+            let (start, end) = line.split_whitespace().next()?.split_once('-')?;
+            Some(usize::from_str_radix(start, 16).ok()?..usize::from_str_radix(end, 16).ok()?)
+        })
+        .collect();
+
+    let Ok(tasks) = fs::read_dir("/proc/self/task") else {
+        return stacks;
+    };
+
+    // The system call number, or -1 if blocked outside of one, then its arguments if any,
+    // the stack pointer and the instruction pointer. Only "running" for the running threads.
+    // Returns the stack pointer first, then the arguments, the only registers known.
+    let read_syscall = |thread_id: u64| {
+        (0..ATTEMPTS).find_map(|_| {
+            let syscall =
+                fs::read_to_string(format!("/proc/self/task/{thread_id}/syscall")).ok()?;
+            if syscall.trim() == "running" {
+                std::thread::yield_now();
+                return None;
+            }
+
+            let mut values: Vec<usize> = syscall
+                .split_whitespace()
+                .skip(1)
+                .filter_map(|x| usize::from_str_radix(x.strip_prefix("0x")?, 16).ok())
+                .collect();
+            values.pop()?;
+            let stack_pointer = values.pop()?;
+            values.insert(0, stack_pointer);
+            Some(values)
+        })
+    };
+
+    let thread_ids = tasks.filter_map(|task| task.ok()?.file_name().to_str()?.parse::<u64>().ok());
+
+    for thread_id in thread_ids.filter(|&x| x != current) {
+        let stack = capture::capture(thread_id)
+            .or_else(|| read_syscall(thread_id))
+            .and_then(|registers| {
+                let stack_pointer = *registers.first()?;
+                let mapping = mappings.iter().find(|x| x.contains(&stack_pointer))?;
+
+                Some(ThreadStack {
+                    thread_id,
+                    range: stack_pointer.saturating_sub(RED_ZONE).max(mapping.start)..mapping.end,
+                    registers,
+                })
+            });
+
+        match stack {
+            Some(stack) => stacks.stacks.push(stack),
+            None => stacks.skipped.push(thread_id),
+        }
+    }
+
+    stacks
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn thread_stacks() -> ThreadStacks {
+    ThreadStacks {
+        stacks: Vec::new(),
+        skipped: Vec::new(),
+    }
+}
@@ -0,0 +1,11 @@
+mod debug;
+mod memory;
+mod modules;
+mod thread;
+mod tls;
+
+pub use debug::debug_message_fmt;
+pub use memory::{read_memory, thread_stacks};
+pub use modules::loaded_modules;
+pub use thread::{current_thread_id, spawn_thread, JoinHandle};
+pub use tls::TlsKey;
@@ -0,0 +1,185 @@
+use crate::modules::Module;
+
+#[cfg(target_os = "linux")]
+const NT_GNU_BUILD_ID: u32 = 3;
+
+// Walks the notes of a PT_NOTE segment looking for the GNU build id.
+#[cfg(target_os = "linux")]
+unsafe fn find_build_id(mut note: *const u8, end: *const u8) -> Option<Vec<u8>> {
+    let align = |x: usize| (x + 3) & !3;
+
+    while (note as usize) + 12 <= end as usize {
+        let header = note as *const u32;
+        let name_size = header.read_unaligned() as usize;
+        let desc_size = header.add(1).read_unaligned() as usize;
+        let note_type = header.add(2).read_unaligned();
+
+        let name = note.add(12);
+        let desc = name.add(align(name_size));
+        let next = desc.add(align(desc_size));
+        if next as usize > end as usize {
+            return None;
+        }
+
+        if note_type == NT_GNU_BUILD_ID && std::slice::from_raw_parts(name, name_size) == b"GNU\0" {
+            return Some(std::slice::from_raw_parts(desc, desc_size).to_vec());
+        }
+
+        note = next;
+    }
+
+    None
+}
+
+#[cfg(target_os = "linux")]
+pub fn loaded_modules() -> Vec<Module> {
+    use std::ffi::CStr;
+
+    unsafe extern "C" fn callback(
+        info: *mut libc::dl_phdr_info,
+        _size: libc::size_t,
+        data: *mut libc::c_void,
+    ) -> libc::c_int {
+        let info = &*info;
+        let modules = &mut *(data as *mut Vec<Module>);
+
+        let headers = std::slice::from_raw_parts(info.dlpi_phdr, info.dlpi_phnum as usize);
+        let bias = info.dlpi_addr as usize;
+
+        let loads = headers.iter().filter(|x| x.p_type == libc::PT_LOAD);
+        let Some(start) = loads.clone().map(|x| x.p_vaddr as usize).min() else {
+            return 0;
+        };
+        let end = loads
+            .map(|x| (x.p_vaddr + x.p_memsz) as usize)
+            .max()
+            .unwrap_or(start);
+
+        let writable = headers
+            .iter()
+            .filter(|x| x.p_type == libc::PT_LOAD && x.p_flags & libc::PF_W != 0)
+            .map(|x| {
+                let start = bias + x.p_vaddr as usize;
+                start..start + x.p_memsz as usize
+            })
+            .collect();
+
+        let build_id = headers
+            .iter()
+            .filter(|x| x.p_type == libc::PT_NOTE)
+            .find_map(|x| {
+                let note = (bias + x.p_vaddr as usize) as *const u8;
+                find_build_id(note, note.add(x.p_memsz as usize))
+            })
+            .unwrap_or_default();
+
+        // The first object is the main program, which has no name.
+        let path = if info.dlpi_name.is_null() || *info.dlpi_name == 0 {
+            if modules.is_empty() {
+                std::env::current_exe()
+                    .map(|x| x.to_string_lossy().into_owned())
+                    .unwrap_or_default()
+            } else {
+                String::new()
+            }
+        } else {
+            CStr::from_ptr(info.dlpi_name)
+                .to_string_lossy()
+                .into_owned()
+        };
+
+        modules.push(Module {
+            name: crate::modules::file_name(&path),
+            path,
+            base: bias + start,
+            size: end - start,
+            build_id,
+            debug_file: None,
+            writable,
+        });
+
+        0
+    }
+
+    let mut modules = Vec::new();
+    unsafe {
+        libc::dl_iterate_phdr(Some(callback), &mut modules as *mut Vec<Module> as *mut _);
+    }
+    modules
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn loaded_modules() -> Vec<Module> {
+    Vec::new()
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use object::Object;
+
+    use super::*;
+
+    fn note(name: &[u8], note_type: u32, desc: &[u8]) -> Vec<u8> {
+        let padded = |x: &[u8]| {
+            let mut x = x.to_vec();
+            x.resize(x.len().next_multiple_of(4), 0);
+            x
+        };
+
+        let mut note = Vec::new();
+        note.extend((name.len() as u32).to_ne_bytes());
+        note.extend((desc.len() as u32).to_ne_bytes());
+        note.extend(note_type.to_ne_bytes());
+        note.extend(padded(name));
+        note.extend(padded(desc));
+        note
+    }
+
+    fn build_id(notes: &[u8]) -> Option<Vec<u8>> {
+        let range = notes.as_ptr_range();
+        unsafe { find_build_id(range.start, range.end) }
+    }
+
+    #[test]
+    fn build_id_after_other_notes() {
+        let notes = [
+            note(b"GNU\0", 1, &[0; 16]),
+            note(b"Go\0", NT_GNU_BUILD_ID, &[1, 2, 3]),
+            note(b"GNU\0", NT_GNU_BUILD_ID, &[4, 5, 6, 7, 8]),
+        ]
+        .concat();
+
+        assert_eq!(build_id(&notes), Some(vec![4, 5, 6, 7, 8]));
+    }
+
+    #[test]
+    fn truncated_notes_have_no_build_id() {
+        let notes = note(b"GNU\0", NT_GNU_BUILD_ID, &[1; 20]);
+
+        assert_eq!(build_id(&notes[..notes.len() - 4]), None);
+        assert_eq!(build_id(&notes[..8]), None);
+        assert_eq!(build_id(&[]), None);
+    }
+
+    #[test]
+    fn executable_is_the_first_module() {
+        let modules = loaded_modules();
+        let executable = &modules[0];
+
+        let address = executable_is_the_first_module as *const () as usize;
+        assert!(executable.contains(address));
+        assert_eq!(
+            executable.path,
+            std::env::current_exe().unwrap().to_string_lossy()
+        );
+        assert!(executable
+            .writable
+            .iter()
+            .all(|x| executable.contains(x.start)));
+
+        let data = std::fs::read(&executable.path).unwrap();
+        let file = object::File::parse(&*data).unwrap();
+        let expected = file.build_id().unwrap().unwrap_or_default();
+        assert_eq!(executable.build_id, expected);
+    }
+}
@@ -0,0 +1,103 @@
+use std::{
+    panic::{self, AssertUnwindSafe},
+    sync::{Arc, Mutex},
+    thread,
+};
+
+#[cfg(target_os = "linux")]
+pub fn current_thread_id() -> u64 {
+    unsafe { libc::gettid() as u64 }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn current_thread_id() -> u64 {
+    unsafe { libc::pthread_self() as u64 }
+}
+
+// Result of the thread, until it is joined.
+type Packet<T> = Arc<Mutex<Option<thread::Result<T>>>>;
+
+pub struct JoinHandle<T> {
+    // None once joined.
+    thread: Option<libc::pthread_t>,
+    packet: Packet<T>,
+}
+
+impl<T> JoinHandle<T> {
+    pub fn join(mut self) -> thread::Result<T> {
+        if let Some(thread) = self.thread.take() {
+            unsafe { libc::pthread_join(thread, core::ptr::null_mut()) };
+        }
+
+        self.packet
+            .lock()
+            .expect("unexpected thread packet lock poison")
+            .take()
+            .expect("joined thread must have a result")
+    }
+}
+
+impl<T> Drop for JoinHandle<T> {
+    fn drop(&mut self) {
+        if let Some(thread) = self.thread {
+            unsafe { libc::pthread_detach(thread) };
+        }
+    }
+}
+
+struct Start<F, T> {
+    f: Option<F>,
+    packet: Packet<T>,
+}
+
+/// Unlike `std::thread::spawn`, `f` is the first code of the thread to run: the standard
+/// library allocates while setting up the thread, before its closure is called.
+pub fn spawn_thread<F, T>(f: F) -> JoinHandle<T>
+where
+    F: FnOnce() -> T,
+    F: Send + 'static,
+    T: Send + 'static,
+{
+    extern "C" fn start<F: FnOnce() -> T, T>(data: *mut libc::c_void) -> *mut libc::c_void {
+        // Released only once `f` has returned.
+        let mut start = unsafe { Box::from_raw(data as *mut Start<F, T>) };
+        let f = start.f.take().expect("thread must be started once");
+
+        let result = panic::catch_unwind(AssertUnwindSafe(f));
+        *start
+            .packet
+            .lock()
+            .expect("unexpected thread packet lock poison") = Some(result);
+
+        core::ptr::null_mut()
+    }
+
+    let packet: Packet<T> = Arc::new(Mutex::new(None));
+    let data = Box::into_raw(Box::new(Start {
+        f: Some(f),
+        packet: packet.clone(),
+    }));
+
+    let mut thread = 0;
+    let result = unsafe {
+        libc::pthread_create(
+            &mut thread,
+            core::ptr::null(),
+            start::<F, T>,
+            data as *mut libc::c_void,
+        )
+    };
+
+    if result != 0 {
+        drop(unsafe { Box::from_raw(data) });
+        panic!(
+            "failed to spawn thread: {}",
+            std::io::Error::from_raw_os_error(result)
+        );
+    }
+
+    JoinHandle {
+        thread: Some(thread),
+        packet,
+    }
+}
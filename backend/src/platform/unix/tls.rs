@@ -0,0 +1,87 @@
+use std::marker::PhantomData;
+
+use libc::pthread_key_t;
+
+// Values of the first keys live in a block embedded in the thread descriptor. Setting a key
+// past that block makes glibc `calloc` the next level of the table, which would recurse into
+// the allocator hooks, so such keys are rejected.
+#[cfg(target_env = "gnu")]
+const KEY_FIRST_LEVEL_SIZE: pthread_key_t = 32;
+
+pub struct TlsKey<T> {
+    key: pthread_key_t,
+    _marker: PhantomData<T>,
+}
+
+impl<T> TlsKey<T>
+where
+    T: From<usize> + Into<usize>,
+{
+    pub fn new() -> Result<Self, ()> {
+        let mut key: pthread_key_t = 0;
+        if unsafe { libc::pthread_key_create(&mut key, None) } != 0 {
+            return Err(());
+        }
+
+        let tls_key = Self {
+            key,
+            _marker: PhantomData,
+        };
+
+        #[cfg(target_env = "gnu")]
+        if tls_key.key >= KEY_FIRST_LEVEL_SIZE {
+            return Err(());
+        }
+
+        Ok(tls_key)
+    }
+
+    pub fn get(&self) -> T {
+        T::from(unsafe { libc::pthread_getspecific(self.key) as usize })
+    }
+
+    pub fn set(&self, val: T) {
+        unsafe {
+            libc::pthread_setspecific(self.key, val.into() as *const libc::c_void);
+        }
+    }
+}
+
+impl<T> Drop for TlsKey<T> {
+    fn drop(&mut self) {
+        unsafe {
+            libc::pthread_key_delete(self.key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slot_starts_at_zero_in_each_thread() {
+        let key = TlsKey::<usize>::new().unwrap();
+        key.set(42);
+        assert_eq!(key.get(), 42);
+
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                assert_eq!(key.get(), 0);
+                key.set(7);
+                assert_eq!(key.get(), 7);
+            });
+        });
+        assert_eq!(key.get(), 42);
+    }
+
+    #[test]
+    fn keys_are_independent() {
+        let first = TlsKey::<usize>::new().unwrap();
+        let second = TlsKey::<usize>::new().unwrap();
+        first.set(1);
+        second.set(2);
+        assert_eq!(first.get(), 1);
+        assert_eq!(second.get(), 2);
+    }
+}
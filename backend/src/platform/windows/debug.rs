@@ -1,10 +1,12 @@
 use std::{ffi::CStr, fmt::Write};
 
-use winapi::um::{debugapi::OutputDebugStringA, processthreadsapi::GetCurrentThreadId};
+use winapi::um::debugapi::OutputDebugStringA;
+
+use super::current_thread_id;
 
 #[allow(dead_code)]
 pub fn debug_message_fmt(args: core::fmt::Arguments) {
-    let thread_id = unsafe { GetCurrentThreadId() };
+    let thread_id = current_thread_id();
 
     let mut formatted = heapless::String::<4096>::new();
     write!(formatted, "[T:{}] {}\0", thread_id, args)
@@ -0,0 +1,148 @@
+use std::{mem, ptr};
+
+use winapi::{
+    shared::{minwindef::ULONG, ntdef::NTSTATUS},
+    um::{
+        handleapi::{CloseHandle, INVALID_HANDLE_VALUE},
+        libloaderapi::{GetModuleHandleA, GetProcAddress},
+        memoryapi::ReadProcessMemory,
+        processthreadsapi::{
+            GetCurrentProcess, GetCurrentProcessId, GetCurrentThreadId, OpenThread,
+        },
+        tlhelp32::{
+            CreateToolhelp32Snapshot, Thread32First, Thread32Next, TH32CS_SNAPTHREAD, THREADENTRY32,
+        },
+        winnt::{HANDLE, PVOID, THREAD_QUERY_INFORMATION},
+    },
+};
+
+use crate::memory::{ThreadStack, ThreadStacks};
+
+const PAGE_SIZE: usize = 0x1000;
+
+// Reads until the first unreadable page, returns the number of bytes read.
+pub fn read_memory(address: usize, buffer: &mut [u8]) -> usize {
+    let mut read = 0;
+
+    // A failed read of a range tells nothing of its readable part, so read page by page.
+    while read < buffer.len() {
+        let current = address + read;
+        let len = (PAGE_SIZE - current % PAGE_SIZE).min(buffer.len() - read);
+        let mut page_read = 0;
+
+        let ok = unsafe {
+            ReadProcessMemory(
+                GetCurrentProcess(),
+                current as *const _,
+                buffer[read..].as_mut_ptr() as *mut _,
+                len,
+                &mut page_read,
+            )
+        };
+        if ok == 0 || page_read == 0 {
+            break;
+        }
+
+        read += page_read;
+    }
+
+    read
+}
+
+#[repr(C)]
+struct ThreadBasicInformation {
+    exit_status: NTSTATUS,
+    teb_base_address: PVOID,
+    client_id: [usize; 2],
+    affinity_mask: usize,
+    priority: i32,
+    base_priority: i32,
+}
+
+type NtQueryInformationThread =
+    unsafe extern "system" fn(HANDLE, u32, PVOID, ULONG, *mut ULONG) -> NTSTATUS;
+
+// Stack limits from the TIB at the start of the TEB of the thread.
+unsafe fn thread_stack(query: NtQueryInformationThread, thread_id: u32) -> Option<ThreadStack> {
+    // THREADINFOCLASS::ThreadBasicInformation
+    const THREAD_BASIC_INFORMATION: u32 = 0;
+
+    let thread = OpenThread(THREAD_QUERY_INFORMATION, 0, thread_id);
+    if thread.is_null() {
+        return None;
+    }
+
+    let mut information: ThreadBasicInformation = mem::zeroed();
+    let status = query(
+        thread,
+        THREAD_BASIC_INFORMATION,
+        &mut information as *mut _ as PVOID,
+        mem::size_of::<ThreadBasicInformation>() as ULONG,
+        ptr::null_mut(),
+    );
+    CloseHandle(thread);
+
+    if status < 0 || information.teb_base_address.is_null() {
+        return None;
+    }
+
+    // NT_TIB: ExceptionList, StackBase, StackLimit.
+    const WORD: usize = mem::size_of::<usize>();
+    let mut limits = [0u8; 2 * WORD];
+    let tib = information.teb_base_address as usize;
+    if read_memory(tib + WORD, &mut limits) != limits.len() {
+        return None;
+    }
+
+    let word = |x: &[u8]| usize::from_ne_bytes(x.try_into().unwrap());
+    Some(ThreadStack {
+        thread_id: thread_id as u64,
+        range: word(&limits[WORD..])..word(&limits[..WORD]),
+        // Reading the context would need to suspend the thread, which may hold the heap lock.
+        registers: Vec::new(),
+    })
+}
+
+// Committed stacks of the other threads, including their unused part.
+pub fn thread_stacks() -> ThreadStacks {
+    let mut stacks = ThreadStacks {
+        stacks: Vec::new(),
+        skipped: Vec::new(),
+    };
+
+    unsafe {
+        let ntdll = GetModuleHandleA(b"ntdll.dll\0".as_ptr() as *const _);
+        let query = GetProcAddress(ntdll, b"NtQueryInformationThread\0".as_ptr() as *const _);
+        if query.is_null() {
+            return stacks;
+        }
+        let query: NtQueryInformationThread = mem::transmute(query);
+
+        let snapshot = CreateToolhelp32Snapshot(TH32CS_SNAPTHREAD, 0);
+        if snapshot == INVALID_HANDLE_VALUE {
+            return stacks;
+        }
+
+        let process_id = GetCurrentProcessId();
+        let current = GetCurrentThreadId();
+
+        let mut entry: THREADENTRY32 = mem::zeroed();
+        entry.dwSize = mem::size_of::<THREADENTRY32>() as u32;
+
+        let mut ok = Thread32First(snapshot, &mut entry);
+        while ok != 0 {
+            if entry.th32OwnerProcessID == process_id && entry.th32ThreadID != current {
+                match thread_stack(query, entry.th32ThreadID) {
+                    Some(stack) => stacks.stacks.push(stack),
+                    None => stacks.skipped.push(entry.th32ThreadID as u64),
+                }
+            }
+
+            ok = Thread32Next(snapshot, &mut entry);
+        }
+
+        CloseHandle(snapshot);
+    }
+
+    stacks
+}
@@ -0,0 +1,123 @@
+use std::{ffi::CStr, mem, slice};
+
+use winapi::um::{
+    handleapi::{CloseHandle, INVALID_HANDLE_VALUE},
+    processthreadsapi::GetCurrentProcessId,
+    tlhelp32::{
+        CreateToolhelp32Snapshot, Module32FirstW, Module32NextW, MODULEENTRY32W, TH32CS_SNAPMODULE,
+        TH32CS_SNAPMODULE32,
+    },
+    winnt::{
+        IMAGE_DEBUG_DIRECTORY, IMAGE_DEBUG_TYPE_CODEVIEW, IMAGE_DIRECTORY_ENTRY_DEBUG,
+        IMAGE_DOS_HEADER, IMAGE_NT_HEADERS, IMAGE_SCN_MEM_WRITE, IMAGE_SECTION_HEADER,
+    },
+};
+
+use std::ops::Range;
+
+use crate::modules::Module;
+
+const CODEVIEW_RSDS: u32 = u32::from_le_bytes(*b"RSDS");
+
+fn wide_to_string(wide: &[u16]) -> String {
+    let len = wide.iter().position(|&x| x == 0).unwrap_or(wide.len());
+    String::from_utf16_lossy(&wide[..len])
+}
+
+// GUID and age of the PDB from the CodeView record of the mapped image, and the PDB path.
+unsafe fn pdb_signature(base: *const u8, size: usize) -> Option<(Vec<u8>, String)> {
+    let dos_header = &*(base as *const IMAGE_DOS_HEADER);
+    let nt_headers = &*(base.add(dos_header.e_lfanew as usize) as *const IMAGE_NT_HEADERS);
+    let directory = nt_headers.OptionalHeader.DataDirectory[IMAGE_DIRECTORY_ENTRY_DEBUG as usize];
+
+    if directory.VirtualAddress == 0
+        || directory.VirtualAddress as usize + directory.Size as usize > size
+    {
+        return None;
+    }
+
+    let entries = slice::from_raw_parts(
+        base.add(directory.VirtualAddress as usize) as *const IMAGE_DEBUG_DIRECTORY,
+        directory.Size as usize / mem::size_of::<IMAGE_DEBUG_DIRECTORY>(),
+    );
+
+    entries
+        .iter()
+        .filter(|x| x.Type == IMAGE_DEBUG_TYPE_CODEVIEW && x.AddressOfRawData != 0)
+        .find_map(|x| {
+            // RSDS signature, 16 bytes of GUID, 4 bytes of age, then the NUL-terminated path.
+            let data = base.add(x.AddressOfRawData as usize);
+            if x.SizeOfData < 24 || (data as *const u32).read_unaligned() != CODEVIEW_RSDS {
+                return None;
+            }
+
+            let signature = slice::from_raw_parts(data.add(4), 20).to_vec();
+            let path = CStr::from_ptr(data.add(24) as *const _)
+                .to_string_lossy()
+                .into_owned();
+
+            Some((signature, path))
+        })
+}
+
+// Writable sections of the mapped image.
+unsafe fn writable_sections(base: *const u8) -> Vec<Range<usize>> {
+    let dos_header = &*(base as *const IMAGE_DOS_HEADER);
+    let nt_headers = base.add(dos_header.e_lfanew as usize) as *const IMAGE_NT_HEADERS;
+    let file_header = &(*nt_headers).FileHeader;
+
+    // Section headers follow the optional header.
+    let first_section = (&(*nt_headers).OptionalHeader as *const _ as *const u8)
+        .add(file_header.SizeOfOptionalHeader as usize)
+        as *const IMAGE_SECTION_HEADER;
+    let sections = slice::from_raw_parts(first_section, file_header.NumberOfSections as usize);
+
+    sections
+        .iter()
+        .filter(|x| x.Characteristics & IMAGE_SCN_MEM_WRITE != 0)
+        .map(|x| {
+            let start = base as usize + x.VirtualAddress as usize;
+            start..start + *x.Misc.VirtualSize() as usize
+        })
+        .collect()
+}
+
+pub fn loaded_modules() -> Vec<Module> {
+    let mut modules = Vec::new();
+
+    unsafe {
+        let snapshot = CreateToolhelp32Snapshot(
+            TH32CS_SNAPMODULE | TH32CS_SNAPMODULE32,
+            GetCurrentProcessId(),
+        );
+        if snapshot == INVALID_HANDLE_VALUE {
+            return modules;
+        }
+
+        let mut entry: MODULEENTRY32W = mem::zeroed();
+        entry.dwSize = mem::size_of::<MODULEENTRY32W>() as u32;
+
+        let mut ok = Module32FirstW(snapshot, &mut entry);
+        while ok != 0 {
+            let base = entry.modBaseAddr as *const u8;
+            let size = entry.modBaseSize as usize;
+            let (build_id, debug_file) = pdb_signature(base, size).unzip();
+
+            modules.push(Module {
+                name: wide_to_string(&entry.szModule),
+                path: wide_to_string(&entry.szExePath),
+                base: base as usize,
+                size,
+                build_id: build_id.unwrap_or_default(),
+                debug_file,
+                writable: writable_sections(base),
+            });
+
+            ok = Module32NextW(snapshot, &mut entry);
+        }
+
+        CloseHandle(snapshot);
+    }
+
+    modules
+}
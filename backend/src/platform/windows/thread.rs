@@ -0,0 +1,116 @@
+use std::{
+    panic::{self, AssertUnwindSafe},
+    sync::{Arc, Mutex},
+    thread,
+};
+
+use winapi::{
+    shared::minwindef::{DWORD, LPVOID},
+    um::{
+        handleapi::CloseHandle,
+        processthreadsapi::{CreateThread, GetCurrentThreadId},
+        synchapi::WaitForSingleObject,
+        winbase::INFINITE,
+        winnt::HANDLE,
+    },
+};
+
+pub fn current_thread_id() -> u64 {
+    unsafe { GetCurrentThreadId() as u64 }
+}
+
+// Result of the thread, until it is joined.
+type Packet<T> = Arc<Mutex<Option<thread::Result<T>>>>;
+
+pub struct JoinHandle<T> {
+    // None once joined.
+    thread: Option<HANDLE>,
+    packet: Packet<T>,
+}
+
+// The handle is only used to wait for the thread and closed once.
+unsafe impl<T: Send> Send for JoinHandle<T> {}
+unsafe impl<T: Send> Sync for JoinHandle<T> {}
+
+impl<T> JoinHandle<T> {
+    pub fn join(mut self) -> thread::Result<T> {
+        if let Some(thread) = self.thread.take() {
+            unsafe {
+                WaitForSingleObject(thread, INFINITE);
+                CloseHandle(thread);
+            }
+        }
+
+        self.packet
+            .lock()
+            .expect("unexpected thread packet lock poison")
+            .take()
+            .expect("joined thread must have a result")
+    }
+}
+
+impl<T> Drop for JoinHandle<T> {
+    fn drop(&mut self) {
+        if let Some(thread) = self.thread {
+            unsafe { CloseHandle(thread) };
+        }
+    }
+}
+
+struct Start<F, T> {
+    f: Option<F>,
+    packet: Packet<T>,
+}
+
+/// Unlike `std::thread::spawn`, `f` is the first code of the thread to run: the standard
+/// library allocates while setting up the thread, before its closure is called.
+pub fn spawn_thread<F, T>(f: F) -> JoinHandle<T>
+where
+    F: FnOnce() -> T,
+    F: Send + 'static,
+    T: Send + 'static,
+{
+    unsafe extern "system" fn start<F: FnOnce() -> T, T>(data: LPVOID) -> DWORD {
+        // Released only once `f` has returned.
+        let mut start = unsafe { Box::from_raw(data as *mut Start<F, T>) };
+        let f = start.f.take().expect("thread must be started once");
+
+        let result = panic::catch_unwind(AssertUnwindSafe(f));
+        *start
+            .packet
+            .lock()
+            .expect("unexpected thread packet lock poison") = Some(result);
+
+        0
+    }
+
+    let packet: Packet<T> = Arc::new(Mutex::new(None));
+    let data = Box::into_raw(Box::new(Start {
+        f: Some(f),
+        packet: packet.clone(),
+    }));
+
+    let thread = unsafe {
+        CreateThread(
+            core::ptr::null_mut(),
+            0,
+            Some(start::<F, T>),
+            data as LPVOID,
+            0,
+            core::ptr::null_mut(),
+        )
+    };
+
+    if thread.is_null() {
+        drop(unsafe { Box::from_raw(data) });
+        panic!(
+            "failed to spawn thread: {}",
+            std::io::Error::last_os_error()
+        );
+    }
+
+    JoinHandle {
+        thread: Some(thread),
+        packet,
+    }
+}
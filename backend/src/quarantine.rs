@@ -0,0 +1,247 @@
+// Freed blocks are filled with the poison byte and held here instead of being released,
+// so that the writes to them are noticed once they leave the quarantine.
+
+use std::collections::{HashSet, VecDeque};
+
+use crate::{
+    detour::Release,
+    redzone::Damage,
+    storage::{Address, FreedAllocation},
+};
+
+// Bytes of a damaged block reported around its first modified byte.
+const DAMAGE_WINDOW: usize = 0x40;
+
+pub struct Quarantined {
+    pub freed: FreedAllocation,
+    poison: u8,
+    release: Release,
+}
+
+impl Quarantined {
+    /// Fills the block with the poison byte.
+    ///
+    /// # Safety
+    ///
+    /// The block of the freed allocation must not be released before `release` is called.
+    pub unsafe fn new(freed: FreedAllocation, poison: u8, release: Release) -> Self {
+        let allocation = &freed.allocation;
+        core::ptr::write_bytes(allocation.base_address as *mut u8, poison, allocation.size);
+
+        Self {
+            freed,
+            poison,
+            release,
+        }
+    }
+
+    // Empty blocks count as a byte, so that they are evicted as well.
+    fn footprint(&self) -> usize {
+        self.freed.allocation.size.max(1)
+    }
+
+    fn block(&self) -> &[u8] {
+        let allocation = &self.freed.allocation;
+        // SAFETY: the block is held until it is released.
+        unsafe {
+            core::slice::from_raw_parts(allocation.base_address as *const u8, allocation.size)
+        }
+    }
+
+    /// First modified byte of the poison, along with the bytes around it.
+    pub fn check(&self) -> Option<Damage> {
+        let block = self.block();
+        let offset = block.iter().position(|&x| x != self.poison)?;
+        let start = offset & !0xF;
+        let end = block.len().min(start + DAMAGE_WINDOW);
+
+        Some(Damage {
+            offset: offset as isize,
+            address: self.freed.allocation.base_address + start,
+            data: block[start..end].to_vec(),
+        })
+    }
+
+    /// Returns the block to the heap.
+    pub fn release(self) {
+        // SAFETY: the block has been held since it was freed.
+        unsafe { self.release.release() }
+    }
+}
+
+/// Freed blocks held in the order they were freed, bounded by their total size.
+/// Every block counts as at least a byte.
+pub struct Quarantine {
+    blocks: VecDeque<Quarantined>,
+    addresses: HashSet<Address>,
+    size: usize,
+    capacity: usize,
+}
+
+impl Quarantine {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            blocks: VecDeque::new(),
+            addresses: HashSet::new(),
+            size: 0,
+            capacity,
+        }
+    }
+
+    // The blocks over the new capacity are left until the next eviction.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+    }
+
+    pub fn find(&self, address: Address) -> Option<&Quarantined> {
+        if !self.addresses.contains(&address) {
+            return None;
+        }
+
+        self.blocks
+            .iter()
+            .find(|x| x.freed.allocation.base_address == address)
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Quarantined> {
+        self.blocks.iter()
+    }
+
+    /// Holds the block and returns the oldest ones to release to stay within the capacity.
+    pub fn push(&mut self, block: Quarantined) -> Vec<Quarantined> {
+        self.size += block.footprint();
+        self.addresses.insert(block.freed.allocation.base_address);
+        self.blocks.push_back(block);
+
+        self.evict()
+    }
+
+    /// Returns the oldest blocks to release to stay within the capacity.
+    pub fn evict(&mut self) -> Vec<Quarantined> {
+        let mut evicted = Vec::new();
+        while self.size > self.capacity {
+            let Some(block) = self.blocks.pop_front() else {
+                break;
+            };
+
+            self.size -= block.footprint();
+            self.addresses.remove(&block.freed.allocation.base_address);
+            evicted.push(block);
+        }
+
+        evicted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::Allocation;
+
+    const SIZE: usize = 0x20;
+
+    unsafe fn release(block: usize) {
+        drop(Box::from_raw(block as *mut [u8; SIZE]));
+    }
+
+    // The first `size` bytes of the block are the allocation.
+    fn quarantined(size: usize, poison: u8) -> Quarantined {
+        let block = Box::into_raw(Box::new([0u8; SIZE])) as usize;
+        let freed = FreedAllocation {
+            allocation: Allocation {
+                base_address: block,
+                size,
+                heap_handle: 0,
+                stack_trace: None,
+                back_trace: None,
+                sequence: 0,
+                timestamp: 0,
+                thread_id: 0,
+                redzone: 0,
+            },
+            stack_trace: None,
+            back_trace: None,
+            thread_id: 0,
+            timestamp: 0,
+        };
+
+        unsafe { Quarantined::new(freed, poison, Release::new(release, block)) }
+    }
+
+    #[test]
+    fn quarantined_block_is_poisoned() {
+        let block = quarantined(SIZE, 0xAB);
+        assert!(block.block().iter().all(|&x| x == 0xAB));
+        assert!(block.check().is_none());
+
+        let base_address = block.freed.allocation.base_address;
+        unsafe { *((base_address + 0x13) as *mut u8) = 0 };
+
+        let damage = block.check().unwrap();
+        assert_eq!(damage.offset, 0x13);
+        assert_eq!(damage.address, base_address + 0x10);
+        assert_eq!(damage.data.len(), SIZE - 0x10);
+        assert_eq!(damage.data[3], 0);
+
+        block.release();
+    }
+
+    #[test]
+    fn oldest_blocks_are_evicted_over_the_capacity() {
+        let mut quarantine = Quarantine::new(2 * SIZE);
+        let blocks = [quarantined(SIZE, 0xAB), quarantined(SIZE, 0xAB), quarantined(SIZE, 0xAB)];
+        let addresses = blocks.each_ref().map(|x| x.freed.allocation.base_address);
+
+        let mut evicted = Vec::new();
+        for block in blocks {
+            evicted.extend(quarantine.push(block));
+        }
+
+        let evicted: Vec<_> = evicted
+            .into_iter()
+            .map(|x| {
+                let address = x.freed.allocation.base_address;
+                x.release();
+                address
+            })
+            .collect();
+        assert_eq!(evicted, [addresses[0]]);
+        assert_eq!(quarantine.len(), 2);
+        assert!(quarantine.find(addresses[0]).is_none());
+        assert!(quarantine.find(addresses[2]).is_some());
+
+        quarantine.set_capacity(0);
+        quarantine
+            .evict()
+            .into_iter()
+            .for_each(Quarantined::release);
+        assert!(quarantine.is_empty());
+    }
+
+    #[test]
+    fn empty_blocks_are_evicted() {
+        let mut quarantine = Quarantine::new(2);
+        let blocks = [quarantined(0, 0xAB), quarantined(0, 0xAB), quarantined(0, 0xAB)];
+
+        let mut evicted = Vec::new();
+        for block in blocks {
+            evicted.extend(quarantine.push(block));
+        }
+
+        assert_eq!(evicted.len(), 1);
+        assert_eq!(quarantine.len(), 2);
+
+        quarantine.set_capacity(0);
+        evicted.extend(quarantine.evict());
+        assert_eq!(evicted.len(), 3);
+        evicted.into_iter().for_each(Quarantined::release);
+    }
+}
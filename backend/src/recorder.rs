@@ -0,0 +1,266 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, Receiver, SyncSender},
+        Arc,
+    },
+};
+
+use common::proto;
+use prost::Message;
+
+use crate::{events::Event, storage::BackTrace, symbols, JoinHandle};
+
+// Number of events waiting to be written before new ones are dropped.
+const QUEUE_CAPACITY: usize = 0x10000;
+const TRACE_VERSION: u32 = 1;
+
+struct Record {
+    event: Event,
+    // Instruction pointers of the back trace, if any.
+    stack: Option<Vec<usize>>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RecordingSummary {
+    pub events: u64,
+    pub dropped: u64,
+}
+
+/// Appends the events to a trace file of length-delimited `TraceRecord` messages.
+///
+/// The hooked threads only push into a bounded queue and never wait for the disk;
+/// a background thread assigns stack ids, resolves their symbols once and writes the file.
+pub struct Recorder {
+    sender: SyncSender<Record>,
+    dropped: Arc<AtomicU64>,
+    writer: JoinHandle<io::Result<u64>>,
+}
+
+impl Recorder {
+    pub fn start(path: &Path) -> io::Result<Self> {
+        let file = BufWriter::new(File::create(path)?);
+        let (sender, receiver) = mpsc::sync_channel(QUEUE_CAPACITY);
+        let dropped = Arc::new(AtomicU64::new(0));
+
+        let writer_dropped = dropped.clone();
+        let writer = crate::spawn_thread(move || write_trace(file, receiver, &writer_dropped));
+
+        Ok(Self {
+            sender,
+            dropped,
+            writer,
+        })
+    }
+
+    pub fn record(&self, event: Event, back_trace: Option<&BackTrace>) {
+        let stack = back_trace.map(|x| {
+            x.frames
+                .iter()
+                .map(|frame| frame.instruction_pointer)
+                .collect()
+        });
+
+        if self.sender.try_send(Record { event, stack }).is_err() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    // Waits for the queued events to be written.
+    pub fn stop(self) -> io::Result<RecordingSummary> {
+        drop(self.sender);
+
+        let events = self
+            .writer
+            .join()
+            .map_err(|_| io::Error::from(io::ErrorKind::Other))??;
+
+        Ok(RecordingSummary {
+            events,
+            dropped: self.dropped.load(Ordering::Relaxed),
+        })
+    }
+}
+
+fn write_record(file: &mut impl Write, record: proto::trace_record::Record) -> io::Result<()> {
+    let record = proto::TraceRecord {
+        record: Some(record),
+    };
+    file.write_all(&record.encode_length_delimited_to_vec())
+}
+
+fn resolve_stack(stack: &[usize]) -> Vec<proto::BackTraceFrame> {
+    let mut symbols = symbols::lock_symbols();
+
+    stack
+        .iter()
+        .map(|&ip| proto::BackTraceFrame {
+            instruction_pointer: ip as u64,
+            stack_pointer: 0,
+            module_base: None,
+            module: None,
+            resolved_symbols: symbols.resolve(ip).iter().map(|x| x.into()).collect(),
+        })
+        .collect()
+}
+
+fn write_trace(
+    mut file: BufWriter<File>,
+    receiver: Receiver<Record>,
+    dropped: &AtomicU64,
+) -> io::Result<u64> {
+    write_record(
+        &mut file,
+        proto::trace_record::Record::Header(proto::TraceHeader {
+            version: TRACE_VERSION,
+            wordsize: crate::wordsize(),
+        }),
+    )?;
+
+    // Stack id 0 stands for no stack.
+    let mut stacks: HashMap<Vec<usize>, u32> = HashMap::new();
+    let mut events = 0;
+    let mut reported_dropped = 0;
+
+    let mut report_dropped = |file: &mut BufWriter<File>| {
+        let dropped = dropped.load(Ordering::Relaxed);
+        if dropped == reported_dropped {
+            return Ok(());
+        }
+
+        let record = proto::trace_record::Record::Dropped(dropped - reported_dropped);
+        reported_dropped = dropped;
+        write_record(file, record)
+    };
+
+    for record in receiver {
+        report_dropped(&mut file)?;
+
+        let stack_id = match record.stack {
+            Some(stack) => match stacks.get(&stack) {
+                Some(&id) => id,
+                None => {
+                    let id = stacks.len() as u32 + 1;
+                    write_record(
+                        &mut file,
+                        proto::trace_record::Record::Stack(proto::TraceStack {
+                            id,
+                            frames: resolve_stack(&stack),
+                        }),
+                    )?;
+                    stacks.insert(stack, id);
+                    id
+                }
+            },
+            None => 0,
+        };
+
+        write_record(
+            &mut file,
+            proto::trace_record::Record::Event(proto::TraceEvent {
+                event: Some((&record.event).into()),
+                stack_id,
+            }),
+        )?;
+
+        events += 1;
+    }
+
+    report_dropped(&mut file)?;
+    file.flush()?;
+
+    Ok(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::{
+        events::EventKind,
+        storage::{Address, BackTraceFrame},
+    };
+
+    fn event(kind: EventKind, base_address: Address) -> Event {
+        Event {
+            kind,
+            base_address,
+            previous_address: None,
+            size: 0x10,
+            heap_handle: 0,
+            sequence: 0,
+            timestamp: 0,
+            thread_id: 0,
+        }
+    }
+
+    fn back_trace(instruction_pointers: &[usize]) -> BackTrace {
+        BackTrace {
+            id: 0,
+            frames: instruction_pointers
+                .iter()
+                .map(|&x| BackTraceFrame {
+                    instruction_pointer: x,
+                    stack_pointer: 0,
+                    module_base: None,
+                    resolved_symbols: Vec::new(),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn trace_records_each_stack_once() {
+        let path = std::env::temp_dir().join(format!("recorder-{}.trace", std::process::id()));
+        let recorder = Recorder::start(&path).unwrap();
+
+        let stack = back_trace(&[0x10, 0x20]);
+        recorder.record(event(EventKind::Allocation, 0x1000), Some(&stack));
+        recorder.record(event(EventKind::Allocation, 0x2000), Some(&stack));
+        recorder.record(event(EventKind::Deallocation, 0x1000), None);
+
+        let summary = recorder.stop().unwrap();
+        assert_eq!((summary.events, summary.dropped), (3, 0));
+
+        let data = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let mut data = data.as_slice();
+        let mut records = Vec::new();
+        while !data.is_empty() {
+            records.push(proto::TraceRecord::decode_length_delimited(&mut data).unwrap());
+        }
+
+        use proto::trace_record::Record;
+        let records: Vec<_> = records
+            .into_iter()
+            .map(|x| match x.record.unwrap() {
+                Record::Header(x) => format!("header {}", x.version),
+                Record::Stack(x) => {
+                    let frames: Vec<_> = x.frames.iter().map(|x| x.instruction_pointer).collect();
+                    format!("stack {} {frames:?}", x.id)
+                }
+                Record::Event(x) => {
+                    format!("event 0x{:X} {}", x.event.unwrap().base_address, x.stack_id)
+                }
+                Record::Dropped(x) => format!("dropped {x}"),
+            })
+            .collect();
+
+        assert_eq!(
+            records,
+            [
+                "header 1",
+                "stack 1 [16, 32]",
+                "event 0x1000 1",
+                "event 0x2000 1",
+                "event 0x1000 0",
+            ]
+        );
+    }
+}
@@ -0,0 +1,253 @@
+// Blocks with redzones are laid out as
+// `[header][front canary][user block][back canary]`, both canaries being `redzone` bytes.
+//
+// The header holds the size of the user block and a check word, so that the blocks allocated
+// before the redzones were set up can be told apart. It is placed in front of the canary,
+// an underflow damages the canary first.
+
+use core::mem;
+
+use common::proto;
+
+use crate::{memory, platform};
+
+pub const HEADER_SIZE: usize = 16;
+
+const CANARY: u8 = 0xAC;
+const HEADER_MAGIC: usize = 0x5A0E_C0DE_5A0E_C0DE_u64 as usize;
+const WORD_SIZE: usize = mem::size_of::<usize>();
+// Smallest page size of the supported platforms.
+const PAGE_SIZE: usize = 0x1000;
+
+/// First damaged byte of the redzones of a block, along with the damaged redzone.
+/// Also the first modified byte of the poison of a quarantined block, along with the bytes
+/// around it.
+#[derive(Debug, Clone)]
+pub struct Damage {
+    // Relative to the user block, negative in front of it.
+    pub offset: isize,
+    pub address: usize,
+    pub data: Vec<u8>,
+}
+
+impl From<&Damage> for proto::Damage {
+    fn from(value: &Damage) -> Self {
+        Self {
+            offset: value.offset as i64,
+            address: value.address as u64,
+            data: value.data.clone(),
+        }
+    }
+}
+
+// Keeps the user blocks aligned as the underlying ones.
+pub const fn round(redzone: usize) -> usize {
+    redzone.next_multiple_of(HEADER_SIZE)
+}
+
+pub const fn front_size(redzone: usize) -> usize {
+    HEADER_SIZE + redzone
+}
+
+pub const fn block_size(size: usize, redzone: usize) -> Option<usize> {
+    match size.checked_add(front_size(redzone)) {
+        Some(size) => size.checked_add(redzone),
+        None => None,
+    }
+}
+
+fn check_word(user: usize, size: usize) -> usize {
+    HEADER_MAGIC ^ user ^ size
+}
+
+fn expected_front(user: usize, size: usize, redzone: usize) -> impl Iterator<Item = u8> {
+    let header = [size, check_word(user, size)];
+
+    header
+        .into_iter()
+        .flat_map(usize::to_ne_bytes)
+        .chain(core::iter::repeat(CANARY))
+        .take(front_size(redzone))
+}
+
+/// Writes the header and the canaries around the user block.
+///
+/// # Safety
+///
+/// `[user - front_size(redzone), user + size + redzone)` must be writable.
+pub unsafe fn arm(user: usize, size: usize, redzone: usize) {
+    let front = (user - front_size(redzone)) as *mut u8;
+    for (index, byte) in expected_front(user, size, redzone).enumerate() {
+        front.add(index).write(byte);
+    }
+
+    core::ptr::write_bytes((user + size) as *mut u8, CANARY, redzone);
+}
+
+// Size of the user block, if the header is intact.
+fn header_size(user: usize, header: [usize; 2]) -> Option<usize> {
+    let [size, check] = header;
+    (check == check_word(user, size)).then_some(size)
+}
+
+// Start of the underlying block, `None` for pointers too low to have redzones in front.
+pub const fn block_start(user: usize, redzone: usize) -> Option<usize> {
+    user.checked_sub(front_size(redzone))
+}
+
+/// Size of the user block, if the block has been armed with the same redzone.
+///
+/// `user` may be any pointer handed to the allocator, the pointers too low to have a header
+/// in front are not armed.
+///
+/// # Safety
+///
+/// The byte right in front of `user` must be readable, as the allocator reads its own header
+/// there as well. The header is read directly only when it lies in the same page.
+pub unsafe fn armed_size(user: usize, redzone: usize) -> Option<usize> {
+    let address = block_start(user, redzone)?;
+    let mut header = [0; 2];
+
+    // The byte right in front of the block is mapped, as is the rest of its page.
+    if address / PAGE_SIZE == (user - 1) / PAGE_SIZE {
+        header = (address as *const [usize; 2]).read_unaligned();
+    } else {
+        let bytes = &mut *(header.as_mut_ptr() as *mut [u8; 2 * WORD_SIZE]);
+        if platform::read_memory(address, bytes) != bytes.len() {
+            return None;
+        }
+    }
+
+    header_size(user, header)
+}
+
+fn find_damage(
+    user: usize,
+    size: usize,
+    redzone: usize,
+    front: &[u8],
+    back: &[u8],
+) -> Option<Damage> {
+    let front_start = user - front_size(redzone);
+
+    if let Some(index) = expected_front(user, size, redzone)
+        .zip(front.iter())
+        .position(|(expected, &actual)| expected != actual)
+    {
+        return Some(Damage {
+            offset: index as isize - front_size(redzone) as isize,
+            address: front_start,
+            data: front.to_vec(),
+        });
+    }
+
+    back.iter().position(|&x| x != CANARY).map(|index| Damage {
+        offset: (size + index) as isize,
+        address: user + size,
+        data: back.to_vec(),
+    })
+}
+
+/// Checks the redzones of a block the caller owns.
+///
+/// # Safety
+///
+/// The block must have been armed with the redzone and not freed since.
+pub unsafe fn inspect(user: usize, size: usize, redzone: usize) -> Option<Damage> {
+    let front = core::slice::from_raw_parts(
+        (user - front_size(redzone)) as *const u8,
+        front_size(redzone),
+    );
+    let back = core::slice::from_raw_parts((user + size) as *const u8, redzone);
+
+    find_damage(user, size, redzone, front, back)
+}
+
+/// Checks the redzones of a block that may be freed meanwhile, unreadable redzones are
+/// not reported.
+pub fn check(user: usize, size: usize, redzone: usize) -> Option<Damage> {
+    let front = memory::read_memory(user - front_size(redzone), front_size(redzone));
+    if front.len() != front_size(redzone) {
+        return None;
+    }
+
+    // Blocks resized while not tracking have an intact header with their actual size.
+    let header = [0, 1].map(|x| {
+        let word = &front[x * WORD_SIZE..(x + 1) * WORD_SIZE];
+        usize::from_ne_bytes(word.try_into().unwrap())
+    });
+    let size = header_size(user, header).unwrap_or(size);
+
+    let back = memory::read_memory(user + size, redzone);
+    if back.len() != redzone {
+        return None;
+    }
+
+    find_damage(user, size, redzone, &front, &back)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: usize = 0x20;
+    const REDZONE: usize = 0x10;
+
+    // Runs `f` on the user block of an armed buffer.
+    fn with_armed_block(f: impl FnOnce(*mut u8)) {
+        let mut buffer = vec![0u8; block_size(SIZE, REDZONE).unwrap()];
+        let user = unsafe { buffer.as_mut_ptr().add(front_size(REDZONE)) };
+        unsafe { arm(user as usize, SIZE, REDZONE) };
+        f(user);
+    }
+
+    fn damage_offset(user: *mut u8) -> Option<isize> {
+        check(user as usize, SIZE, REDZONE).map(|x| x.offset)
+    }
+
+    #[test]
+    fn armed_block_is_intact() {
+        with_armed_block(|user| {
+            assert_eq!(damage_offset(user), None);
+            assert!(unsafe { inspect(user as usize, SIZE, REDZONE) }.is_none());
+            assert_eq!(unsafe { armed_size(user as usize, REDZONE) }, Some(SIZE));
+        });
+    }
+
+    #[test]
+    fn overflow_damages_the_back_canary() {
+        with_armed_block(|user| {
+            unsafe { *user.add(SIZE + 3) = 0 };
+            assert_eq!(damage_offset(user), Some((SIZE + 3) as isize));
+
+            let damage = unsafe { inspect(user as usize, SIZE, REDZONE) }.unwrap();
+            assert_eq!(damage.address, user as usize + SIZE);
+            assert_eq!(damage.data.len(), REDZONE);
+        });
+    }
+
+    #[test]
+    fn underflow_damages_the_front_canary() {
+        with_armed_block(|user| {
+            unsafe { *user.sub(1) = 0 };
+            assert_eq!(damage_offset(user), Some(-1));
+        });
+    }
+
+    // The size passed in is checked once the header is damaged.
+    #[test]
+    fn damaged_header_is_reported() {
+        with_armed_block(|user| {
+            unsafe { *user.sub(front_size(REDZONE)) ^= 0xFF };
+            assert_eq!(damage_offset(user), Some(-(front_size(REDZONE) as isize)));
+            assert_eq!(unsafe { armed_size(user as usize, REDZONE) }, None);
+        });
+    }
+
+    // Bogus pointers below the redzones are not armed rather than underflowing.
+    #[test]
+    fn pointer_too_low_is_not_armed() {
+        assert_eq!(unsafe { armed_size(8, 16) }, None);
+        assert_eq!(block_start(8, 16), None);
+    }
+}
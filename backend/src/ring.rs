@@ -0,0 +1,109 @@
+use std::collections::VecDeque;
+
+/// Bounded ring of the most recent items shared by all the readers.
+///
+/// Items are addressed by cursor, the number of items pushed before them. Pushing never
+/// waits for the readers: the oldest item is evicted instead, and a reader which has not
+/// seen it yet is told how many items it has lost.
+pub struct CursorRing<T> {
+    items: VecDeque<T>,
+    capacity: usize,
+    // Number of items ever pushed, the cursor of the next one.
+    pushed: u64,
+}
+
+impl<T: Clone> CursorRing<T> {
+    pub const fn new(capacity: usize) -> Self {
+        Self {
+            items: VecDeque::new(),
+            capacity,
+            pushed: 0,
+        }
+    }
+
+    // Drops all the items and restarts the cursors. Nothing is recorded with zero capacity.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.items = VecDeque::with_capacity(capacity);
+        self.capacity = capacity;
+        self.pushed = 0;
+    }
+
+    pub fn cursor(&self) -> u64 {
+        self.pushed
+    }
+
+    pub fn push(&mut self, item: T) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        if self.items.len() == self.capacity {
+            self.items.pop_front();
+        }
+
+        self.items.push_back(item);
+        self.pushed += 1;
+    }
+
+    /// Appends at most `count` items starting at `cursor`.
+    /// Returns the cursor to continue from and the number of items lost since `cursor`.
+    pub fn read(&self, cursor: u64, count: usize, items: &mut Vec<T>) -> (u64, u64) {
+        let oldest = self.pushed - self.items.len() as u64;
+        let dropped = oldest.saturating_sub(cursor);
+        let first = cursor.clamp(oldest, self.pushed);
+        let last = self.pushed.min(first.saturating_add(count as u64));
+
+        items.extend(
+            self.items
+                .range((first - oldest) as usize..(last - oldest) as usize)
+                .cloned(),
+        );
+
+        (last, dropped)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(ring: &CursorRing<u64>, cursor: u64, count: usize) -> (Vec<u64>, u64, u64) {
+        let mut items = Vec::new();
+        let (cursor, dropped) = ring.read(cursor, count, &mut items);
+        (items, cursor, dropped)
+    }
+
+    #[test]
+    fn read_in_batches() {
+        let mut ring = CursorRing::new(4);
+        (0..3).for_each(|x| ring.push(x));
+
+        assert_eq!(read(&ring, 0, 2), (vec![0, 1], 2, 0));
+        assert_eq!(read(&ring, 2, 2), (vec![2], 3, 0));
+        assert_eq!(read(&ring, 3, 2), (vec![], 3, 0));
+        assert_eq!(read(&ring, 1, usize::MAX), (vec![1, 2], 3, 0));
+    }
+
+    #[test]
+    fn overflow_drops_the_oldest_items() {
+        let mut ring = CursorRing::new(4);
+        (0..7).for_each(|x| ring.push(x));
+
+        // The reader is told about the items overwritten before it read them.
+        assert_eq!(read(&ring, 1, 10), (vec![3, 4, 5, 6], 7, 2));
+        assert_eq!(read(&ring, 5, 10), (vec![5, 6], 7, 0));
+        assert_eq!(read(&ring, u64::MAX, usize::MAX), (vec![], 7, 0));
+    }
+
+    #[test]
+    fn nothing_recorded_without_capacity() {
+        let mut ring = CursorRing::new(0);
+        ring.push(0);
+        assert_eq!(read(&ring, 0, 10), (vec![], 0, 0));
+
+        ring.set_capacity(2);
+        ring.push(1);
+        ring.set_capacity(0);
+        assert_eq!(ring.cursor(), 0);
+    }
+}
@@ -1,8 +1,30 @@
-use std::sync::{Mutex, MutexGuard};
+use std::{
+    io,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Mutex, MutexGuard, RwLock,
+    },
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
 
 use common::proto;
 
-use crate::storage::AllocationsStorage;
+use crate::{
+    errors::{ErrorLog, HeapError},
+    events::{Event, EventRing},
+    faults::{FaultCondition, Faults},
+    quarantine::Quarantine,
+    recorder::{Recorder, RecordingSummary},
+    storage::{AllocationsStorage, BackTrace, FreedAllocationsStorage, StackTable},
+};
+
+// Number of events buffered for the subscribers.
+const EVENTS_CAPACITY: usize = 0x10000;
+// Number of heap errors kept.
+const ERRORS_CAPACITY: usize = 0x1000;
+// Unlikely to be a valid pointer or a small integer read after the block is freed.
+const DEFAULT_POISON_BYTE: u8 = 0xEE;
 
 #[derive(Debug, Clone)]
 pub struct Configuration {
@@ -11,16 +33,23 @@ pub struct Configuration {
     pub backtrace_frames_skip: u32,
     pub backtrace_frames_count: u32,
     pub backtrace_resolve_symbols_count: u32,
+    pub freed_history_size: usize,
+    // Total size of the freed blocks held poisoned before being released, zero to disable.
+    pub quarantine_size: usize,
+    pub poison_byte: u8,
 }
 
 impl Default for Configuration {
     fn default() -> Self {
         Self {
-            stack_trace_offset: 0x0,
-            stack_trace_size: 0x0,
-            backtrace_frames_count: 0x0,
-            backtrace_frames_skip: 0x0,
-            backtrace_resolve_symbols_count: 0x0,
+            stack_trace_offset: 0,
+            stack_trace_size: 0,
+            backtrace_frames_skip: 0,
+            backtrace_frames_count: 0,
+            backtrace_resolve_symbols_count: 0,
+            freed_history_size: 0,
+            quarantine_size: 0,
+            poison_byte: DEFAULT_POISON_BYTE,
         }
     }
 }
@@ -33,6 +62,9 @@ impl From<proto::Configuration> for Configuration {
             backtrace_frames_count: value.backtrace_frames_count,
             backtrace_frames_skip: value.backtrace_frames_skip,
             backtrace_resolve_symbols_count: value.backtrace_resolve_symbols_count,
+            freed_history_size: value.freed_history_size as usize,
+            quarantine_size: value.quarantine_size as usize,
+            poison_byte: value.poison_byte.map_or(DEFAULT_POISON_BYTE, |x| x as u8),
         }
     }
 }
@@ -45,6 +77,9 @@ impl From<Configuration> for proto::Configuration {
             backtrace_frames_count: value.backtrace_frames_count,
             backtrace_frames_skip: value.backtrace_frames_skip,
             backtrace_resolve_symbols_count: value.backtrace_resolve_symbols_count,
+            freed_history_size: value.freed_history_size as u64,
+            quarantine_size: value.quarantine_size as u64,
+            poison_byte: Some(value.poison_byte as u32),
         }
     }
 }
@@ -55,6 +90,7 @@ pub struct Statistics {
     pub total_reallocations: usize,
     pub total_deallocations: usize,
     pub total_deallocations_non_allocated: usize,
+    pub total_injected_failures: usize,
 }
 
 impl Statistics {
@@ -68,19 +104,58 @@ pub type StateRef = &'static State;
 pub struct State {
     configuration: Mutex<Configuration>,
     storage: Mutex<Box<dyn AllocationsStorage>>,
+    freed_storage: Mutex<FreedAllocationsStorage>,
+    quarantine: Mutex<Quarantine>,
+    faults: Mutex<Faults>,
+    // Set while there are fault rules, the allocations do not take the lock otherwise.
+    injecting_faults: AtomicBool,
+    stacks: Mutex<StackTable>,
     statistics: Mutex<Box<Statistics>>,
+    events: Mutex<EventRing>,
+    errors: Mutex<ErrorLog>,
+    subscribers: AtomicUsize,
+    recorder: RwLock<Option<Recorder>>,
+    started: Instant,
+    run: u64,
+    sequence: AtomicU64,
+    // Timestamp of the last time the detours were enabled, the blocks freed before may have
+    // been reused since without being tracked.
+    enabled_at: AtomicU64,
 }
 
 impl State {
     pub fn new(configuration: Configuration, storage: Box<dyn AllocationsStorage>) -> Self {
         Self {
+            freed_storage: Mutex::new(FreedAllocationsStorage::new(
+                configuration.freed_history_size,
+            )),
+            quarantine: Mutex::new(Quarantine::new(configuration.quarantine_size)),
             configuration: Mutex::new(configuration),
+            faults: Mutex::new(Faults::new()),
+            injecting_faults: AtomicBool::new(false),
             storage: Mutex::new(storage),
+            stacks: Mutex::new(StackTable::new()),
             statistics: Mutex::new(Box::new(Statistics::default())),
+            events: Mutex::new(EventRing::new(0)),
+            errors: Mutex::new(ErrorLog::new(ERRORS_CAPACITY)),
+            subscribers: AtomicUsize::new(0),
+            recorder: RwLock::new(None),
+            started: Instant::now(),
+            run: run_id(),
+            sequence: AtomicU64::new(0),
+            enabled_at: AtomicU64::new(0),
         }
     }
 
     pub fn set_configuration(&self, configuration: Configuration) {
+        self.lock_freed_storage()
+            .set_capacity(configuration.freed_history_size);
+        self.lock_quarantine()
+            .set_capacity(configuration.quarantine_size);
+
+        // Traces interned before have been captured with the previous configuration.
+        self.lock_stacks().clear();
+
         *self
             .configuration
             .lock()
@@ -98,9 +173,152 @@ impl State {
         self.storage.lock().expect("unexpected storage lock poison")
     }
 
+    pub fn lock_freed_storage(&self) -> MutexGuard<'_, FreedAllocationsStorage> {
+        self.freed_storage
+            .lock()
+            .expect("unexpected freed storage lock poison")
+    }
+
+    pub fn lock_quarantine(&self) -> MutexGuard<'_, Quarantine> {
+        self.quarantine
+            .lock()
+            .expect("unexpected quarantine lock poison")
+    }
+
+    pub fn lock_faults(&self) -> MutexGuard<'_, Faults> {
+        self.faults.lock().expect("unexpected faults lock poison")
+    }
+
+    pub fn is_injecting_faults(&self) -> bool {
+        self.injecting_faults.load(Ordering::Relaxed)
+    }
+
+    // A zero seed is picked from the clock.
+    pub fn set_faults(&self, conditions: Vec<FaultCondition>, seed: u64) {
+        let seed = match seed {
+            0 => self.timestamp() | 1,
+            seed => seed,
+        };
+
+        let mut faults = self.lock_faults();
+        faults.set(conditions, seed);
+        self.injecting_faults
+            .store(!faults.is_empty(), Ordering::Relaxed);
+    }
+
+    pub fn lock_stacks(&self) -> MutexGuard<'_, StackTable> {
+        self.stacks.lock().expect("unexpected stacks lock poison")
+    }
+
+    // Nanoseconds since the state was created.
+    pub fn timestamp(&self) -> u64 {
+        self.started.elapsed().as_nanos() as u64
+    }
+
+    pub fn mark_enabled(&self) {
+        self.enabled_at.store(self.timestamp(), Ordering::Relaxed);
+    }
+
+    pub fn enabled_at(&self) -> u64 {
+        self.enabled_at.load(Ordering::Relaxed)
+    }
+
+    // Tells this run of the process from the others, the sequence numbers and the addresses
+    // of different runs are unrelated.
+    pub fn run(&self) -> u64 {
+        self.run
+    }
+
+    // Orders the allocations, unlike timestamps never repeats.
+    pub fn next_sequence(&self) -> u64 {
+        self.sequence.fetch_add(1, Ordering::Relaxed)
+    }
+
+    pub fn lock_events(&self) -> MutexGuard<'_, EventRing> {
+        self.events.lock().expect("unexpected events lock poison")
+    }
+
+    // Events are recorded only while there are subscribers. Returns the cursor of the next event.
+    pub fn subscribe(&self) -> u64 {
+        let mut events = self.lock_events();
+        if self.subscribers.fetch_add(1, Ordering::AcqRel) == 0 {
+            events.set_capacity(EVENTS_CAPACITY);
+        }
+        events.cursor()
+    }
+
+    pub fn unsubscribe(&self) {
+        let mut events = self.lock_events();
+        if self.subscribers.fetch_sub(1, Ordering::AcqRel) == 1 {
+            events.set_capacity(0);
+        }
+    }
+
+    pub fn record_event(&self, event: Event, back_trace: Option<&BackTrace>) {
+        if self.subscribers.load(Ordering::Acquire) != 0 {
+            self.lock_events().push(event);
+        }
+
+        if let Some(recorder) = self
+            .recorder
+            .read()
+            .expect("unexpected recorder lock poison")
+            .as_ref()
+        {
+            recorder.record(event, back_trace);
+        }
+    }
+
+    pub fn lock_errors(&self) -> MutexGuard<'_, ErrorLog> {
+        self.errors.lock().expect("unexpected errors lock poison")
+    }
+
+    pub fn record_error(&self, error: HeapError) {
+        self.lock_errors().push(error);
+    }
+
+    pub fn start_recording(&self, path: &Path) -> io::Result<()> {
+        // Started outside of the lock, the thread may be tracked and record its own allocations.
+        let new_recorder = Recorder::start(path)?;
+
+        let mut recorder = self
+            .recorder
+            .write()
+            .expect("unexpected recorder lock poison");
+
+        if recorder.is_some() {
+            drop(recorder);
+            new_recorder.stop().ok();
+            return Err(io::Error::from(io::ErrorKind::AlreadyExists));
+        }
+
+        *recorder = Some(new_recorder);
+        Ok(())
+    }
+
+    // Returns `None` if not recording.
+    pub fn stop_recording(&self) -> Option<io::Result<RecordingSummary>> {
+        let recorder = self
+            .recorder
+            .write()
+            .expect("unexpected recorder lock poison")
+            .take();
+
+        recorder.map(Recorder::stop)
+    }
+
     pub fn lock_statistics(&self) -> MutexGuard<'_, Box<Statistics>> {
         self.statistics
             .lock()
             .expect("unexpected statistics lock poison")
     }
 }
+
+// Wall clock mixed with the process id, for the runs reusing a process id to differ as well.
+fn run_id() -> u64 {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos() as u64;
+    now ^ (std::process::id() as u64).rotate_right(16)
+}
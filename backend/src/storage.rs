@@ -1,10 +1,13 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::ops::Bound::{Excluded, Included};
+use std::sync::{Arc, Weak};
 
 use common::proto;
 
 pub type Address = usize;
 pub type HeapHandle = usize;
+// Zero is never assigned to an interned back trace.
+pub type StackId = u64;
 
 #[derive(Debug, Clone)]
 pub struct StackTrace {
@@ -16,6 +19,9 @@ pub struct StackTrace {
 pub struct BackTraceSymbol {
     pub name: Option<String>,
     pub address: Option<usize>,
+    pub filename: Option<String>,
+    pub lineno: Option<u32>,
+    pub colno: Option<u32>,
 }
 
 #[derive(Debug, Clone)]
@@ -28,6 +34,7 @@ pub struct BackTraceFrame {
 
 #[derive(Debug, Clone)]
 pub struct BackTrace {
+    pub id: StackId,
     pub frames: Vec<BackTraceFrame>,
 }
 
@@ -37,7 +44,31 @@ pub struct Allocation {
     pub size: usize,
     pub heap_handle: HeapHandle,
     pub stack_trace: Option<StackTrace>,
-    pub back_trace: Option<BackTrace>,
+    pub back_trace: Option<Arc<BackTrace>>,
+    // Monotonically increasing, unique among all the allocations.
+    pub sequence: u64,
+    // Nanoseconds since the initialization.
+    pub timestamp: u64,
+    // OS thread id of the allocating thread.
+    pub thread_id: u64,
+    // Size of the canaries on each side of the block, zero if there are none.
+    pub redzone: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct FreedAllocation {
+    pub allocation: Allocation,
+    pub stack_trace: Option<StackTrace>,
+    pub back_trace: Option<Arc<BackTrace>>,
+    pub thread_id: u64,
+    pub timestamp: u64,
+}
+
+impl Allocation {
+    // Zero-sized allocations still own their base address.
+    pub fn contains(&self, address: Address) -> bool {
+        address >= self.base_address && address - self.base_address < self.size.max(1)
+    }
 }
 
 impl From<&StackTrace> for proto::StackTrace {
@@ -50,11 +81,35 @@ impl From<&StackTrace> for proto::StackTrace {
     }
 }
 
+impl From<&proto::StackTrace> for StackTrace {
+    fn from(value: &proto::StackTrace) -> Self {
+        Self {
+            base: value.stack_pointer as usize,
+            trace: value.trace.iter().map(|&x| x as usize).collect(),
+        }
+    }
+}
+
 impl From<&BackTraceSymbol> for proto::BackTraceSymbol {
     fn from(value: &BackTraceSymbol) -> Self {
         Self {
-            name: value.name.as_ref().map(|x| x.clone()),
+            name: value.name.clone(),
             address: value.address.map(|x| x as u64),
+            filename: value.filename.clone(),
+            lineno: value.lineno,
+            colno: value.colno,
+        }
+    }
+}
+
+impl From<&proto::BackTraceSymbol> for BackTraceSymbol {
+    fn from(value: &proto::BackTraceSymbol) -> Self {
+        Self {
+            name: value.name.clone(),
+            address: value.address.map(|x| x as usize),
+            filename: value.filename.clone(),
+            lineno: value.lineno,
+            colno: value.colno,
         }
     }
 }
@@ -66,6 +121,18 @@ impl From<&BackTraceFrame> for proto::BackTraceFrame {
             stack_pointer: value.stack_pointer as u64,
             module_base: value.module_base.map(|x| x as u64),
             resolved_symbols: value.resolved_symbols.iter().map(|x| x.into()).collect(),
+            module: None,
+        }
+    }
+}
+
+impl From<&proto::BackTraceFrame> for BackTraceFrame {
+    fn from(value: &proto::BackTraceFrame) -> Self {
+        Self {
+            instruction_pointer: value.instruction_pointer as usize,
+            stack_pointer: value.stack_pointer as usize,
+            module_base: value.module_base.map(|x| x as usize),
+            resolved_symbols: value.resolved_symbols.iter().map(|x| x.into()).collect(),
         }
     }
 }
@@ -78,6 +145,15 @@ impl From<&BackTrace> for proto::BackTrace {
     }
 }
 
+impl From<&proto::BackTrace> for BackTrace {
+    fn from(value: &proto::BackTrace) -> Self {
+        Self {
+            id: 0,
+            frames: value.frames.iter().map(|x| x.into()).collect(),
+        }
+    }
+}
+
 impl From<&Allocation> for proto::Allocation {
     fn from(value: &Allocation) -> Self {
         Self {
@@ -85,7 +161,46 @@ impl From<&Allocation> for proto::Allocation {
             size: value.size as u64,
             heap_handle: value.heap_handle as u64,
             stack_trace: value.stack_trace.as_ref().map(|x| x.into()),
-            back_trace: value.back_trace.as_ref().map(|x| x.into()),
+            back_trace: value.back_trace.as_deref().map(|x| x.into()),
+            sequence: value.sequence,
+            timestamp: value.timestamp,
+            thread_id: value.thread_id,
+            stack_id: value.back_trace.as_ref().map_or(0, |x| x.id),
+            redzone: value.redzone as u64,
+        }
+    }
+}
+
+impl From<&proto::Allocation> for Allocation {
+    fn from(value: &proto::Allocation) -> Self {
+        Self {
+            base_address: value.base_address as Address,
+            size: value.size as usize,
+            heap_handle: value.heap_handle as HeapHandle,
+            stack_trace: value.stack_trace.as_ref().map(|x| x.into()),
+            back_trace: value.back_trace.as_ref().map(|x| {
+                Arc::new(BackTrace {
+                    id: value.stack_id,
+                    ..x.into()
+                })
+            }),
+            sequence: value.sequence,
+            timestamp: value.timestamp,
+            thread_id: value.thread_id,
+            redzone: value.redzone as usize,
+        }
+    }
+}
+
+impl From<&FreedAllocation> for proto::FreedAllocation {
+    fn from(value: &FreedAllocation) -> Self {
+        Self {
+            allocation: Some((&value.allocation).into()),
+            free_stack_trace: value.stack_trace.as_ref().map(|x| x.into()),
+            free_back_trace: value.back_trace.as_deref().map(|x| x.into()),
+            free_thread_id: value.thread_id,
+            free_timestamp: value.timestamp,
+            free_stack_id: value.back_trace.as_ref().map_or(0, |x| x.id),
         }
     }
 }
@@ -93,17 +208,21 @@ impl From<&Allocation> for proto::Allocation {
 pub trait AllocationsStorage: Sync + Send {
     fn store(&mut self, allocation: Allocation);
 
-    fn remove(&mut self, address: Address) -> Result<(), ()>;
+    #[allow(clippy::result_unit_err)]
+    fn remove(&mut self, address: Address) -> Result<Allocation, ()>;
 
     fn find(&self, address: Address) -> Option<&Allocation>;
 
+    // Finds the allocation whose `[base, base + size)` covers the address.
+    fn find_containing(&self, address: Address) -> Option<&Allocation>;
+
     fn find_range<'a>(
         &'a self,
         lower: Address,
         upper: Address,
-    ) -> Box<dyn Iterator<Item = &Allocation> + 'a>;
+    ) -> Box<dyn Iterator<Item = &'a Allocation> + 'a>;
 
-    fn dump<'a>(&'a self) -> Box<dyn Iterator<Item = &Allocation> + 'a>;
+    fn dump<'a>(&'a self) -> Box<dyn Iterator<Item = &'a Allocation> + 'a>;
 
     fn clear(&mut self);
 
@@ -122,28 +241,39 @@ impl BtreeMapStorage {
     }
 }
 
+impl Default for BtreeMapStorage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl AllocationsStorage for BtreeMapStorage {
     fn store(&mut self, allocation: Allocation) {
         self.map.insert(allocation.base_address, allocation);
     }
 
-    fn remove(&mut self, address: Address) -> Result<(), ()> {
-        if self.map.remove(&address).is_some() {
-            Ok(())
-        } else {
-            Err(())
-        }
+    fn remove(&mut self, address: Address) -> Result<Allocation, ()> {
+        self.map.remove(&address).ok_or(())
     }
 
     fn find(&self, address: Address) -> Option<&Allocation> {
         self.map.get(&address)
     }
 
+    fn find_containing(&self, address: Address) -> Option<&Allocation> {
+        // Allocations never overlap, so only the closest one at or below the address may contain it.
+        self.map
+            .range(..=address)
+            .next_back()
+            .map(|(_, allocation)| allocation)
+            .filter(|allocation| allocation.contains(address))
+    }
+
     fn find_range<'a>(
         &'a self,
         lower: Address,
         upper: Address,
-    ) -> Box<dyn Iterator<Item = &Allocation> + 'a> {
+    ) -> Box<dyn Iterator<Item = &'a Allocation> + 'a> {
         if lower > upper {
             Box::new(core::iter::empty())
         } else {
@@ -155,8 +285,8 @@ impl AllocationsStorage for BtreeMapStorage {
         }
     }
 
-    fn dump<'a>(&'a self) -> Box<dyn Iterator<Item = &Allocation> + 'a> {
-        Box::new(self.map.iter().map(|(_, k)| k))
+    fn dump<'a>(&'a self) -> Box<dyn Iterator<Item = &'a Allocation> + 'a> {
+        Box::new(self.map.values())
     }
 
     fn clear(&mut self) {
@@ -167,3 +297,340 @@ impl AllocationsStorage for BtreeMapStorage {
         self.map.len()
     }
 }
+
+// Bounded history of freed allocations, the oldest records are evicted first.
+pub struct FreedAllocationsStorage {
+    history: VecDeque<FreedAllocation>,
+    // Positions in the history of the allocations freed at the address, oldest first.
+    // A position counts every allocation ever stored, so eviction does not shift it.
+    addresses: HashMap<Address, VecDeque<u64>>,
+    // Position of the oldest allocation of the history.
+    first: u64,
+    capacity: usize,
+}
+
+impl FreedAllocationsStorage {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            history: VecDeque::new(),
+            addresses: HashMap::new(),
+            first: 0,
+            capacity,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        self.evict();
+    }
+
+    pub fn store(&mut self, freed_allocation: FreedAllocation) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let position = self.first + self.history.len() as u64;
+        self.addresses
+            .entry(freed_allocation.allocation.base_address)
+            .or_default()
+            .push_back(position);
+        self.history.push_back(freed_allocation);
+        self.evict();
+    }
+
+    // Most recently freed first: the same address may have been freed many times.
+    pub fn find(&self, address: Address) -> impl Iterator<Item = &FreedAllocation> {
+        self.addresses
+            .get(&address)
+            .into_iter()
+            .flat_map(|x| x.iter().rev())
+            .map(|&position| &self.history[(position - self.first) as usize])
+    }
+
+    pub fn find_containing(&self, address: Address) -> impl Iterator<Item = &FreedAllocation> {
+        self.dump().filter(move |x| x.allocation.contains(address))
+    }
+
+    pub fn find_range(
+        &self,
+        lower: Address,
+        upper: Address,
+    ) -> impl Iterator<Item = &FreedAllocation> {
+        self.dump()
+            .filter(move |x| (lower..upper).contains(&x.allocation.base_address))
+    }
+
+    pub fn dump(&self) -> impl Iterator<Item = &FreedAllocation> {
+        self.history.iter().rev()
+    }
+
+    pub fn clear(&mut self) {
+        self.first += self.history.len() as u64;
+        self.history.clear();
+        self.addresses.clear();
+    }
+
+    pub fn count(&self) -> usize {
+        self.history.len()
+    }
+
+    fn evict(&mut self) {
+        while self.history.len() > self.capacity {
+            let Some(evicted) = self.history.pop_front() else {
+                break;
+            };
+
+            let address = evicted.allocation.base_address;
+            if let Some(positions) = self.addresses.get_mut(&address) {
+                positions.pop_front();
+                if positions.is_empty() {
+                    self.addresses.remove(&address);
+                }
+            }
+
+            self.first += 1;
+        }
+    }
+}
+
+/// Back traces shared by all the allocations made from the same call site.
+///
+/// Traces are keyed by their instruction pointers, so the stack pointers are the ones
+/// of the first occurrence. Only weak references are kept: a trace is freed along with
+/// the last record referencing it, and its id is never reused.
+#[derive(Default)]
+pub struct StackTable {
+    stacks: HashMap<Vec<usize>, Weak<BackTrace>>,
+    last_id: StackId,
+    // Number of entries after the last pruning.
+    pruned_count: usize,
+}
+
+impl StackTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn find(&self, instruction_pointers: &[usize]) -> Option<Arc<BackTrace>> {
+        self.stacks
+            .get(instruction_pointers)
+            .and_then(|x| x.upgrade())
+    }
+
+    pub fn intern(&mut self, back_trace: BackTrace) -> Arc<BackTrace> {
+        let instruction_pointers: Vec<usize> = back_trace
+            .frames
+            .iter()
+            .map(|x| x.instruction_pointer)
+            .collect();
+
+        if let Some(existing) = self.find(&instruction_pointers) {
+            return existing;
+        }
+
+        self.last_id += 1;
+        let back_trace = Arc::new(BackTrace {
+            id: self.last_id,
+            ..back_trace
+        });
+
+        self.stacks
+            .insert(instruction_pointers, Arc::downgrade(&back_trace));
+
+        // Amortized removal of the entries of the freed traces.
+        if self.stacks.len() >= 2 * self.pruned_count.max(0x100) {
+            self.stacks.retain(|_, x| x.strong_count() != 0);
+            self.pruned_count = self.stacks.len();
+        }
+
+        back_trace
+    }
+
+    // New traces are not deduplicated against the ones interned before.
+    pub fn clear(&mut self) {
+        self.stacks.clear();
+        self.pruned_count = 0;
+    }
+
+    pub fn count(&self) -> usize {
+        self.stacks
+            .values()
+            .filter(|x| x.strong_count() != 0)
+            .count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn allocation(base_address: Address, size: usize) -> Allocation {
+        Allocation {
+            base_address,
+            size,
+            heap_handle: 0,
+            stack_trace: None,
+            back_trace: None,
+            sequence: 0,
+            timestamp: 0,
+            thread_id: 0,
+            redzone: 0,
+        }
+    }
+
+    fn freed(base_address: Address, timestamp: u64) -> FreedAllocation {
+        FreedAllocation {
+            allocation: Allocation {
+                sequence: timestamp,
+                timestamp,
+                ..allocation(base_address, 0x10)
+            },
+            stack_trace: None,
+            back_trace: None,
+            thread_id: 0,
+            timestamp,
+        }
+    }
+
+    fn containing(storage: &BtreeMapStorage, address: Address) -> Option<Address> {
+        storage.find_containing(address).map(|x| x.base_address)
+    }
+
+    #[test]
+    fn find_containing_interior_addresses() {
+        let mut storage = BtreeMapStorage::new();
+        storage.store(allocation(0x1000, 0x10));
+        storage.store(allocation(0x1010, 0x20));
+
+        assert_eq!(containing(&storage, 0xFFF), None);
+        assert_eq!(containing(&storage, 0x1000), Some(0x1000));
+        assert_eq!(containing(&storage, 0x100F), Some(0x1000));
+        assert_eq!(containing(&storage, 0x1010), Some(0x1010));
+        assert_eq!(containing(&storage, 0x102F), Some(0x1010));
+        assert_eq!(containing(&storage, 0x1030), None);
+    }
+
+    #[test]
+    fn find_containing_between_allocations() {
+        let mut storage = BtreeMapStorage::new();
+        storage.store(allocation(0x1000, 0x10));
+        storage.store(allocation(0x2000, 0x10));
+
+        // The closest allocation below does not reach the address.
+        assert_eq!(containing(&storage, 0x1800), None);
+        assert_eq!(containing(&storage, 0x2008), Some(0x2000));
+    }
+
+    #[test]
+    fn find_containing_empty_allocation() {
+        let mut storage = BtreeMapStorage::new();
+        storage.store(allocation(0x1000, 0));
+
+        assert_eq!(containing(&storage, 0x1000), Some(0x1000));
+        assert_eq!(containing(&storage, 0x1001), None);
+    }
+
+    fn found(storage: &FreedAllocationsStorage, address: Address) -> Vec<u64> {
+        storage.find(address).map(|x| x.timestamp).collect()
+    }
+
+    #[test]
+    fn freed_find_most_recent_first() {
+        let mut storage = FreedAllocationsStorage::new(0x10);
+        storage.store(freed(0x1000, 1));
+        storage.store(freed(0x2000, 2));
+        storage.store(freed(0x1000, 3));
+
+        assert_eq!(found(&storage, 0x1000), [3, 1]);
+        assert_eq!(found(&storage, 0x2000), [2]);
+        assert_eq!(found(&storage, 0x1008), []);
+    }
+
+    #[test]
+    fn freed_find_after_eviction() {
+        let mut storage = FreedAllocationsStorage::new(2);
+        storage.store(freed(0x1000, 1));
+        storage.store(freed(0x2000, 2));
+        storage.store(freed(0x1000, 3));
+        storage.store(freed(0x3000, 4));
+
+        assert_eq!(storage.count(), 2);
+        assert_eq!(found(&storage, 0x1000), [3]);
+        assert_eq!(found(&storage, 0x2000), []);
+        assert_eq!(found(&storage, 0x3000), [4]);
+
+        storage.set_capacity(1);
+        assert_eq!(found(&storage, 0x1000), []);
+        assert_eq!(found(&storage, 0x3000), [4]);
+    }
+
+    #[test]
+    fn freed_find_after_clear() {
+        let mut storage = FreedAllocationsStorage::new(0x10);
+        storage.store(freed(0x1000, 1));
+        storage.clear();
+        storage.store(freed(0x1000, 2));
+
+        assert_eq!(found(&storage, 0x1000), [2]);
+    }
+
+    #[test]
+    fn freed_history_disabled() {
+        let mut storage = FreedAllocationsStorage::new(0);
+        storage.store(freed(0x1000, 1));
+
+        assert_eq!(storage.count(), 0);
+        assert_eq!(found(&storage, 0x1000), []);
+    }
+
+    fn back_trace(instruction_pointers: &[usize], stack_pointer: usize) -> BackTrace {
+        BackTrace {
+            id: 0,
+            frames: instruction_pointers
+                .iter()
+                .map(|&x| BackTraceFrame {
+                    instruction_pointer: x,
+                    stack_pointer,
+                    module_base: None,
+                    resolved_symbols: Vec::new(),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn stack_table_shares_call_sites() {
+        let mut stacks = StackTable::new();
+
+        let first = stacks.intern(back_trace(&[1, 2], 0x100));
+        // Same call site from another stack depth.
+        let same = stacks.intern(back_trace(&[1, 2], 0x200));
+        let other = stacks.intern(back_trace(&[1, 3], 0x100));
+
+        assert!(Arc::ptr_eq(&first, &same));
+        assert_eq!(same.frames[0].stack_pointer, 0x100);
+        assert_ne!(first.id, 0);
+        assert_ne!(first.id, other.id);
+        assert_eq!(stacks.count(), 2);
+    }
+
+    #[test]
+    fn stack_table_forgets_freed_traces() {
+        let mut stacks = StackTable::new();
+
+        let first = stacks.intern(back_trace(&[1], 0));
+        let id = first.id;
+        drop(first);
+
+        assert_eq!(stacks.count(), 0);
+        assert!(stacks.find(&[1]).is_none());
+
+        // Ids are never reused.
+        let again = stacks.intern(back_trace(&[1], 0));
+        assert_ne!(again.id, id);
+    }
+}
@@ -0,0 +1,214 @@
+use std::{
+    collections::HashMap,
+    ops::Range,
+    sync::{Mutex, MutexGuard},
+};
+
+use common::proto;
+use lazy_static::lazy_static;
+use object::{BinaryFormat, Object, ObjectSegment, ObjectSymbol, SymbolKind};
+
+use crate::{
+    modules,
+    storage::{Address, BackTraceSymbol},
+};
+
+/// Symbols of the instruction pointers, resolved on demand and kept for the process lifetime.
+///
+/// Resolution is slow, so the allocation hooks only capture the instruction pointers
+/// and the symbols are resolved when a response is built.
+pub struct SymbolCache {
+    symbols: HashMap<usize, Vec<BackTraceSymbol>>,
+}
+
+lazy_static! {
+    static ref SYMBOL_CACHE: Mutex<SymbolCache> = Mutex::new(SymbolCache::new());
+}
+
+// Resolving allocates, so the calling thread must hold the detour lock or not be hooked.
+pub fn lock_symbols() -> MutexGuard<'static, SymbolCache> {
+    SYMBOL_CACHE
+        .lock()
+        .expect("unexpected symbol cache lock poison")
+}
+
+impl SymbolCache {
+    fn new() -> Self {
+        Self {
+            symbols: HashMap::new(),
+        }
+    }
+
+    /// Every symbol of the instruction pointer, innermost inlined function first.
+    pub fn resolve(&mut self, instruction_pointer: usize) -> &[BackTraceSymbol] {
+        self.symbols.entry(instruction_pointer).or_insert_with(|| {
+            let mut symbols = Vec::new();
+
+            backtrace::resolve(instruction_pointer as *mut _, |symbol| {
+                symbols.push(BackTraceSymbol {
+                    name: symbol.name().and_then(|x| x.as_str().map(|y| y.to_owned())),
+                    address: symbol.addr().map(|x| x as usize),
+                    filename: symbol.filename().map(|x| x.to_string_lossy().into_owned()),
+                    lineno: symbol.lineno(),
+                    colno: symbol.colno(),
+                });
+            });
+
+            symbols
+        })
+    }
+
+    // Fills the frames which have not been resolved yet with at most `count` symbols each.
+    pub fn symbolize(&mut self, back_trace: &mut proto::BackTrace, count: usize) {
+        for frame in back_trace.frames.iter_mut() {
+            if frame.resolved_symbols.is_empty() {
+                frame.resolved_symbols = self
+                    .resolve(frame.instruction_pointer as usize)
+                    .iter()
+                    .take(count)
+                    .map(|x| x.into())
+                    .collect();
+            }
+        }
+    }
+}
+
+/// Address ranges of the functions of the loaded modules with a matching name, merged and in
+/// address order.
+///
+/// The symbol tables are read from the module files: the modules loaded afterwards and the
+/// inlined functions are not covered, unlike the symbols resolved from the debug information.
+pub fn find_functions(matches: impl Fn(&str) -> bool) -> Vec<Range<Address>> {
+    let mut ranges = Vec::new();
+
+    for module in modules::loaded_modules() {
+        let Ok(data) = std::fs::read(&module.path) else {
+            continue;
+        };
+        let Ok(file) = object::File::parse(&*data) else {
+            continue;
+        };
+
+        // The base of the module is where its lowest address is loaded.
+        let lowest = match file.format() {
+            BinaryFormat::Elf => file.segments().map(|x| x.address()).min().unwrap_or(0),
+            _ => file.relative_address_base(),
+        };
+        let bias = module.base.wrapping_sub(lowest as usize);
+
+        ranges.extend(
+            file.symbols()
+                .chain(file.dynamic_symbols())
+                .filter(|x| x.kind() == SymbolKind::Text && x.size() != 0)
+                .filter(|x| {
+                    x.name_bytes()
+                        .ok()
+                        .and_then(|name| backtrace::SymbolName::new(name).as_str())
+                        .is_some_and(&matches)
+                })
+                .map(|x| {
+                    let start = bias.wrapping_add(x.address() as usize);
+                    start..start.wrapping_add(x.size() as usize)
+                }),
+        );
+    }
+
+    ranges.sort_by_key(|x| x.start);
+
+    let mut merged: Vec<Range<Address>> = Vec::new();
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+            _ => merged.push(range),
+        }
+    }
+
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[inline(never)]
+    fn find_functions_target() -> usize {
+        std::hint::black_box(0x1234)
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn find_functions_of_the_executable() {
+        let address = find_functions_target as *const () as usize;
+        assert_eq!(find_functions_target(), 0x1234);
+
+        let ranges = find_functions(|name| name.contains("find_functions_target"));
+        assert!(ranges.iter().any(|x| x.contains(&address)));
+
+        assert!(find_functions(|name| name.contains("no such function")).is_empty());
+    }
+
+    #[inline(never)]
+    fn resolve_target() -> usize {
+        std::hint::black_box(0x5678)
+    }
+
+    fn frame(instruction_pointer: usize, resolved: &[&str]) -> proto::BackTraceFrame {
+        proto::BackTraceFrame {
+            instruction_pointer: instruction_pointer as u64,
+            resolved_symbols: resolved
+                .iter()
+                .map(|x| proto::BackTraceSymbol {
+                    name: Some(x.to_string()),
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn resolved_symbols_are_cached() {
+        // Resolved as a return address, which points after the call.
+        let address = resolve_target as *const () as usize + 1;
+        assert_eq!(resolve_target(), 0x5678);
+
+        let mut symbols = SymbolCache::new();
+        let names: Vec<_> = symbols
+            .resolve(address)
+            .iter()
+            .filter_map(|x| x.name.clone())
+            .collect();
+        assert!(names.iter().any(|x| x.contains("resolve_target")));
+
+        let first = symbols.resolve(address).as_ptr();
+        assert_eq!(symbols.resolve(address).as_ptr(), first);
+        assert_eq!(symbols.symbols.len(), 1);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn symbolize_keeps_resolved_frames() {
+        let address = resolve_target as *const () as usize + 1;
+
+        let mut back_trace = proto::BackTrace {
+            frames: vec![frame(address, &[]), frame(address, &["resolved"])],
+        };
+        lock_symbols().symbolize(&mut back_trace, 1);
+
+        let names: Vec<Vec<_>> = back_trace
+            .frames
+            .iter()
+            .map(|x| {
+                x.resolved_symbols
+                    .iter()
+                    .map(|x| x.name.clone().unwrap())
+                    .collect()
+            })
+            .collect();
+        assert_eq!(names.len(), 2);
+        assert_eq!(names[0].len(), 1);
+        assert!(names[0][0].contains("resolve_target"));
+        assert_eq!(names[1], ["resolved"]);
+    }
+}
@@ -1,3 +1,6 @@
+// Vendored copy, lints are not maintained here.
+#![allow(warnings, clippy::all)]
+
 extern crate cc;
 
 use std::env;
@@ -96,6 +96,8 @@
 // When we're building as part of libstd, silence all warnings since they're
 // irrelevant as this crate is developed out-of-tree.
 #![cfg_attr(backtrace_in_libstd, allow(warnings))]
+// Same for the copy vendored into allocation-catcher, lints are not maintained here.
+#![allow(warnings, clippy::all)]
 #![cfg_attr(not(feature = "std"), allow(dead_code))]
 // We know this is deprecated, it's only here for back-compat reasons.
 #![cfg_attr(feature = "rustc-serialize", allow(deprecated))]
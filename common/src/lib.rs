@@ -14,4 +14,21 @@ pub enum PacketId {
     Find = 5,
     GetStatistics = 6,
     ResetStatistics = 7,
+    FindPage = 8,
+    Aggregate = 9,
+    TakeSnapshot = 10,
+    GetSnapshot = 11,
+    PutSnapshot = 12,
+    DeleteSnapshot = 13,
+    Diff = 14,
+    Subscribe = 15,
+    StartRecording = 16,
+    StopRecording = 17,
+    GetModules = 18,
+    ReadMemory = 19,
+    FindRetainers = 20,
+    LeakCheck = 21,
+    GetErrors = 22,
+    CheckHeap = 23,
+    SetFaults = 24,
 }
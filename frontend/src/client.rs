@@ -42,6 +42,109 @@ impl RequestSpec for proto::FindRequest {
     type RESPONSE = proto::FindResponse;
 }
 
+impl RequestSpec for proto::FindPageRequest {
+    const PACKET_ID: PacketId = PacketId::FindPage;
+
+    type RESPONSE = proto::FindPageResponse;
+}
+
+impl RequestSpec for proto::AggregateRequest {
+    const PACKET_ID: PacketId = PacketId::Aggregate;
+
+    type RESPONSE = proto::AggregateResponse;
+}
+
+impl RequestSpec for proto::TakeSnapshotRequest {
+    const PACKET_ID: PacketId = PacketId::TakeSnapshot;
+
+    type RESPONSE = proto::TakeSnapshotResponse;
+}
+
+impl RequestSpec for proto::GetSnapshotRequest {
+    const PACKET_ID: PacketId = PacketId::GetSnapshot;
+
+    type RESPONSE = proto::GetSnapshotResponse;
+}
+
+impl RequestSpec for proto::PutSnapshotRequest {
+    const PACKET_ID: PacketId = PacketId::PutSnapshot;
+
+    type RESPONSE = proto::PutSnapshotResponse;
+}
+
+impl RequestSpec for proto::DeleteSnapshotRequest {
+    const PACKET_ID: PacketId = PacketId::DeleteSnapshot;
+
+    type RESPONSE = proto::DeleteSnapshotResponse;
+}
+
+impl RequestSpec for proto::DiffRequest {
+    const PACKET_ID: PacketId = PacketId::Diff;
+
+    type RESPONSE = proto::DiffResponse;
+}
+
+// Responded with a stream of `EventBatch` messages.
+impl RequestSpec for proto::SubscribeRequest {
+    const PACKET_ID: PacketId = PacketId::Subscribe;
+
+    type RESPONSE = proto::EventBatch;
+}
+
+impl RequestSpec for proto::StartRecordingRequest {
+    const PACKET_ID: PacketId = PacketId::StartRecording;
+
+    type RESPONSE = proto::StartRecordingResponse;
+}
+
+impl RequestSpec for proto::StopRecordingRequest {
+    const PACKET_ID: PacketId = PacketId::StopRecording;
+
+    type RESPONSE = proto::StopRecordingResponse;
+}
+
+impl RequestSpec for proto::GetModulesRequest {
+    const PACKET_ID: PacketId = PacketId::GetModules;
+
+    type RESPONSE = proto::GetModulesResponse;
+}
+
+impl RequestSpec for proto::ReadMemoryRequest {
+    const PACKET_ID: PacketId = PacketId::ReadMemory;
+
+    type RESPONSE = proto::ReadMemoryResponse;
+}
+
+impl RequestSpec for proto::FindRetainersRequest {
+    const PACKET_ID: PacketId = PacketId::FindRetainers;
+
+    type RESPONSE = proto::FindRetainersResponse;
+}
+
+impl RequestSpec for proto::LeakCheckRequest {
+    const PACKET_ID: PacketId = PacketId::LeakCheck;
+
+    type RESPONSE = proto::LeakCheckResponse;
+}
+
+impl RequestSpec for proto::GetErrorsRequest {
+    const PACKET_ID: PacketId = PacketId::GetErrors;
+
+    type RESPONSE = proto::GetErrorsResponse;
+}
+
+impl RequestSpec for proto::CheckHeapRequest {
+    const PACKET_ID: PacketId = PacketId::CheckHeap;
+
+    type RESPONSE = proto::CheckHeapResponse;
+}
+
+impl RequestSpec for proto::SetFaultsRequest {
+    const PACKET_ID: PacketId = PacketId::SetFaults;
+
+    type RESPONSE = proto::SetFaultsResponse;
+}
+
 impl RequestSpec for proto::GetStatisticsRequest {
     const PACKET_ID: PacketId = PacketId::GetStatistics;
 
@@ -69,4 +172,8 @@ impl Client {
         buf.put(data);
         self.transport.request(buf.freeze())
     }
+
+    pub fn receive(&mut self) -> io::Result<Bytes> {
+        self.transport.receive()
+    }
 }
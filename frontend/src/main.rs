@@ -1,35 +1,147 @@
 mod client;
+mod replay;
+mod stacks;
+mod symbolize;
 mod transport;
 
 use std::{
+    cell::RefCell,
     io,
     net::{Ipv4Addr, SocketAddr, SocketAddrV4},
+    path::PathBuf,
     str::FromStr,
 };
 
 use anyhow::anyhow;
-use clap::{arg, error::ErrorKind, value_parser, ArgMatches, Command};
+use clap::{arg, error::ErrorKind, value_parser, ArgAction, ArgMatches, Command};
 
 use client::{proto, Client as TransportClient, RequestSpec};
+use stacks::Stacks;
+use symbolize::Symbolizer;
 
 pub struct Client {
     endpoint: SocketAddr,
+    symbolizer: Option<RefCell<Symbolizer>>,
 }
 
 impl Client {
     pub fn new(endpoint: SocketAddr) -> Self {
-        Self { endpoint }
+        Self {
+            endpoint,
+            symbolizer: None,
+        }
     }
 
-    pub fn send_request<T: RequestSpec>(&self, msg: T) -> io::Result<T::RESPONSE> {
+    pub fn with_symbolizer(self, symbolizer: Symbolizer) -> Self {
+        Self {
+            symbolizer: Some(RefCell::new(symbolizer)),
+            ..self
+        }
+    }
+
+    // Resolves the frames left unresolved by the target, if offline symbolization is enabled.
+    pub fn symbolize<'a>(
+        &self,
+        back_traces: impl Iterator<Item = &'a mut proto::BackTrace>,
+    ) -> io::Result<()> {
+        let Some(symbolizer) = self.symbolizer.as_ref() else {
+            return Ok(());
+        };
+
+        let mut symbolizer = symbolizer.borrow_mut();
+        if !symbolizer.has_modules() {
+            symbolizer.set_modules(self.send_request(proto::GetModulesRequest {})?.modules);
+        }
+
+        back_traces.for_each(|x| symbolizer.symbolize(x));
+        Ok(())
+    }
+
+    fn connect(&self) -> io::Result<TransportClient> {
         let transport = Box::new(
             transport::connect_tcp(self.endpoint)
                 .map_err(|_| io::Error::from(io::ErrorKind::ConnectionRefused))?,
         );
-        let mut client = TransportClient::new(transport);
+        Ok(TransportClient::new(transport))
+    }
+
+    pub fn send_request<T: RequestSpec>(&self, msg: T) -> io::Result<T::RESPONSE> {
+        let mut client = self.connect()?;
         let response_bytes = client.request(T::PACKET_ID, msg.encode_to_vec().into())?;
         Ok(<T::RESPONSE as prost::Message>::decode(response_bytes)?)
     }
+
+    // Back traces shared by many allocations are transferred only once.
+    pub fn find(&self, records: Vec<proto::FindRecord>) -> io::Result<Vec<proto::FoundAllocation>> {
+        let mut resp = self.send_request(proto::FindRequest {
+            records,
+            intern_stacks: true,
+        })?;
+
+        let stacks = Stacks::new(resp.stacks);
+        for found in resp.allocations.iter_mut() {
+            found.allocations.iter_mut().for_each(|x| stacks.expand(x));
+            found
+                .freed_allocations
+                .iter_mut()
+                .for_each(|x| stacks.expand_freed(x));
+        }
+
+        self.symbolize(resp.allocations.iter_mut().flat_map(|x| {
+            x.allocations
+                .iter_mut()
+                .flat_map(|x| x.back_trace.as_mut())
+                .chain(x.freed_allocations.iter_mut().flat_map(|x| {
+                    x.allocation
+                        .as_mut()
+                        .and_then(|x| x.back_trace.as_mut())
+                        .into_iter()
+                        .chain(x.free_back_trace.as_mut())
+                }))
+        }))?;
+
+        Ok(resp.allocations)
+    }
+
+    pub fn find_page(
+        &self,
+        record: proto::FindRecord,
+        cursor: u64,
+        page_size: u32,
+    ) -> io::Result<proto::FindPageResponse> {
+        let mut resp = self.send_request(proto::FindPageRequest {
+            record: Some(record),
+            cursor,
+            page_size,
+            intern_stacks: true,
+        })?;
+
+        let stacks = Stacks::new(std::mem::take(&mut resp.stacks));
+        resp.allocations.iter_mut().for_each(|x| stacks.expand(x));
+
+        self.symbolize(
+            resp.allocations
+                .iter_mut()
+                .flat_map(|x| x.back_trace.as_mut()),
+        )?;
+
+        Ok(resp)
+    }
+
+    // Calls `handle` for every response pushed by the server until it fails.
+    pub fn send_streaming_request<T: RequestSpec>(
+        &self,
+        msg: T,
+        mut handle: impl FnMut(T::RESPONSE) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        let mut client = self.connect()?;
+        let mut response_bytes = client.request(T::PACKET_ID, msg.encode_to_vec().into())?;
+
+        loop {
+            handle(<T::RESPONSE as prost::Message>::decode(response_bytes)?)?;
+            response_bytes = client.receive()?;
+        }
+    }
 }
 
 fn ping(client: &Client) -> anyhow::Result<()> {
@@ -51,15 +163,23 @@ fn clear(client: &Client) -> anyhow::Result<()> {
 }
 
 fn setcfg(_cmd: &mut Command, sub: &ArgMatches, client: &Client) -> anyhow::Result<()> {
-    client.send_request(proto::SetConfigurationRequest {
+    let resp = client.send_request(proto::SetConfigurationRequest {
         configuration: Some(proto::Configuration {
             stack_trace_offset: *sub.get_one("stoff").unwrap(),
             stack_trace_size: *sub.get_one("stsize").unwrap(),
             backtrace_frames_skip: *sub.get_one("btskip").unwrap(),
             backtrace_frames_count: *sub.get_one("btcount").unwrap(),
             backtrace_resolve_symbols_count: *sub.get_one("btsymbols").unwrap(),
+            freed_history_size: *sub.get_one("freedhist").unwrap(),
+            quarantine_size: *sub.get_one("quarantine").unwrap(),
+            poison_byte: sub.get_one("poison").copied(),
         }),
     })?;
+
+    for warning in resp.warnings {
+        println!("Warning: {warning}");
+    }
+
     println!("Done!");
     Ok(())
 }
@@ -70,43 +190,245 @@ fn getcfg(client: &Client) -> anyhow::Result<()> {
     Ok(())
 }
 
-fn dump(client: &Client) -> anyhow::Result<()> {
-    let resp = client.send_request(proto::FindRequest {
-        records: vec![proto::FindRecord {
-            id: 0,
-            filter: None,
-        }],
-    })?;
+// Inclusive bounds given on the command line to the half-open range of the protocol.
+fn range_predicate(
+    arg: &ArgMatches,
+    lower: &str,
+    upper: &str,
+    predicate: fn(proto::Range) -> proto::predicate::Predicate,
+) -> Option<proto::Predicate> {
+    let lower_bound = arg.get_one::<u64>(lower).copied();
+    let upper_bound = arg.get_one::<u64>(upper).copied();
+
+    if lower_bound.is_none() && upper_bound.is_none() {
+        return None;
+    }
+
+    Some(proto::Predicate {
+        predicate: Some(predicate(proto::Range {
+            lower: lower_bound.unwrap_or(0),
+            upper: upper_bound.map_or(u64::MAX, |x| x.saturating_add(1)),
+        })),
+    })
+}
+
+fn predicates(arg: &ArgMatches) -> Vec<proto::Predicate> {
+    use proto::predicate::Predicate;
+
+    let mut predicates: Vec<proto::Predicate> = [
+        range_predicate(arg, "minsize", "maxsize", Predicate::Size),
+        range_predicate(arg, "minseq", "maxseq", Predicate::Sequence),
+        range_predicate(arg, "since", "until", Predicate::Timestamp),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
 
-    assert_eq!(resp.allocations.len(), 1);
+    if let Some(&heap_handle) = arg.get_one::<u64>("heap") {
+        predicates.push(proto::Predicate {
+            predicate: Some(Predicate::HeapHandle(heap_handle)),
+        });
+    }
+
+    if let Some(&thread_id) = arg.get_one::<u64>("thread") {
+        predicates.push(proto::Predicate {
+            predicate: Some(Predicate::ThreadId(thread_id)),
+        });
+    }
+
+    if let Some(pattern) = arg.get_one::<String>("symbol") {
+        predicates.push(proto::Predicate {
+            predicate: Some(Predicate::Symbol(proto::SymbolPattern {
+                pattern: pattern.clone(),
+                regex: arg.get_flag("regex"),
+            })),
+        });
+    }
+
+    predicates
+}
 
-    print_allocations(&resp.allocations.first().unwrap().allocations);
+fn find_record(filter: proto::Filter, arg: &ArgMatches) -> proto::FindRecord {
+    let sort = arg.get_one::<String>("sort").map(|key| proto::Sort {
+        key: match key.as_str() {
+            "address" => proto::SortKey::Address,
+            "size" => proto::SortKey::Size,
+            "sequence" => proto::SortKey::Sequence,
+            "timestamp" => proto::SortKey::Timestamp,
+            _ => unreachable!(),
+        } as i32,
+        descending: arg.get_flag("desc"),
+    });
+
+    proto::FindRecord {
+        id: 0,
+        filter: Some(filter),
+        predicates: predicates(arg),
+        sort,
+        offset: *arg.get_one("offset").unwrap(),
+        limit: arg.get_one("limit").copied(),
+    }
+}
+
+fn find_and_print(
+    record: proto::FindRecord,
+    arg: &ArgMatches,
+    client: &Client,
+) -> anyhow::Result<()> {
+    let freed = record.filter.as_ref().is_some_and(|x| x.freed);
+
+    // Only sorting needs the whole result at once.
+    if !freed && record.sort.is_none() {
+        return find_paged(record, arg, client);
+    }
+
+    let allocations = client.find(vec![record])?;
+
+    assert_eq!(allocations.len(), 1);
+
+    let found = allocations.first().unwrap();
+    if freed {
+        print_freed_allocations(&found.freed_allocations);
+    } else {
+        print_allocations(&found.allocations);
+    }
+
+    Ok(())
+}
+
+fn find_paged(
+    mut record: proto::FindRecord,
+    arg: &ArgMatches,
+    client: &Client,
+) -> anyhow::Result<()> {
+    let page_size = *arg.get_one::<u32>("pagesize").unwrap();
+
+    // Pages are not aware of the offset and limit, apply them while printing.
+    let first = std::mem::take(&mut record.offset);
+    let end = record
+        .limit
+        .take()
+        .map_or(u64::MAX, |x| first.saturating_add(x));
+
+    let mut index = 0;
+    let mut cursor = Some(0);
+
+    while let Some(current) = cursor {
+        if index >= end {
+            break;
+        }
+
+        let resp = client.find_page(record.clone(), current, page_size)?;
+
+        for allocation in resp.allocations.iter() {
+            if index >= end {
+                break;
+            }
+            if index >= first {
+                print_allocation(allocation);
+            }
+            index += 1;
+        }
+
+        cursor = resp.next_cursor;
+    }
+
+    if index <= first {
+        println!("No allocations found.");
+    }
 
     Ok(())
 }
 
+fn dump(arg: &ArgMatches, client: &Client) -> anyhow::Result<()> {
+    let freed = arg.get_flag("freed");
+
+    find_and_print(
+        find_record(
+            proto::Filter {
+                location: None,
+                freed,
+            },
+            arg,
+        ),
+        arg,
+        client,
+    )
+}
+
 fn print_allocation(allocation: &proto::Allocation) {
     println!(
-        "Allocation: [base=0x{:X},size=0x{:X}({})]",
-        allocation.base_address, allocation.size, allocation.size
+        "Allocation: [base=0x{:X},size=0x{:X}({}),seq={},thread={},time={:.6}s]",
+        allocation.base_address,
+        allocation.size,
+        allocation.size,
+        allocation.sequence,
+        allocation.thread_id,
+        allocation.timestamp as f64 / 1e9
     );
-    if let Some(stacktrace) = allocation.stack_trace.as_ref() {
+    print_traces(
+        allocation.stack_trace.as_ref(),
+        allocation.back_trace.as_ref(),
+    );
+}
+
+fn print_freed_allocation(freed_allocation: &proto::FreedAllocation) {
+    if let Some(allocation) = freed_allocation.allocation.as_ref() {
+        print_allocation(allocation);
+    }
+    println!(
+        "Freed: [thread={},time={:.6}s]",
+        freed_allocation.free_thread_id,
+        freed_allocation.free_timestamp as f64 / 1e9
+    );
+    print_traces(
+        freed_allocation.free_stack_trace.as_ref(),
+        freed_allocation.free_back_trace.as_ref(),
+    );
+}
+
+// `module+0xoffset`, stable across runs unlike the instruction pointer.
+fn module_offset(frame: &proto::BackTraceFrame) -> String {
+    let base = frame.module_base.unwrap_or_default();
+
+    match frame.module.as_ref() {
+        Some(module) => format!("{module}+0x{:X}", frame.instruction_pointer - base),
+        None => format!("{base:X}"),
+    }
+}
+
+// ` at file:line:column`, the form editors and terminals recognize as a link.
+fn source_location(symbol: &proto::BackTraceSymbol) -> String {
+    let Some(filename) = symbol.filename.as_ref() else {
+        return String::new();
+    };
+
+    match (symbol.lineno, symbol.colno) {
+        (Some(lineno), Some(colno)) => format!(" at {filename}:{lineno}:{colno}"),
+        (Some(lineno), None) => format!(" at {filename}:{lineno}"),
+        _ => format!(" at {filename}"),
+    }
+}
+
+fn print_traces(stacktrace: Option<&proto::StackTrace>, backtrace: Option<&proto::BackTrace>) {
+    if let Some(stacktrace) = stacktrace {
         println!("Stack trace: {:X?}", stacktrace.trace);
     }
-    if let Some(backtrace) = allocation.back_trace.as_ref() {
+    if let Some(backtrace) = backtrace {
         println!("Back trace: ");
         for frame in backtrace.frames.iter() {
             println!(
-                " - ip: {:X}, sp: {:X} mod: {:X}. sym: {}",
+                " - ip: {:X}, sp: {:X} mod: {}. sym: {}",
                 frame.instruction_pointer,
                 frame.stack_pointer,
-                frame.module_base.unwrap_or_default(),
+                module_offset(frame),
                 {
                     if let Some(sym) = frame.resolved_symbols.first() {
                         format!(
-                            "{} @  0x{:X}",
+                            "{} @  0x{:X}{}",
                             sym.name.as_ref().unwrap_or(&"".to_owned()),
-                            sym.address.unwrap_or_default()
+                            sym.address.unwrap_or_default(),
+                            source_location(sym)
                         )
                     } else {
                         "-".to_owned()
@@ -127,24 +449,76 @@ fn print_allocations(allocations: &Vec<proto::Allocation>) {
     }
 }
 
+fn print_freed_allocations(freed_allocations: &Vec<proto::FreedAllocation>) {
+    if freed_allocations.is_empty() {
+        println!("No freed allocations found.");
+    } else {
+        for freed_allocation in freed_allocations {
+            print_freed_allocation(freed_allocation);
+        }
+    }
+}
+
 fn find(arg: &ArgMatches, client: &Client) -> anyhow::Result<()> {
     let address = *arg.get_one::<u64>("address").unwrap();
+    let freed = arg.get_flag("freed");
     println!("Address: 0x{address:X}");
 
-    let resp = client.send_request(proto::FindRequest {
-        records: vec![proto::FindRecord {
-            id: 0,
-            filter: Some(proto::Filter {
-                location: Some(proto::filter::Location::Address(address)),
-            }),
-        }],
-    })?;
+    let allocations = client.find(vec![proto::FindRecord {
+        id: 0,
+        filter: Some(proto::Filter {
+            location: Some(proto::filter::Location::Address(address)),
+            freed,
+        }),
+        ..Default::default()
+    }])?;
 
-    assert_eq!(resp.allocations.len(), 1);
+    assert_eq!(allocations.len(), 1);
 
-    let allocations = &resp.allocations.first().unwrap().allocations;
+    let found = allocations.first().unwrap();
 
-    if let Some(allocation) = allocations.first() {
+    if freed {
+        // Every time the address has been freed, most recent first.
+        print_freed_allocations(&found.freed_allocations);
+    } else if let Some(allocation) = found.allocations.first() {
+        print_allocation(allocation);
+    } else {
+        println!("No allocation found.");
+    }
+
+    Ok(())
+}
+
+fn whose(arg: &ArgMatches, client: &Client) -> anyhow::Result<()> {
+    let address = *arg.get_one::<u64>("address").unwrap();
+    let freed = arg.get_flag("freed");
+    println!("Address: 0x{address:X}");
+
+    let allocations = client.find(vec![proto::FindRecord {
+        id: 0,
+        filter: Some(proto::Filter {
+            location: Some(proto::filter::Location::Containing(address)),
+            freed,
+        }),
+        ..Default::default()
+    }])?;
+
+    assert_eq!(allocations.len(), 1);
+
+    let found = allocations.first().unwrap();
+
+    if freed {
+        for freed_allocation in found.freed_allocations.iter() {
+            if let Some(allocation) = freed_allocation.allocation.as_ref() {
+                println!("Offset: 0x{:X}", address - allocation.base_address);
+            }
+            print_freed_allocation(freed_allocation);
+        }
+        if found.freed_allocations.is_empty() {
+            println!("No freed allocations found.");
+        }
+    } else if let Some(allocation) = found.allocations.first() {
+        println!("Offset: 0x{:X}", address - allocation.base_address);
         print_allocation(allocation);
     } else {
         println!("No allocation found.");
@@ -156,6 +530,7 @@ fn find(arg: &ArgMatches, client: &Client) -> anyhow::Result<()> {
 fn findrange(cmd: &mut Command, arg: &ArgMatches, client: &Client) -> anyhow::Result<()> {
     let lower = *arg.get_one::<u64>("lower").unwrap();
     let upper = *arg.get_one::<u64>("upper").unwrap();
+    let freed = arg.get_flag("freed");
 
     if lower > upper {
         return Err(cmd
@@ -168,21 +543,687 @@ fn findrange(cmd: &mut Command, arg: &ArgMatches, client: &Client) -> anyhow::Re
 
     println!("Range: 0x{lower:X}-0x{upper:X}");
 
-    let resp = client.send_request(proto::FindRequest {
-        records: vec![proto::FindRecord {
-            id: 0,
-            filter: Some(proto::Filter {
+    find_and_print(
+        find_record(
+            proto::Filter {
                 location: Some(proto::filter::Location::Range(proto::Range {
                     lower,
                     upper,
                 })),
-            }),
-        }],
+                freed,
+            },
+            arg,
+        ),
+        arg,
+        client,
+    )
+}
+
+fn group_by(arg: &ArgMatches) -> proto::GroupBy {
+    match arg.get_one::<String>("by").unwrap().as_str() {
+        "backtrace" => proto::GroupBy::BackTrace,
+        "stacktrace" => proto::GroupBy::StackTrace,
+        _ => unreachable!(),
+    }
+}
+
+fn print_group_example(group_by: proto::GroupBy, key: &[u64], example: Option<&proto::Allocation>) {
+    if let Some(example) = example {
+        println!("Example: 0x{:X}", example.base_address);
+        match group_by {
+            proto::GroupBy::BackTrace => print_traces(None, example.back_trace.as_ref()),
+            proto::GroupBy::StackTrace => println!("Key: {:X?}", key),
+        }
+    }
+}
+
+fn top(arg: &ArgMatches, client: &Client) -> anyhow::Result<()> {
+    let group_by = group_by(arg);
+
+    let mut resp = client.send_request(proto::AggregateRequest {
+        group_by: group_by as i32,
+        depth: *arg.get_one("depth").unwrap(),
+        predicates: predicates(arg),
+        limit: Some(*arg.get_one("limit").unwrap()),
+    })?;
+
+    client.symbolize(
+        resp.groups
+            .iter_mut()
+            .flat_map(|x| x.example.as_mut().and_then(|x| x.back_trace.as_mut())),
+    )?;
+
+    if resp.groups.is_empty() {
+        println!("No allocations found.");
+    }
+
+    for (index, group) in resp.groups.iter().enumerate() {
+        println!(
+            "#{}: {} bytes in {} allocations [min=0x{:X},max=0x{:X}]",
+            index + 1,
+            group.total_size,
+            group.count,
+            group.min_size,
+            group.max_size
+        );
+        print_group_example(group_by, &group.key, group.example.as_ref());
+    }
+
+    Ok(())
+}
+
+fn snapshot(sub: &ArgMatches, client: &Client) -> anyhow::Result<()> {
+    match sub.subcommand().unwrap() {
+        ("take", arg) => {
+            let name = arg.get_one::<String>("name").unwrap();
+            let resp = client.send_request(proto::TakeSnapshotRequest { name: name.clone() })?;
+            println!("Snapshot {name}: {} allocations", resp.count);
+
+            if let Some(file) = arg.get_one::<String>("save") {
+                save_snapshot(name, file, client)?;
+            }
+        }
+        ("save", arg) => save_snapshot(
+            arg.get_one::<String>("name").unwrap(),
+            arg.get_one::<String>("file").unwrap(),
+            client,
+        )?,
+        ("load", arg) => {
+            let name = arg.get_one::<String>("name").unwrap();
+            let data = std::fs::read(arg.get_one::<String>("file").unwrap())?;
+            let snapshot = <proto::Snapshot as prost::Message>::decode(data.as_slice())?;
+            println!(
+                "Snapshot {name}: {} allocations",
+                snapshot.allocations.len()
+            );
+            client.send_request(proto::PutSnapshotRequest {
+                name: name.clone(),
+                snapshot: Some(snapshot),
+            })?;
+        }
+        ("delete", arg) => {
+            client.send_request(proto::DeleteSnapshotRequest {
+                name: arg.get_one::<String>("name").unwrap().clone(),
+            })?;
+            println!("Done!");
+        }
+        _ => unreachable!(),
+    }
+
+    Ok(())
+}
+
+fn save_snapshot(name: &str, file: &str, client: &Client) -> anyhow::Result<()> {
+    let resp = client.send_request(proto::GetSnapshotRequest {
+        name: name.to_owned(),
+    })?;
+    let snapshot = resp
+        .snapshot
+        .ok_or_else(|| anyhow!("no snapshot field present"))?;
+    std::fs::write(file, prost::Message::encode_to_vec(&snapshot))?;
+    println!("Saved to {file}");
+    Ok(())
+}
+
+fn diff(arg: &ArgMatches, client: &Client) -> anyhow::Result<()> {
+    let group_by = group_by(arg);
+
+    let mut resp = client.send_request(proto::DiffRequest {
+        from: arg.get_one::<String>("from").unwrap().clone(),
+        to: arg.get_one::<String>("to").unwrap().clone(),
+        group_by: group_by as i32,
+        depth: *arg.get_one("depth").unwrap(),
+        limit: Some(*arg.get_one("limit").unwrap()),
+    })?;
+
+    client.symbolize(
+        resp.groups
+            .iter_mut()
+            .flat_map(|x| x.example.as_mut().and_then(|x| x.back_trace.as_mut())),
+    )?;
+
+    if resp.groups.is_empty() {
+        println!("No difference.");
+    }
+
+    for (index, group) in resp.groups.iter().enumerate() {
+        let growth = group.added_size as i64 - group.removed_size as i64 + group.resized_delta;
+        println!(
+            "#{}: {:+} bytes [added={}({} bytes),removed={}({} bytes),resized={}({:+} bytes)]",
+            index + 1,
+            growth,
+            group.added_count,
+            group.added_size,
+            group.removed_count,
+            group.removed_size,
+            group.resized_count,
+            group.resized_delta
+        );
+        print_group_example(group_by, &group.key, group.example.as_ref());
+    }
+
+    Ok(())
+}
+
+fn watch(arg: &ArgMatches, client: &Client) -> anyhow::Result<()> {
+    let range = |lower: &str, upper: &str| {
+        let lower_bound = arg.get_one::<u64>(lower).copied();
+        let upper_bound = arg.get_one::<u64>(upper).copied();
+
+        (lower_bound.is_some() || upper_bound.is_some()).then(|| proto::Range {
+            lower: lower_bound.unwrap_or(0),
+            upper: upper_bound.map_or(u64::MAX, |x| x.saturating_add(1)),
+        })
+    };
+
+    let req = proto::SubscribeRequest {
+        size: range("minsize", "maxsize"),
+        heap_handle: arg.get_one::<u64>("heap").copied(),
+        address: range("lower", "upper"),
+        errors: arg.get_flag("errors"),
+    };
+
+    client.send_streaming_request(req, |mut batch| {
+        if batch.dropped != 0 {
+            println!("... {} events dropped", batch.dropped);
+        }
+
+        for event in batch.events.iter() {
+            print_event(event);
+        }
+
+        if batch.dropped_errors != 0 {
+            println!("... {} errors dropped", batch.dropped_errors);
+        }
+
+        client.symbolize(batch.errors.iter_mut().flat_map(heap_error_back_traces))?;
+        for error in batch.errors.iter() {
+            print_heap_error(error);
+        }
+
+        Ok(())
+    })
+}
+
+fn heap_error_back_traces(
+    error: &mut proto::HeapError,
+) -> impl Iterator<Item = &mut proto::BackTrace> {
+    let previous_free = error.previous_free.as_mut();
+
+    error
+        .back_trace
+        .as_mut()
+        .into_iter()
+        .chain(
+            error
+                .allocation
+                .as_mut()
+                .and_then(|x| x.back_trace.as_mut()),
+        )
+        .chain(previous_free.into_iter().flat_map(|x| {
+            x.allocation
+                .as_mut()
+                .and_then(|x| x.back_trace.as_mut())
+                .into_iter()
+                .chain(x.free_back_trace.as_mut())
+        }))
+}
+
+fn print_heap_error(error: &proto::HeapError) {
+    let kind = match error.kind() {
+        proto::HeapErrorKind::InvalidFree => "invalid free",
+        proto::HeapErrorKind::DoubleFree => "double free",
+        proto::HeapErrorKind::Corruption => "corruption",
+        proto::HeapErrorKind::WriteAfterFree => "write after free",
+        proto::HeapErrorKind::InjectedFailure => "injected failure",
+    };
+    let target = match error.injected.as_ref() {
+        Some(injected) => format!("0x{:X}({}) bytes", injected.size, injected.size),
+        None => format!("0x{:X}", error.address),
+    };
+    println!(
+        "[{:.6}s] thread={} {kind} of {target} heap=0x{:X}",
+        error.timestamp as f64 / 1e9,
+        error.thread_id,
+        error.heap_handle
+    );
+    if let Some(injected) = error.injected.as_ref() {
+        println!("Failed by rule #{}", injected.rule);
+    }
+    print_traces(error.stack_trace.as_ref(), error.back_trace.as_ref());
+
+    if let Some(damage) = error.damage.as_ref() {
+        let offset = match damage.offset {
+            offset if offset < 0 => format!("-0x{:X}", offset.unsigned_abs()),
+            offset => format!("+0x{offset:X}"),
+        };
+        match error.kind() {
+            proto::HeapErrorKind::WriteAfterFree => println!("Poison modified at {offset}:"),
+            _ => println!("Damaged at {offset}, redzone:"),
+        }
+        print_hexdump(damage.address, &damage.data, 1);
+    }
+
+    if let Some(allocation) = error.allocation.as_ref() {
+        if error.address != allocation.base_address {
+            println!(
+                "Inside the allocation at +0x{:X}:",
+                error.address - allocation.base_address
+            );
+        }
+        print_allocation(allocation);
+    }
+    if let Some(previous_free) = error.previous_free.as_ref() {
+        println!("Previously freed:");
+        print_freed_allocation(previous_free);
+    }
+}
+
+fn errors(arg: &ArgMatches, client: &Client) -> anyhow::Result<()> {
+    let mut resp = client.send_request(proto::GetErrorsRequest {
+        cursor: *arg.get_one("cursor").unwrap(),
+        limit: Some(*arg.get_one("limit").unwrap()),
     })?;
+    client.symbolize(resp.errors.iter_mut().flat_map(heap_error_back_traces))?;
 
-    assert_eq!(resp.allocations.len(), 1);
+    if resp.dropped != 0 {
+        println!("... {} errors dropped", resp.dropped);
+    }
+
+    if resp.errors.is_empty() {
+        println!("No errors found.");
+    }
+
+    for error in resp.errors.iter() {
+        print_heap_error(error);
+    }
+
+    println!("Next cursor: {}", resp.next_cursor);
+
+    Ok(())
+}
+
+fn check_heap(arg: &ArgMatches, client: &Client) -> anyhow::Result<()> {
+    let mut resp = client.send_request(proto::CheckHeapRequest {
+        limit: Some(*arg.get_one("limit").unwrap()),
+    })?;
+    client.symbolize(resp.errors.iter_mut().flat_map(heap_error_back_traces))?;
+
+    println!(
+        "Checked {} allocations with redzones and {} quarantined blocks.",
+        resp.checked, resp.quarantined
+    );
+
+    for error in resp.errors.iter() {
+        print_heap_error(error);
+    }
+
+    Ok(())
+}
+
+fn faults(arg: &ArgMatches, client: &Client) -> anyhow::Result<()> {
+    use proto::fault_rule::Condition;
+
+    let mut conditions = Vec::new();
+
+    if let Some(&n) = arg.get_one::<u64>("every") {
+        conditions.push(Condition::Every(n));
+    }
+
+    if let Some(&p) = arg.get_one::<f64>("probability") {
+        conditions.push(Condition::Probability(p));
+    }
+
+    if let Some(&size) = arg.get_one::<u64>("larger") {
+        conditions.push(Condition::LargerThan(size));
+    }
+
+    if let Some(pattern) = arg.get_one::<String>("symbol") {
+        conditions.push(Condition::Symbol(proto::SymbolPattern {
+            pattern: pattern.clone(),
+            regex: arg.get_flag("regex"),
+        }));
+    }
+
+    if let Some(&budget) = arg.get_one::<u64>("budget") {
+        conditions.push(Condition::Budget(budget));
+    }
+
+    for (index, condition) in conditions.iter().enumerate() {
+        println!("Rule #{index}: {condition:?}");
+    }
+
+    if conditions.is_empty() {
+        println!("Allocations are no longer failed.");
+    }
+
+    let resp = client.send_request(proto::SetFaultsRequest {
+        rules: conditions
+            .into_iter()
+            .map(|x| proto::FaultRule { condition: Some(x) })
+            .collect(),
+        seed: *arg.get_one("seed").unwrap(),
+    })?;
+
+    for warning in resp.warnings {
+        println!("Warning: {warning}");
+    }
+
+    Ok(())
+}
+
+fn print_event(event: &proto::Event) {
+    let time = event.timestamp as f64 / 1e9;
+
+    match proto::EventKind::try_from(event.kind) {
+        Ok(proto::EventKind::Allocation) => println!(
+            "[{time:.6}s] thread={} alloc 0x{:X} size=0x{:X}({}) heap=0x{:X} seq={}",
+            event.thread_id,
+            event.base_address,
+            event.size,
+            event.size,
+            event.heap_handle,
+            event.sequence
+        ),
+        Ok(proto::EventKind::Reallocation) => println!(
+            "[{time:.6}s] thread={} realloc 0x{:X} -> 0x{:X} size=0x{:X}({}) heap=0x{:X} seq={}",
+            event.thread_id,
+            event.previous_address.unwrap_or_default(),
+            event.base_address,
+            event.size,
+            event.size,
+            event.heap_handle,
+            event.sequence
+        ),
+        Ok(proto::EventKind::Deallocation) => println!(
+            "[{time:.6}s] thread={} free 0x{:X} size=0x{:X}({}) heap=0x{:X} seq={}",
+            event.thread_id,
+            event.base_address,
+            event.size,
+            event.size,
+            event.heap_handle,
+            event.sequence
+        ),
+        Err(_) => println!("[{time:.6}s] unknown event {}", event.kind),
+    }
+}
+
+fn record(sub: &ArgMatches, client: &Client) -> anyhow::Result<()> {
+    match sub.subcommand().unwrap() {
+        ("start", arg) => {
+            client.send_request(proto::StartRecordingRequest {
+                path: arg.get_one::<String>("path").unwrap().clone(),
+            })?;
+            println!("Done!");
+        }
+        ("stop", _) => {
+            let resp = client.send_request(proto::StopRecordingRequest {})?;
+            println!("Recorded {} events, {} dropped", resp.events, resp.dropped);
+        }
+        _ => unreachable!(),
+    }
+
+    Ok(())
+}
+
+fn replay(arg: &ArgMatches) -> anyhow::Result<()> {
+    let data = std::fs::read(arg.get_one::<String>("file").unwrap())?;
+    let until = arg
+        .get_one::<f64>("at")
+        .map(|seconds| (seconds * 1e9) as u64);
+    let limit = *arg.get_one::<usize>("limit").unwrap();
+
+    let replay = replay::replay(&data, until)?;
+
+    println!(
+        "Events: {}, dropped: {}{}",
+        replay.events,
+        replay.dropped,
+        if replay.truncated { " (truncated)" } else { "" }
+    );
+    println!(
+        "Live at {:.6}s: {} bytes in {} allocations",
+        replay.timestamp as f64 / 1e9,
+        replay.live_size,
+        replay.live_count
+    );
+    println!(
+        "Peak at {:.6}s: {} bytes",
+        replay.peak_timestamp as f64 / 1e9,
+        replay.peak_size
+    );
+
+    let mut call_sites: Vec<_> = replay.call_sites.iter().collect();
+    call_sites.sort_by_key(|(_, x)| std::cmp::Reverse((x.live_size, x.total_size)));
+
+    for (index, (stack_id, call_site)) in call_sites.into_iter().take(limit).enumerate() {
+        println!(
+            "#{}: live {} bytes in {} allocations, allocated {} bytes in {} allocations",
+            index + 1,
+            call_site.live_size,
+            call_site.live_count,
+            call_site.total_size,
+            call_site.total_count
+        );
+        match replay.stacks.get(stack_id) {
+            Some(frames) => print_traces(
+                None,
+                Some(&proto::BackTrace {
+                    frames: frames.clone(),
+                }),
+            ),
+            None => println!("No back trace"),
+        }
+    }
+
+    Ok(())
+}
+
+// Rows of 16 bytes as bytes or as words of the target, with an ASCII column.
+fn print_hexdump(address: u64, data: &[u8], wordsize: usize) {
+    const ROW_SIZE: usize = 16;
+
+    for (index, row) in data.chunks(ROW_SIZE).enumerate() {
+        let mut line = format!("0x{:X}:", address + (index * ROW_SIZE) as u64);
+
+        if wordsize > 1 {
+            for word in row.chunks(wordsize) {
+                // Words are little endian, a partial word is printed as bytes.
+                if word.len() == wordsize {
+                    let value = word
+                        .iter()
+                        .rev()
+                        .fold(0u64, |value, &x| (value << 8) | x as u64);
+                    line += &format!(" {value:0width$X}", width = wordsize * 2);
+                } else {
+                    word.iter().for_each(|x| line += &format!(" {x:02X}"));
+                }
+            }
+        } else {
+            row.iter().for_each(|x| line += &format!(" {x:02X}"));
+        }
+
+        let ascii: String = row
+            .iter()
+            .map(|&x| {
+                if x.is_ascii_graphic() || x == b' ' {
+                    x as char
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+
+        // Align the ASCII column of the last row.
+        let width = if wordsize > 1 {
+            ROW_SIZE / wordsize * (wordsize * 2 + 1)
+        } else {
+            ROW_SIZE * 3
+        };
+        let padding = (width + line.find(':').unwrap() + 1).saturating_sub(line.len());
+        println!("{line}{}  |{ascii}|", " ".repeat(padding));
+    }
+}
+
+fn retainers(arg: &ArgMatches, client: &Client) -> anyhow::Result<()> {
+    let address = *arg.get_one::<u64>("address").unwrap();
+
+    let mut resp = client.send_request(proto::FindRetainersRequest {
+        address,
+        scan_stacks: arg.get_flag("stacks"),
+        scan_modules: arg.get_flag("modules"),
+        limit: Some(*arg.get_one("limit").unwrap()),
+    })?;
+    client.symbolize(
+        resp.target
+            .iter_mut()
+            .chain(resp.retainers.iter_mut().flat_map(|x| x.owner.as_mut()))
+            .flat_map(|x| x.back_trace.as_mut()),
+    )?;
+
+    if let Some(target) = resp.target.as_ref() {
+        print_allocation(target);
+    }
+
+    print_skipped_threads(&resp.skipped_threads);
+
+    if resp.retainers.is_empty() {
+        println!("No retainers found.");
+    }
+
+    for (index, retainer) in resp.retainers.iter().enumerate() {
+        let location = match retainer.kind() {
+            proto::RetainerKind::Heap => "heap".to_owned(),
+            proto::RetainerKind::Stack => format!("stack of thread {}", retainer.thread_id),
+            proto::RetainerKind::Module => format!("data of {}", retainer.module),
+        };
+        println!(
+            "#{}: 0x{:X} in {location} at +0x{:X} points to +0x{:X}",
+            index + 1,
+            retainer.address,
+            retainer.offset,
+            retainer.target_offset
+        );
+        if let Some(owner) = retainer.owner.as_ref() {
+            print_allocation(owner);
+        }
+    }
+
+    Ok(())
+}
+
+fn print_skipped_threads(thread_ids: &[u64]) {
+    if !thread_ids.is_empty() {
+        let thread_ids: Vec<_> = thread_ids.iter().map(|x| x.to_string()).collect();
+        println!(
+            "Warning: the stacks of threads {} were not scanned, the result is incomplete",
+            thread_ids.join(", ")
+        );
+    }
+}
+
+fn leaks(arg: &ArgMatches, client: &Client) -> anyhow::Result<()> {
+    let group_by = group_by(arg);
 
-    print_allocations(&resp.allocations.first().unwrap().allocations);
+    let mut resp = client.send_request(proto::LeakCheckRequest {
+        group_by: group_by as i32,
+        depth: *arg.get_one("depth").unwrap(),
+        limit: Some(*arg.get_one("limit").unwrap()),
+    })?;
+
+    client.symbolize(
+        resp.definitely_lost
+            .iter_mut()
+            .chain(resp.indirectly_lost.iter_mut())
+            .chain(resp.possibly_lost.iter_mut())
+            .flat_map(|x| x.example.as_mut().and_then(|x| x.back_trace.as_mut())),
+    )?;
+
+    let total = |groups: &[proto::AllocationGroup]| {
+        groups.iter().fold((0, 0), |(count, size), x| {
+            (count + x.count, size + x.total_size)
+        })
+    };
+    let (definitely_count, definitely_size) = total(&resp.definitely_lost);
+    let (indirectly_count, indirectly_size) = total(&resp.indirectly_lost);
+    let (possibly_count, possibly_size) = total(&resp.possibly_lost);
+
+    println!(
+        "Reachable: {} bytes in {} allocations",
+        resp.reachable_size, resp.reachable_count
+    );
+    println!("Definitely lost: {definitely_size} bytes in {definitely_count} allocations");
+    println!("Indirectly lost: {indirectly_size} bytes in {indirectly_count} allocations");
+    println!("Possibly lost: {possibly_size} bytes in {possibly_count} allocations");
+    print_skipped_threads(&resp.skipped_threads);
+
+    for (kind, groups) in [
+        ("definitely", &resp.definitely_lost),
+        ("indirectly", &resp.indirectly_lost),
+        ("possibly", &resp.possibly_lost),
+    ] {
+        for (index, group) in groups.iter().enumerate() {
+            println!(
+                "#{}: {} bytes in {} allocations {kind} lost [min=0x{:X},max=0x{:X}]",
+                index + 1,
+                group.total_size,
+                group.count,
+                group.min_size,
+                group.max_size
+            );
+            print_group_example(group_by, &group.key, group.example.as_ref());
+        }
+    }
+
+    Ok(())
+}
+
+fn hexdump(arg: &ArgMatches, client: &Client) -> anyhow::Result<()> {
+    let address = *arg.get_one::<u64>("address").unwrap();
+
+    let resp = client.send_request(proto::ReadMemoryRequest {
+        address,
+        size: arg.get_one::<u64>("size").copied(),
+    })?;
+
+    if let Some(allocation) = resp.allocation.as_ref() {
+        print_allocation(allocation);
+    } else {
+        println!("Address is not in a tracked allocation.");
+    }
+
+    let wordsize = if arg.get_flag("words") {
+        client.send_request(proto::PingRequest { num: 0 })?.wordsize as usize
+    } else {
+        1
+    };
+
+    print_hexdump(resp.address, &resp.data, wordsize);
+
+    if resp.data.is_empty() {
+        println!("Memory is not readable.");
+    }
+
+    Ok(())
+}
+
+fn modules(client: &Client) -> anyhow::Result<()> {
+    let resp = client.send_request(proto::GetModulesRequest {})?;
+
+    for module in resp.modules.iter() {
+        let build_id: String = module.build_id.iter().map(|x| format!("{x:02x}")).collect();
+        println!(
+            "0x{:X}-0x{:X} {} [build-id={}{}]",
+            module.base,
+            module.base + module.size,
+            module.path,
+            if build_id.is_empty() { "-" } else { &build_id },
+            module
+                .debug_file
+                .as_ref()
+                .map_or(String::new(), |x| format!(",pdb={x}"))
+        );
+    }
 
     Ok(())
 }
@@ -206,25 +1247,50 @@ fn resetstat(client: &Client) -> anyhow::Result<()> {
 fn run(mut cmd: Command) -> anyhow::Result<()> {
     let matches = cmd.get_matches_mut();
 
+    // Works offline, without the target process.
+    if let Some(("replay", sub)) = matches.subcommand() {
+        return replay(sub);
+    }
+
     let host = matches
         .get_one::<String>("host")
         .map(String::as_str)
-        .unwrap_or(&"127.0.0.1");
-    let port = matches.get_one::<u16>("port").map(|&x| x).unwrap_or(9940);
+        .unwrap_or("127.0.0.1");
+    let port = matches.get_one::<u16>("port").copied().unwrap_or(9940);
     let endpoint = SocketAddr::V4(SocketAddrV4::new(
         Ipv4Addr::from_str(host).map_err(|_| anyhow!("Could not parse IPv4"))?,
         port,
     ));
-    let client = &Client::new(endpoint);
+    let mut client = Client::new(endpoint);
+    if matches.get_flag("symbolize") {
+        let search_path = matches
+            .get_many::<PathBuf>("symbols")
+            .map_or(Vec::new(), |x| x.cloned().collect());
+        client = client.with_symbolizer(Symbolizer::new(search_path));
+    }
+    let client = &client;
 
     match matches.subcommand().unwrap() {
         ("ping", _) => ping(client)?,
         ("clear", _) => clear(client)?,
         ("setcfg", sub) => setcfg(&mut cmd, sub, client)?,
         ("getcfg", _) => getcfg(client)?,
-        ("dump", _) => dump(client)?,
+        ("dump", sub) => dump(sub, client)?,
         ("find", sub) => find(sub, client)?,
+        ("whose", sub) => whose(sub, client)?,
         ("findrange", sub) => findrange(&mut cmd, sub, client)?,
+        ("top", sub) => top(sub, client)?,
+        ("snapshot", sub) => snapshot(sub, client)?,
+        ("diff", sub) => diff(sub, client)?,
+        ("watch", sub) => watch(sub, client)?,
+        ("errors", sub) => errors(sub, client)?,
+        ("checkheap", sub) => check_heap(sub, client)?,
+        ("faults", sub) => faults(sub, client)?,
+        ("record", sub) => record(sub, client)?,
+        ("hexdump", sub) => hexdump(sub, client)?,
+        ("retainers", sub) => retainers(sub, client)?,
+        ("leaks", sub) => leaks(sub, client)?,
+        ("modules", _) => modules(client)?,
         ("getstat", _) => getstat(client)?,
         ("resetstat", _) => resetstat(client)?,
         _ => unreachable!(),
@@ -234,8 +1300,8 @@ fn run(mut cmd: Command) -> anyhow::Result<()> {
 }
 
 fn parse_hex_address(s: &str) -> Result<u64, clap::Error> {
-    if s.starts_with("0x") {
-        if let Ok(x) = u64::from_str_radix(&s[2..], 16) {
+    if let Some(hex) = s.strip_prefix("0x") {
+        if let Ok(x) = u64::from_str_radix(hex, 16) {
             return Ok(x);
         }
     }
@@ -243,6 +1309,62 @@ fn parse_hex_address(s: &str) -> Result<u64, clap::Error> {
     Err(clap::Error::new(clap::error::ErrorKind::ValueValidation))
 }
 
+fn predicate_args(cmd: Command) -> Command {
+    cmd.arg(arg!(--minsize <size> "Minimum size").value_parser(value_parser!(u64)))
+        .arg(arg!(--maxsize <size> "Maximum size").value_parser(value_parser!(u64)))
+        .arg(arg!(--heap <heap_handle> "Heap handle").value_parser(parse_hex_address))
+        .arg(arg!(--thread <thread_id> "Allocating thread id").value_parser(value_parser!(u64)))
+        .arg(arg!(--minseq <sequence> "Minimum sequence number").value_parser(value_parser!(u64)))
+        .arg(arg!(--maxseq <sequence> "Maximum sequence number").value_parser(value_parser!(u64)))
+        .arg(
+            arg!(--since <nanoseconds> "Allocated no earlier than")
+                .value_parser(value_parser!(u64)),
+        )
+        .arg(arg!(--until <nanoseconds> "Allocated no later than").value_parser(value_parser!(u64)))
+        .arg(arg!(--symbol <pattern> "Any back trace symbol contains the pattern"))
+        .arg(arg!(--regex "Match the symbol pattern as a regular expression"))
+}
+
+fn query_args(cmd: Command) -> Command {
+    predicate_args(cmd)
+        .arg(arg!(--sort <key> "Sort key").value_parser([
+            "address",
+            "size",
+            "sequence",
+            "timestamp",
+        ]))
+        .arg(arg!(--desc "Sort in descending order"))
+        .arg(
+            arg!(--offset <count> "Number of allocations to skip")
+                .value_parser(value_parser!(u64))
+                .default_value("0"),
+        )
+        .arg(arg!(--limit <count> "Maximum number of allocations").value_parser(value_parser!(u64)))
+        .arg(
+            arg!(--pagesize <count> "Number of allocations scanned per request")
+                .value_parser(value_parser!(u32))
+                .default_value("4096"),
+        )
+}
+
+fn group_args(cmd: Command) -> Command {
+    cmd.arg(
+        arg!(--by <key> "Group by")
+            .value_parser(["backtrace", "stacktrace"])
+            .default_value("backtrace"),
+    )
+    .arg(
+        arg!(--depth <count> "Number of leading frames or words to group by, 0 for all")
+            .value_parser(value_parser!(u32))
+            .default_value("0"),
+    )
+    .arg(
+        arg!(--limit <count> "Number of groups")
+            .value_parser(value_parser!(u64))
+            .default_value("20"),
+    )
+}
+
 fn cli() -> Command {
     Command::new("allocation-catcher")
         .about("Allocation catcher")
@@ -250,6 +1372,13 @@ fn cli() -> Command {
         .arg_required_else_help(true)
         .arg(arg!(--host <host> "Host"))
         .arg(arg!(--port <port> "Host"))
+        .arg(arg!(--symbolize "Resolve the symbols locally, from copies of the target modules"))
+        .arg(
+            arg!(--symbols <dir> "Directory searched for the modules and their debug files")
+                .value_parser(value_parser!(PathBuf))
+                .action(ArgAction::Append)
+                .requires("symbolize"),
+        )
         .subcommand(Command::new("ping").about("Ping"))
         .subcommand(Command::new("getcfg").about("Get configuration"))
         .subcommand(
@@ -279,21 +1408,190 @@ fn cli() -> Command {
                     arg!(--btsymbols <backtrace_resolve_symbols_count> "Backtrace resolve symbols count")
                         .value_parser(value_parser!(u32))
                         .required(true),
+                )
+                .arg(
+                    arg!(--freedhist <freed_history_size> "Number of freed allocations to remember")
+                        .value_parser(value_parser!(u64))
+                        .default_value("0"),
+                )
+                .arg(
+                    arg!(--quarantine <quarantine_size> "Total size of the freed blocks to hold poisoned")
+                        .value_parser(value_parser!(u64))
+                        .default_value("0"),
+                )
+                .arg(
+                    arg!(--poison <poison_byte> "Byte the quarantined blocks are filled with")
+                        .value_parser(value_parser!(u32).range(..=0xFF)),
                 ),
         )
         .subcommand(Command::new("clear").about("Clear storage"))
-        .subcommand(Command::new("dump").about("Dump storage"))
+        .subcommand(query_args(
+            Command::new("dump")
+                .about("Dump storage")
+                .arg(arg!(--freed "Dump the freed allocations history")),
+        ))
         .subcommand(
             Command::new("find")
                 .about("Find allocation")
-                .arg(arg!(<address> "Address to find").value_parser(parse_hex_address)),
+                .arg(arg!(<address> "Address to find").value_parser(parse_hex_address))
+                .arg(arg!(--freed "Find what used to live at the address")),
         )
         .subcommand(
+            Command::new("whose")
+                .about("Find allocation containing the address")
+                .arg(arg!(<address> "Address inside of the allocation").value_parser(parse_hex_address))
+                .arg(arg!(--freed "Find freed allocations that contained the address")),
+        )
+        .subcommand(query_args(
             Command::new("findrange")
                 .about("Find allocations in range")
                 .arg(arg!(<lower> "Lower bound").value_parser(parse_hex_address))
-                .arg(arg!(<upper> "Upper bound").value_parser(parse_hex_address)),
+                .arg(arg!(<upper> "Upper bound").value_parser(parse_hex_address))
+                .arg(arg!(--freed "Find freed allocations in range")),
+        ))
+        .subcommand(predicate_args(group_args(
+            Command::new("top").about("Call sites owning the most live memory"),
+        )))
+        .subcommand(
+            Command::new("snapshot")
+                .about("Manage snapshots of the live allocations")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("take")
+                        .about("Take a snapshot")
+                        .arg(arg!(<name> "Snapshot name"))
+                        .arg(arg!(--save <file> "Also save the snapshot to the file")),
+                )
+                .subcommand(
+                    Command::new("save")
+                        .about("Save a snapshot to the file")
+                        .arg(arg!(<name> "Snapshot name"))
+                        .arg(arg!(<file> "File")),
+                )
+                .subcommand(
+                    Command::new("load")
+                        .about("Load a saved snapshot from the file")
+                        .arg(arg!(<name> "Snapshot name"))
+                        .arg(arg!(<file> "File")),
+                )
+                .subcommand(
+                    Command::new("delete")
+                        .about("Delete a snapshot")
+                        .arg(arg!(<name> "Snapshot name")),
+                ),
+        )
+        .subcommand(group_args(
+            Command::new("diff")
+                .about("Difference between two snapshots by call site")
+                .arg(arg!(<from> "Older snapshot name"))
+                .arg(arg!(<to> "Newer snapshot name")),
+        ))
+        .subcommand(
+            Command::new("watch")
+                .about("Print allocation events as they happen")
+                .arg(arg!(--minsize <size> "Minimum size").value_parser(value_parser!(u64)))
+                .arg(arg!(--maxsize <size> "Maximum size").value_parser(value_parser!(u64)))
+                .arg(arg!(--heap <heap_handle> "Heap handle").value_parser(parse_hex_address))
+                .arg(arg!(--lower <address> "Lowest address").value_parser(parse_hex_address))
+                .arg(arg!(--upper <address> "Highest address").value_parser(parse_hex_address))
+                .arg(arg!(--errors "Also print the invalid and double frees")),
+        )
+        .subcommand(
+            Command::new("faults")
+                .about("Fail the allocations matching any of the rules, none to stop")
+                .arg(arg!(--every <n> "Fail every nth allocation").value_parser(value_parser!(u64)))
+                .arg(
+                    arg!(--probability <p> "Fail the allocations with the probability")
+                        .value_parser(value_parser!(f64)),
+                )
+                .arg(
+                    arg!(--larger <size> "Fail the allocations larger than the size")
+                        .value_parser(value_parser!(u64)),
+                )
+                .arg(arg!(--symbol <pattern> "Fail the allocations with a back trace symbol containing the pattern"))
+                .arg(arg!(--regex "Match the symbol pattern as a regular expression"))
+                .arg(
+                    arg!(--budget <size> "Fail the allocations once the others total the size")
+                        .value_parser(value_parser!(u64)),
+                )
+                .arg(
+                    arg!(--seed <seed> "Seed of the probability, zero to pick one")
+                        .value_parser(value_parser!(u64))
+                        .default_value("0"),
+                ),
+        )
+        .subcommand(
+            Command::new("checkheap")
+                .about("Check the canaries of the allocations with redzones and the quarantined blocks")
+                .arg(
+                    arg!(--limit <count> "Maximum number of damaged allocations")
+                        .value_parser(value_parser!(u64))
+                        .default_value("100"),
+                ),
+        )
+        .subcommand(
+            Command::new("errors")
+                .about("Print the invalid and double frees")
+                .arg(
+                    arg!(--cursor <cursor> "Number of errors to skip")
+                        .value_parser(value_parser!(u64))
+                        .default_value("0"),
+                )
+                .arg(
+                    arg!(--limit <count> "Maximum number of errors")
+                        .value_parser(value_parser!(u64))
+                        .default_value("100"),
+                ),
+        )
+        .subcommand(
+            Command::new("record")
+                .about("Record every event to a trace file on the target machine")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("start")
+                        .about("Start recording")
+                        .arg(arg!(<path> "Trace file path")),
+                )
+                .subcommand(Command::new("stop").about("Stop recording")),
+        )
+        .subcommand(
+            Command::new("replay")
+                .about("Reconstruct the heap from a trace file")
+                .arg(arg!(<file> "Trace file"))
+                .arg(arg!(--at <seconds> "Stop at the time").value_parser(value_parser!(f64)))
+                .arg(
+                    arg!(--limit <count> "Number of call sites")
+                        .value_parser(value_parser!(usize))
+                        .default_value("20"),
+                ),
+        )
+        .subcommand(
+            Command::new("hexdump")
+                .about("Dump the memory of an allocation")
+                .arg(arg!(<address> "Address").value_parser(parse_hex_address))
+                .arg(
+                    arg!(--size <size> "Number of bytes, the whole allocation by default")
+                        .value_parser(value_parser!(u64)),
+                )
+                .arg(arg!(--words "Show pointer-sized words instead of bytes")),
+        )
+        .subcommand(
+            Command::new("retainers")
+                .about("Find the words pointing inside an allocation")
+                .arg(arg!(<address> "Address").value_parser(parse_hex_address))
+                .arg(arg!(--stacks "Also scan the stacks of the threads"))
+                .arg(arg!(--modules "Also scan the writable data of the modules"))
+                .arg(
+                    arg!(--limit <count> "Maximum number of retainers")
+                        .value_parser(value_parser!(u32))
+                        .default_value("100"),
+                ),
         )
+        .subcommand(group_args(
+            Command::new("leaks")
+                .about("Allocations no longer reachable from the stacks and the module data"),
+        ))
+        .subcommand(Command::new("modules").about("List the loaded modules"))
         .subcommand(Command::new("getstat").about("Get statistics"))
         .subcommand(Command::new("resetstat").about("Reset statistics"))
 }
@@ -0,0 +1,239 @@
+use std::collections::HashMap;
+
+use bytes::Buf;
+use prost::Message;
+
+use crate::client::proto;
+
+#[derive(Debug, Default, Clone)]
+pub struct CallSite {
+    pub live_count: u64,
+    pub live_size: u64,
+    pub total_count: u64,
+    pub total_size: u64,
+}
+
+struct LiveAllocation {
+    size: u64,
+    stack_id: u32,
+}
+
+/// State of the heap reconstructed from a trace file.
+#[derive(Default)]
+pub struct Replay {
+    pub stacks: HashMap<u32, Vec<proto::BackTraceFrame>>,
+    pub call_sites: HashMap<u32, CallSite>,
+    pub events: u64,
+    pub dropped: u64,
+    // The trace ends with an incomplete record, e.g. after a crash.
+    pub truncated: bool,
+    pub timestamp: u64,
+    pub live_count: u64,
+    pub live_size: u64,
+    pub peak_size: u64,
+    pub peak_timestamp: u64,
+    live: HashMap<u64, LiveAllocation>,
+}
+
+impl Replay {
+    fn allocate(&mut self, address: u64, size: u64, stack_id: u32) {
+        self.free(address);
+        self.live.insert(address, LiveAllocation { size, stack_id });
+        self.live_size += size;
+    }
+
+    fn free(&mut self, address: u64) -> Option<LiveAllocation> {
+        let allocation = self.live.remove(&address)?;
+        self.live_size -= allocation.size;
+        Some(allocation)
+    }
+
+    fn apply(&mut self, event: &proto::Event, stack_id: u32) {
+        match proto::EventKind::try_from(event.kind) {
+            Ok(proto::EventKind::Allocation) => {
+                self.allocate(event.base_address, event.size, stack_id);
+                self.count_allocation(event.size, stack_id);
+            }
+            Ok(proto::EventKind::Reallocation) => {
+                let previous = event.previous_address.and_then(|x| self.free(x));
+                // Attribute the block to where it has been allocated if the new site is unknown.
+                let stack_id = match (stack_id, previous) {
+                    (0, Some(previous)) => previous.stack_id,
+                    _ => stack_id,
+                };
+                self.allocate(event.base_address, event.size, stack_id);
+                self.count_allocation(event.size, stack_id);
+            }
+            Ok(proto::EventKind::Deallocation) => {
+                self.free(event.base_address);
+            }
+            Err(_) => {}
+        }
+
+        self.events += 1;
+        self.timestamp = event.timestamp;
+
+        if self.live_size > self.peak_size {
+            self.peak_size = self.live_size;
+            self.peak_timestamp = event.timestamp;
+        }
+    }
+
+    fn count_allocation(&mut self, size: u64, stack_id: u32) {
+        let call_site = self.call_sites.entry(stack_id).or_default();
+        call_site.total_count += 1;
+        call_site.total_size += size;
+    }
+
+    fn finish(&mut self) {
+        self.live_count = self.live.len() as u64;
+
+        for allocation in self.live.values() {
+            let call_site = self.call_sites.entry(allocation.stack_id).or_default();
+            call_site.live_count += 1;
+            call_site.live_size += allocation.size;
+        }
+    }
+}
+
+/// Replays the events of the trace up to the timestamp, or all of them.
+pub fn replay(mut data: &[u8], until: Option<u64>) -> anyhow::Result<Replay> {
+    let mut replay = Replay::default();
+
+    while data.has_remaining() {
+        let Ok(record) = proto::TraceRecord::decode_length_delimited(&mut data) else {
+            replay.truncated = true;
+            break;
+        };
+
+        match record.record {
+            Some(proto::trace_record::Record::Header(header)) => {
+                if header.version != 1 {
+                    return Err(anyhow::anyhow!(
+                        "unsupported trace version {}",
+                        header.version
+                    ));
+                }
+            }
+            Some(proto::trace_record::Record::Stack(stack)) => {
+                replay.stacks.insert(stack.id, stack.frames);
+            }
+            Some(proto::trace_record::Record::Event(event)) => {
+                let Some(inner) = event.event.as_ref() else {
+                    continue;
+                };
+                if until.is_some_and(|x| inner.timestamp > x) {
+                    break;
+                }
+                replay.apply(inner, event.stack_id);
+            }
+            Some(proto::trace_record::Record::Dropped(dropped)) => replay.dropped += dropped,
+            None => {}
+        }
+    }
+
+    replay.finish();
+    Ok(replay)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(record: proto::trace_record::Record) -> Vec<u8> {
+        proto::TraceRecord {
+            record: Some(record),
+        }
+        .encode_length_delimited_to_vec()
+    }
+
+    fn event(
+        kind: proto::EventKind,
+        base_address: u64,
+        previous_address: Option<u64>,
+        size: u64,
+        timestamp: u64,
+        stack_id: u32,
+    ) -> Vec<u8> {
+        record(proto::trace_record::Record::Event(proto::TraceEvent {
+            event: Some(proto::Event {
+                kind: kind as i32,
+                base_address,
+                previous_address,
+                size,
+                timestamp,
+                ..Default::default()
+            }),
+            stack_id,
+        }))
+    }
+
+    fn trace() -> Vec<u8> {
+        use proto::{trace_record::Record, EventKind};
+
+        [
+            record(Record::Header(proto::TraceHeader {
+                version: 1,
+                wordsize: 8,
+            })),
+            record(Record::Stack(proto::TraceStack {
+                id: 1,
+                frames: Vec::new(),
+            })),
+            event(EventKind::Allocation, 0x1000, None, 0x10, 1, 1),
+            event(EventKind::Allocation, 0x2000, None, 0x20, 2, 0),
+            record(Record::Dropped(2)),
+            event(EventKind::Reallocation, 0x3000, Some(0x1000), 0x30, 3, 0),
+            event(EventKind::Deallocation, 0x2000, None, 0, 4, 0),
+        ]
+        .concat()
+    }
+
+    fn call_site(replay: &Replay, stack_id: u32) -> (u64, u64, u64, u64) {
+        let x = &replay.call_sites[&stack_id];
+        (x.live_count, x.live_size, x.total_count, x.total_size)
+    }
+
+    #[test]
+    fn replay_whole_trace() {
+        let replay = replay(&trace(), None).unwrap();
+
+        assert_eq!((replay.events, replay.dropped), (4, 2));
+        assert_eq!((replay.live_count, replay.live_size), (1, 0x30));
+        assert_eq!((replay.peak_size, replay.peak_timestamp), (0x50, 3));
+        assert!(replay.stacks.contains_key(&1));
+        assert!(!replay.truncated);
+
+        // The reallocated block stays attributed to the site of its allocation.
+        assert_eq!(call_site(&replay, 1), (1, 0x30, 2, 0x40));
+        assert_eq!(call_site(&replay, 0), (0, 0, 1, 0x20));
+    }
+
+    #[test]
+    fn replay_until_timestamp() {
+        let replay = replay(&trace(), Some(2)).unwrap();
+
+        assert_eq!(replay.events, 2);
+        assert_eq!((replay.live_count, replay.live_size), (2, 0x30));
+        assert_eq!(replay.timestamp, 2);
+    }
+
+    #[test]
+    fn replay_truncated_trace() {
+        let trace = trace();
+        let replay = replay(&trace[..trace.len() - 1], None).unwrap();
+
+        assert!(replay.truncated);
+        assert_eq!(replay.events, 3);
+    }
+
+    #[test]
+    fn unsupported_version() {
+        let header = record(proto::trace_record::Record::Header(proto::TraceHeader {
+            version: 2,
+            wordsize: 8,
+        }));
+
+        assert!(replay(&header, None).is_err());
+    }
+}
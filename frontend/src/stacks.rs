@@ -0,0 +1,39 @@
+use std::collections::HashMap;
+
+use crate::client::proto;
+
+/// Stack table of a response, used to put the back traces back into the allocations.
+pub struct Stacks {
+    back_traces: HashMap<u64, proto::BackTrace>,
+}
+
+impl Stacks {
+    pub fn new(stacks: Vec<proto::Stack>) -> Self {
+        Self {
+            back_traces: stacks
+                .into_iter()
+                .filter_map(|x| Some((x.id, x.back_trace?)))
+                .collect(),
+        }
+    }
+
+    fn get(&self, stack_id: u64) -> Option<proto::BackTrace> {
+        self.back_traces.get(&stack_id).cloned()
+    }
+
+    pub fn expand(&self, allocation: &mut proto::Allocation) {
+        if allocation.back_trace.is_none() {
+            allocation.back_trace = self.get(allocation.stack_id);
+        }
+    }
+
+    pub fn expand_freed(&self, freed_allocation: &mut proto::FreedAllocation) {
+        if let Some(allocation) = freed_allocation.allocation.as_mut() {
+            self.expand(allocation);
+        }
+
+        if freed_allocation.free_back_trace.is_none() {
+            freed_allocation.free_back_trace = self.get(freed_allocation.free_stack_id);
+        }
+    }
+}
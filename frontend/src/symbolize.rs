@@ -0,0 +1,337 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use gimli::{EndianRcSlice, RunTimeEndian};
+use object::{BinaryFormat, Object, ObjectSection, ObjectSegment, ObjectSymbol, SymbolKind};
+
+use crate::client::proto;
+
+type Context = addr2line::Context<EndianRcSlice<RunTimeEndian>>;
+
+// Debug information of a local copy of a module.
+struct ModuleFile {
+    context: Option<Context>,
+    // Function symbols sorted by address, used when there is no DWARF.
+    symbols: Vec<(u64, u64, String)>,
+    // Address the module base is loaded at according to the file.
+    first_address: u64,
+}
+
+impl ModuleFile {
+    fn load(path: &Path, build_id: &[u8]) -> Option<Self> {
+        let data = std::fs::read(path).ok()?;
+        let file = object::File::parse(&*data).ok()?;
+
+        // A file of another build would resolve to garbage.
+        if !build_id.is_empty() && file_build_id(&file).is_some_and(|x| x != build_id) {
+            return None;
+        }
+
+        let endian = if file.is_little_endian() {
+            RunTimeEndian::Little
+        } else {
+            RunTimeEndian::Big
+        };
+        let load_section = |id: gimli::SectionId| -> Result<_, gimli::Error> {
+            let data = file
+                .section_by_name(id.name())
+                .and_then(|x| x.uncompressed_data().ok())
+                .unwrap_or_default();
+            Ok(EndianRcSlice::new(Rc::from(&*data), endian))
+        };
+        let context = gimli::Dwarf::load(load_section)
+            .ok()
+            .and_then(|x| Context::from_dwarf(x).ok());
+
+        let mut symbols: Vec<_> = file
+            .symbols()
+            .chain(file.dynamic_symbols())
+            .filter(|x| x.kind() == SymbolKind::Text && x.address() != 0)
+            .filter_map(|x| Some((x.address(), x.size(), x.name().ok()?.to_owned())))
+            .collect();
+        symbols.sort_by_key(|x| x.0);
+
+        let first_address = match file.format() {
+            BinaryFormat::Pe => file.relative_address_base(),
+            _ => file.segments().map(|x| x.address()).min().unwrap_or(0),
+        };
+
+        Some(Self {
+            context,
+            symbols,
+            first_address,
+        })
+    }
+
+    fn find_symbol(&self, address: u64) -> Option<&(u64, u64, String)> {
+        let index = self.symbols.partition_point(|x| x.0 <= address);
+        self.symbols[..index]
+            .last()
+            .filter(|x| x.1 == 0 || address < x.0 + x.1)
+    }
+
+    // `address` is relative to the first address of the file.
+    fn resolve(&self, address: u64) -> Vec<proto::BackTraceSymbol> {
+        let mut resolved = Vec::new();
+
+        if let Some(mut frames) = self
+            .context
+            .as_ref()
+            .and_then(|x| x.find_frames(address).skip_all_loads().ok())
+        {
+            while let Ok(Some(frame)) = frames.next() {
+                let name = frame
+                    .function
+                    .as_ref()
+                    .and_then(|x| x.demangle().ok())
+                    .map(|x| x.into_owned());
+                let location = frame.location.as_ref();
+
+                resolved.push(proto::BackTraceSymbol {
+                    name,
+                    address: None,
+                    filename: location.and_then(|x| x.file).map(|x| x.to_owned()),
+                    lineno: location.and_then(|x| x.line),
+                    colno: location.and_then(|x| x.column),
+                });
+            }
+        }
+
+        // Stripped of DWARF, the symbol table still names the function.
+        if resolved.iter().all(|x| x.name.is_none()) {
+            if let Some((symbol_address, _, name)) = self.find_symbol(address) {
+                let symbol = proto::BackTraceSymbol {
+                    name: Some(addr2line::demangle_auto(name.into(), None).into_owned()),
+                    address: Some(*symbol_address),
+                    ..Default::default()
+                };
+
+                match resolved.first_mut() {
+                    Some(first) => first.name = symbol.name,
+                    None => resolved.push(symbol),
+                }
+            }
+        }
+
+        resolved
+    }
+}
+
+fn file_build_id(file: &object::File) -> Option<Vec<u8>> {
+    if let Ok(Some(build_id)) = file.build_id() {
+        return Some(build_id.to_vec());
+    }
+
+    // Same layout as the signature reported by the target, GUID then age.
+    let pdb = file.pdb_info().ok()??;
+    Some([&pdb.guid()[..], &pdb.age().to_le_bytes()].concat())
+}
+
+/// Resolves the frames of the target from local copies of its modules,
+/// for targets running without symbol resolution or debug information.
+pub struct Symbolizer {
+    search_path: Vec<PathBuf>,
+    modules: Option<Vec<proto::Module>>,
+    files: HashMap<u64, Option<ModuleFile>>,
+}
+
+impl Symbolizer {
+    pub fn new(search_path: Vec<PathBuf>) -> Self {
+        Self {
+            search_path,
+            modules: None,
+            files: HashMap::new(),
+        }
+    }
+
+    pub fn has_modules(&self) -> bool {
+        self.modules.is_some()
+    }
+
+    pub fn set_modules(&mut self, mut modules: Vec<proto::Module>) {
+        modules.sort_by_key(|x| x.base);
+        self.modules = Some(modules);
+        self.files.clear();
+    }
+
+    // Separate debug files by build id first, then files named as the module,
+    // then the path of the module on the target.
+    fn candidates(&self, module: &proto::Module) -> Vec<PathBuf> {
+        let mut candidates = Vec::new();
+
+        if module.build_id.len() > 1 {
+            let hex: String = module.build_id.iter().map(|x| format!("{x:02x}")).collect();
+            let debug_file = format!(".build-id/{}/{}.debug", &hex[..2], &hex[2..]);
+            candidates.extend(self.search_path.iter().map(|x| x.join(&debug_file)));
+        }
+
+        candidates.extend(self.search_path.iter().map(|x| x.join(&module.name)));
+        if let Some(debug_file) = module.debug_file.as_ref() {
+            let name = debug_file.rsplit(['/', '\\']).next().unwrap_or(debug_file);
+            candidates.extend(self.search_path.iter().map(|x| x.join(name)));
+        }
+        candidates.push(PathBuf::from(&module.path));
+
+        candidates
+    }
+
+    fn find_module(&self, address: u64) -> Option<&proto::Module> {
+        let modules = self.modules.as_deref()?;
+        let index = modules.partition_point(|x| x.base <= address);
+        modules[..index]
+            .last()
+            .filter(|x| address < x.base + x.size)
+    }
+
+    fn resolve(&mut self, address: u64) -> Vec<proto::BackTraceSymbol> {
+        let Some(module) = self.find_module(address).cloned() else {
+            return Vec::new();
+        };
+
+        if !self.files.contains_key(&module.base) {
+            let file = self
+                .candidates(&module)
+                .iter()
+                .find_map(|x| ModuleFile::load(x, &module.build_id));
+            self.files.insert(module.base, file);
+        }
+
+        let Some(file) = self.files[&module.base].as_ref() else {
+            return Vec::new();
+        };
+
+        // Return addresses point past the call, the instruction before belongs to the caller.
+        let offset = (address - module.base).saturating_sub(1);
+        let mut resolved = file.resolve(offset + file.first_address);
+        for symbol in resolved.iter_mut() {
+            symbol.address = symbol.address.map(|x| x - file.first_address + module.base);
+        }
+
+        resolved
+    }
+
+    // Only the frames the target has not resolved itself.
+    pub fn symbolize(&mut self, back_trace: &mut proto::BackTrace) {
+        for frame in back_trace.frames.iter_mut() {
+            if frame.resolved_symbols.is_empty() {
+                frame.resolved_symbols = self.resolve(frame.instruction_pointer);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn module(build_id: Vec<u8>) -> proto::Module {
+        proto::Module {
+            name: "app".to_owned(),
+            path: "/opt/app/bin/app".to_owned(),
+            base: 0x1000,
+            size: 0x1000,
+            build_id,
+            debug_file: Some(r"C:\build\app.pdb".to_owned()),
+        }
+    }
+
+    #[test]
+    fn candidates_in_search_order() {
+        let symbolizer = Symbolizer::new(vec![PathBuf::from("/symbols")]);
+        let candidates = symbolizer.candidates(&module(vec![0xAB, 0xCD, 0xEF]));
+
+        assert_eq!(
+            candidates,
+            [
+                PathBuf::from("/symbols/.build-id/ab/cdef.debug"),
+                PathBuf::from("/symbols/app"),
+                PathBuf::from("/symbols/app.pdb"),
+                PathBuf::from("/opt/app/bin/app"),
+            ]
+        );
+    }
+
+    #[test]
+    fn frames_outside_of_the_modules_stay_unresolved() {
+        let mut symbolizer = Symbolizer::new(Vec::new());
+        symbolizer.set_modules(vec![module(Vec::new())]);
+
+        assert!(symbolizer.resolve(0x500).is_empty());
+        assert!(symbolizer.resolve(0x2000).is_empty());
+    }
+
+    #[inline(never)]
+    fn symbolize_target() -> u64 {
+        std::hint::black_box(0x1234)
+    }
+
+    // The executable as the target would report it, from the mappings of its file.
+    #[cfg(target_os = "linux")]
+    fn executable(build_id: Vec<u8>) -> proto::Module {
+        let path = std::env::current_exe().unwrap();
+        let maps = std::fs::read_to_string("/proc/self/maps").unwrap();
+        let ranges: Vec<(u64, u64)> = maps
+            .lines()
+            .filter(|x| x.ends_with(&*path.to_string_lossy()))
+            .filter_map(|x| {
+                let (start, end) = x.split_whitespace().next()?.split_once('-')?;
+                Some((
+                    u64::from_str_radix(start, 16).ok()?,
+                    u64::from_str_radix(end, 16).ok()?,
+                ))
+            })
+            .collect();
+        let base = ranges.iter().map(|x| x.0).min().unwrap();
+        let end = ranges.iter().map(|x| x.1).max().unwrap();
+
+        proto::Module {
+            name: path.file_name().unwrap().to_string_lossy().into_owned(),
+            path: path.to_string_lossy().into_owned(),
+            base,
+            size: end - base,
+            build_id,
+            debug_file: None,
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn symbolize_frame(module: proto::Module, instruction_pointer: u64) -> Vec<String> {
+        let mut symbolizer = Symbolizer::new(Vec::new());
+        symbolizer.set_modules(vec![module]);
+
+        let mut back_trace = proto::BackTrace {
+            frames: vec![proto::BackTraceFrame {
+                instruction_pointer,
+                ..Default::default()
+            }],
+        };
+        symbolizer.symbolize(&mut back_trace);
+
+        back_trace.frames[0]
+            .resolved_symbols
+            .iter()
+            .filter_map(|x| x.name.clone())
+            .collect()
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn symbolize_frames_of_the_executable() {
+        assert_eq!(symbolize_target(), 0x1234);
+        // Return addresses point past the call.
+        let instruction_pointer = symbolize_target as *const () as u64 + 1;
+
+        let names = symbolize_frame(executable(Vec::new()), instruction_pointer);
+        assert!(names.iter().any(|x| x.contains("symbolize_target")));
+
+        // A file of another build is not used.
+        let data = std::fs::read(std::env::current_exe().unwrap()).unwrap();
+        if file_build_id(&object::File::parse(&*data).unwrap()).is_some() {
+            let names = symbolize_frame(executable(vec![0; 20]), instruction_pointer);
+            assert!(names.is_empty());
+        }
+    }
+}
@@ -6,9 +6,13 @@ use std::{
 use bytes::{Bytes, BytesMut};
 
 pub fn stream_request<S: Read + Write>(stream: &mut S, packet: Bytes) -> io::Result<Bytes> {
-    stream.write(&(packet.len() as u32).to_be_bytes())?;
-    stream.write(packet.as_ref())?;
+    stream.write_all(&(packet.len() as u32).to_be_bytes())?;
+    stream.write_all(packet.as_ref())?;
 
+    stream_receive(stream)
+}
+
+pub fn stream_receive<S: Read>(stream: &mut S) -> io::Result<Bytes> {
     let mut buf = [0u8; 4];
     stream.read_exact(&mut buf)?;
 
@@ -25,10 +29,17 @@ pub fn connect_tcp(addrs: impl ToSocketAddrs) -> io::Result<TcpStream> {
 
 pub trait Transport {
     fn request(&mut self, packet: Bytes) -> io::Result<Bytes>;
+
+    // Next packet pushed by the server after a streaming request.
+    fn receive(&mut self) -> io::Result<Bytes>;
 }
 
 impl<S: Read + Write> Transport for S {
     fn request(&mut self, packet: Bytes) -> io::Result<Bytes> {
         stream_request(self, packet)
     }
+
+    fn receive(&mut self) -> io::Result<Bytes> {
+        stream_receive(self)
+    }
 }